@@ -0,0 +1,282 @@
+//! Reassembly of VP8 frames split across RTP packets,
+//! https://datatracker.ietf.org/doc/html/rfc7741#section-4.5
+
+use bytes::Bytes;
+use rtp::{codecs::vp8::Vp8Packet, header::Header, packet::Packet, packetizer::Depacketizer};
+
+use crate::{Error, PayloadDescriptor};
+
+/// A VP8 frame assembled from the RTP packets sharing its timestamp
+#[derive(Debug, Clone)]
+pub struct AssembledFrame {
+    /// Header of the packet starting the frame, or of the earliest packet
+    /// received when that one was lost
+    pub rtp_header: Header,
+    /// Headers of the packets received, in sequence number order
+    pub packet_headers: Vec<Header>,
+    /// Payload descriptor of the packet starting the frame, `None` when it
+    /// was lost
+    pub descriptor: Option<PayloadDescriptor>,
+    /// Depacketized payloads in sequence number order. Lost packets leave
+    /// gaps, so only complete frames are guaranteed to hold a valid VP8 frame.
+    pub data: Vec<u8>,
+    pub packet_count: usize,
+    /// Packets lost within the frame. When its first packet was lost the
+    /// frame starts after the previous one, and when its marker was lost it
+    /// ends before the next one.
+    pub missing_packets: usize,
+    /// Packets lost between the previous frame and this one's first packet,
+    /// which belonged to frames that were lost entirely
+    pub lost_before: usize,
+    /// Whether every packet from the one starting the frame up to the one
+    /// carrying the marker bit arrived
+    pub complete: bool,
+}
+
+impl AssembledFrame {
+    /// Total depacketized payload bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[derive(Debug)]
+struct FramePacket {
+    header: Header,
+    starts_frame: bool,
+    descriptor: PayloadDescriptor,
+    payload: Bytes,
+}
+
+/// Collects packets by RTP timestamp until the marker bit. Frames must
+/// arrive in order, a packet with a new timestamp ends the frame being
+/// assembled, which is then emitted incomplete.
+#[derive(Debug, Default)]
+pub struct Vp8FrameAssembler {
+    packets: Vec<FramePacket>,
+    /// Timestamp of the last emitted frame, whose late packets are dropped
+    last_timestamp: Option<u32>,
+    /// Sequence number the last emitted frame ended at
+    last_end: Option<u16>,
+}
+
+impl Vp8FrameAssembler {
+    /// Adds a packet, returning the frames it completes or ends
+    pub fn push(&mut self, packet: Packet) -> Result<Vec<AssembledFrame>, Error> {
+        if packet.payload.is_empty() {
+            return Ok(self.push_padding(packet.header.sequence_number));
+        }
+        let mut vp8_pkt = Vp8Packet::default();
+        let payload = vp8_pkt
+            .depacketize(&packet.payload)
+            .map_err(Error::Depacketize)?;
+        let descriptor = PayloadDescriptor::new(&vp8_pkt, &packet.payload);
+        let header = packet.header;
+
+        let mut frames = vec![];
+        if self.last_timestamp == Some(header.timestamp) {
+            return Ok(frames);
+        }
+        if let Some(pending) = self.packets.first() {
+            if pending.header.timestamp != header.timestamp {
+                frames.extend(self.flush_before(Some(header.sequence_number)));
+            }
+        }
+        let duplicate = self
+            .packets
+            .iter()
+            .any(|p| p.header.sequence_number == header.sequence_number);
+        if duplicate {
+            return Ok(frames);
+        }
+
+        let marker = header.marker;
+        self.packets.push(FramePacket {
+            header,
+            starts_frame: vp8_pkt.s == 1 && vp8_pkt.pid == 0,
+            descriptor,
+            payload,
+        });
+        if marker {
+            frames.extend(self.flush());
+        }
+        Ok(frames)
+    }
+
+    /// Counts a padding-only packet, e.g. sent for bandwidth probing, as
+    /// received so its sequence number isn't taken for a lost frame. Padding
+    /// follows whole frames, so it ends the frame being assembled.
+    fn push_padding(&mut self, sequence_number: u16) -> Vec<AssembledFrame> {
+        let frames = if self.packets.is_empty() {
+            vec![]
+        } else {
+            self.flush_before(Some(sequence_number))
+                .into_iter()
+                .collect()
+        };
+        if self
+            .last_end
+            .is_none_or(|end| sequence_number.wrapping_sub(end) as i16 > 0)
+        {
+            self.last_end = Some(sequence_number);
+        }
+        frames
+    }
+
+    /// Emits the frame being assembled, e.g. once the stream ends
+    pub fn flush(&mut self) -> Option<AssembledFrame> {
+        self.flush_before(None)
+    }
+
+    /// Emits the frame being assembled, ending before `next` when its marker
+    /// was lost
+    fn flush_before(&mut self, next: Option<u16>) -> Option<AssembledFrame> {
+        let mut packets = std::mem::take(&mut self.packets);
+        //  order relative to the first packet received so sequence numbers
+        //  wrapping within the frame still sort correctly
+        let base = packets.first()?.header.sequence_number;
+        packets.sort_by_key(|p| p.header.sequence_number.wrapping_sub(base) as i16);
+
+        let (first, last) = (&packets[0], &packets[packets.len() - 1]);
+        let starts_frame = first.starts_frame;
+        let (first_seq, last_seq) = (first.header.sequence_number, last.header.sequence_number);
+        //  packets lost between two sequence numbers, none if they arrived
+        //  out of order
+        let between = |from: u16, to: u16| (to.wrapping_sub(from).wrapping_sub(1) as i16).max(0);
+
+        let gap = self.last_end.map_or(0, |end| between(end, first_seq)) as usize;
+        let (missing_before, lost_before) = if starts_frame { (0, gap) } else { (gap, 0) };
+        let missing_after = match next {
+            Some(next) if !last.header.marker => between(last_seq, next),
+            _ => 0,
+        };
+        let received_span = last_seq.wrapping_sub(first_seq) as usize + 1;
+        let missing_packets =
+            missing_before + received_span - packets.len() + missing_after as usize;
+        let end = last_seq.wrapping_add(missing_after as u16);
+        let complete = starts_frame && last.header.marker && missing_packets == 0;
+
+        let mut data = Vec::with_capacity(packets.iter().map(|p| p.payload.len()).sum());
+        for packet in &packets {
+            data.extend_from_slice(&packet.payload);
+        }
+
+        self.last_timestamp = Some(first.header.timestamp);
+        self.last_end = Some(end);
+        Some(AssembledFrame {
+            rtp_header: first.header.clone(),
+            packet_headers: packets.iter().map(|p| p.header.clone()).collect(),
+            descriptor: starts_frame.then_some(first.descriptor),
+            data,
+            packet_count: packets.len(),
+            missing_packets,
+            lost_before,
+            complete,
+        })
+    }
+}
+
+#[cfg(test)]
+use crate::test_packet;
+
+#[test]
+fn assemble_out_of_order_across_wraparound() {
+    let mut assembler = Vp8FrameAssembler::default();
+    //  payload descriptors are a single byte, 0x10 sets S
+    let packets = [
+        test_packet(0, 65535, 90, false, &[0x10, 1, 2, 3]),
+        test_packet(0, 1, 90, false, &[0x00, 7, 8, 9]),
+        test_packet(0, 0, 90, false, &[0x00, 4, 5, 6]),
+        test_packet(0, 2, 90, true, &[0x00, 10, 11, 12]),
+    ];
+
+    let mut frames = vec![];
+    for packet in packets {
+        frames.extend(assembler.push(packet).unwrap());
+    }
+    assert_eq!(frames.len(), 1);
+
+    let frame = &frames[0];
+    assert_eq!(frame.data, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    assert_eq!(frame.packet_count, 4);
+    assert_eq!(frame.rtp_header.sequence_number, 65535);
+    assert!(frame.complete);
+}
+
+#[test]
+fn assemble_frame_with_lost_packet() {
+    let mut assembler = Vp8FrameAssembler::default();
+    assert!(assembler
+        .push(test_packet(0, 10, 90, false, &[0x10, 1, 2, 3]))
+        .unwrap()
+        .is_empty());
+    let frames = assembler
+        .push(test_packet(0, 12, 90, true, &[0x00, 7, 8, 9]))
+        .unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].size(), 6);
+    assert_eq!(frames[0].missing_packets, 1);
+    assert!(!frames[0].complete);
+
+    //  a late packet of the emitted frame is dropped, then a new timestamp
+    //  ends the next frame, whose start and marker were lost
+    assert!(assembler
+        .push(test_packet(0, 11, 90, false, &[0x00, 4, 5, 6]))
+        .unwrap()
+        .is_empty());
+    assert!(assembler
+        .push(test_packet(0, 14, 180, false, &[0x00, 1, 2, 3]))
+        .unwrap()
+        .is_empty());
+    let frames = assembler
+        .push(test_packet(0, 15, 270, true, &[0x10, 1, 2, 3]))
+        .unwrap();
+    assert_eq!(frames.len(), 2);
+    assert!(frames[0].descriptor.is_none());
+    assert_eq!(frames[0].missing_packets, 1);
+    assert!(!frames[0].complete);
+    assert!(frames[1].complete);
+
+    //  packets between complete frames belonged to a lost frame
+    let frames = assembler
+        .push(test_packet(0, 18, 360, true, &[0x10, 1, 2, 3]))
+        .unwrap();
+    assert_eq!(frames[0].missing_packets, 0);
+    assert_eq!(frames[0].lost_before, 2);
+    assert!(frames[0].complete);
+}
+
+#[test]
+fn assemble_around_padding() {
+    let mut assembler = Vp8FrameAssembler::default();
+    let frames = assembler
+        .push(test_packet(0, 10, 90, true, &[0x10, 1, 2, 3]))
+        .unwrap();
+    assert_eq!(frames.len(), 1);
+
+    //  padding takes up sequence numbers between frames
+    assert!(assembler
+        .push(test_packet(0, 11, 90, false, &[]))
+        .unwrap()
+        .is_empty());
+    let frames = assembler
+        .push(test_packet(0, 12, 180, true, &[0x10, 4, 5, 6]))
+        .unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].lost_before, 0);
+    assert!(frames[0].complete);
+
+    //  and ends a frame whose marker was lost
+    assert!(assembler
+        .push(test_packet(0, 13, 270, false, &[0x10, 7, 8, 9]))
+        .unwrap()
+        .is_empty());
+    let frames = assembler.push(test_packet(0, 15, 270, false, &[])).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].missing_packets, 1);
+    assert!(!frames[0].complete);
+    let frames = assembler
+        .push(test_packet(0, 16, 360, true, &[0x10, 1, 2, 3]))
+        .unwrap();
+    assert_eq!(frames[0].lost_before, 0);
+}
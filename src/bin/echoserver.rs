@@ -11,6 +11,34 @@ use mediasoup::worker::{WorkerLogLevel, WorkerLogTag};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::num::{NonZeroU32, NonZeroU8};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+
+/// Opens the rtpdump file named by `FRAMETRACE_RTPDUMP` to record the tracer's
+/// packets to, if set. Recordings grow for as long as the call lasts.
+fn rtpdump_recording() -> Option<Box<dyn std::io::Write + Send>> {
+    let path = std::env::var_os("FRAMETRACE_RTPDUMP")?;
+    match std::fs::File::create(&path) {
+        Ok(file) => Some(Box::new(std::io::BufWriter::new(file))),
+        Err(error) => {
+            eprintln!("Failed to create rtpdump file {:?}: {}", path, error);
+            None
+        }
+    }
+}
+
+/// Passes a packet to the logger without blocking the worker, counting the
+/// ones dropped while the logger lags behind
+fn send_to_logger(logger: &SyncSender<Vec<u8>>, dropped: &AtomicUsize, pkt: &[u8]) {
+    if logger.try_send(pkt.to_vec()).is_err() {
+        //  neither logged nor recorded, so warn at every power of two
+        let dropped = dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        if dropped.is_power_of_two() {
+            eprintln!("Logger fell behind, {} packets dropped so far", dropped);
+        }
+    }
+}
 
 /// List of codecs that SFU will accept from clients
 fn media_codecs() -> Vec<RtpCodecCapability> {
@@ -353,12 +381,13 @@ impl Handler<ClientMessage> for EchoConnection {
                             println!("{:?} producer created: {}", kind, id);
 
                             let mut vp8_only_caps = rtp_caps.clone();
-                            //  only logging vp8 is currently supported
+                            //  only logging vp8 is currently supported, along with
+                            //  its retransmissions
                             vp8_only_caps.codecs.retain(|codec| {
                                 matches!(
                                     codec,
                                     RtpCodecCapability::Video {
-                                        mime_type: MimeTypeVideo::Vp8,
+                                        mime_type: MimeTypeVideo::Vp8 | MimeTypeVideo::Rtx,
                                         ..
                                     }
                                 )
@@ -369,16 +398,69 @@ impl Handler<ClientMessage> for EchoConnection {
                                 .await
                             {
                                 println!("tracer consumer created: {:?}", tracer_consumer.id());
-                                let logger = frametrace::spawn_rtp_logger(
-                                    std::fs::File::create("video_log.json").unwrap(),
-                                );
+                                //  decode the extensions negotiated for the tracer
+                                let mut options = frametrace::LoggerOptions::default();
+                                for extension in &tracer_consumer.rtp_parameters().header_extensions
+                                {
+                                    options
+                                        .header_extensions
+                                        .register(extension.id as u8, extension.uri.as_str());
+                                }
+                                //  and merge rtx into the stream it retransmits. The
+                                //  router only retransmits to consumers that NACK,
+                                //  which the tracer never does, so retransmissions are
+                                //  only seen when logging a capture.
+                                for codec in &tracer_consumer.rtp_parameters().codecs {
+                                    if let RtpCodecParameters::Video {
+                                        mime_type: MimeTypeVideo::Rtx,
+                                        payload_type,
+                                        parameters,
+                                        ..
+                                    } = codec
+                                    {
+                                        if let Some(RtpCodecParametersParametersValue::Number(
+                                            apt,
+                                        )) = parameters.get("apt")
+                                        {
+                                            options
+                                                .rtx
+                                                .register_payload_type(*payload_type, *apt as u8);
+                                        }
+                                    }
+                                }
+                                for encoding in &tracer_consumer.rtp_parameters().encodings {
+                                    if let (Some(ssrc), Some(rtx)) = (encoding.ssrc, &encoding.rtx)
+                                    {
+                                        options.rtx.register_ssrc(rtx.ssrc, ssrc);
+                                    }
+                                }
+                                let log = std::fs::File::create("video_log.json").unwrap();
+                                //  the packets themselves can be kept too, to replay
+                                //  through `pcaptrace` when a parser bug is suspected
+                                let logger = match rtpdump_recording() {
+                                    Some(rtpdump) => frametrace::spawn_rtp_logger_recording(
+                                        log, options, rtpdump,
+                                    ),
+                                    None => frametrace::spawn_rtp_logger_with_options(log, options),
+                                };
+                                let dropped = Arc::new(AtomicUsize::new(0));
+                                //  rtcp the router sends the tracer (sender reports
+                                //  and sdes), told apart from rtp by the logger. The
+                                //  browser's feedback (PLI, FIR, NACK) ends at the
+                                //  router and never reaches it, so frames are only
+                                //  correlated with feedback when logging a capture.
+                                let (rtcp_logger, rtcp_dropped) = (logger.clone(), dropped.clone());
+                                let rtcp_handler = tracer_transport.on_rtcp(move |pkt| {
+                                    send_to_logger(&rtcp_logger, &rtcp_dropped, pkt);
+                                });
                                 let handler = tracer_consumer.on_rtp(move |pkt| {
-                                    let _ = logger.try_send(pkt.to_vec());
+                                    send_to_logger(&logger, &dropped, pkt);
                                 });
 
                                 //  TODO: manage lifecycle of these
                                 std::mem::forget(tracer_consumer);
                                 std::mem::forget(handler);
+                                std::mem::forget(rtcp_handler);
                             }
                         }
                         Err(error) => {
@@ -0,0 +1,167 @@
+/// Logs the VP8 streams of a pcap or pcapng capture, like `echoserver` logs
+/// live ones, an rtpdump recording of them, or the frames of an IVF or WebM
+/// file.
+///
+/// usage: pcaptrace <capture> [--port <port>]... [--payload-type <pt>]...
+///                  [--srtp <ssrc>:<profile>:<key>]... [--ivf <ssrc>:<file>]...
+///                  [--modes] [--output <log>]
+///
+/// `--srtp` unprotects the SSRC's SRTP and SRTCP with a profile like
+/// `AES_CM_128_HMAC_SHA1_80` and the hex master key followed by its salt.
+/// `--ivf` writes the SSRC's complete frames to an IVF file.
+/// `--modes` logs the macroblock prediction modes of each frame.
+///
+/// IVF and WebM files only take `--output`, and rtpdump files, which don't
+/// record ports, everything but `--port`.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::exit;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: pcaptrace <capture> [--port <port>]... [--payload-type <pt>]... \
+         [--srtp <ssrc>:<profile>:<key>]... [--ivf <ssrc>:<file>]... [--modes] [--output <log>]"
+    );
+    exit(2)
+}
+
+/// Parses a decimal or `0x` prefixed hex SSRC
+fn ssrc(arg: &str) -> Option<u32> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+/// Parses `<ssrc>:<profile>:<hex master key and salt>`
+fn srtp_stream(arg: &str) -> Option<(u32, frametrace::SrtpContext)> {
+    let mut parts = arg.splitn(3, ':');
+    let ssrc = ssrc(parts.next()?)?;
+    let profile = frametrace::SrtpProfile::from_name(parts.next()?)?;
+    let hex = parts.next()?;
+    let key = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    if key.len() < 16 {
+        return None;
+    }
+    let (master_key, master_salt) = key.split_at(16);
+    let context = frametrace::SrtpContext::new(profile, master_key, master_salt).ok()?;
+    Some((ssrc, context))
+}
+
+/// Kinds of input, told apart by their signatures
+enum Input {
+    Capture,
+    RtpDump,
+    Ivf,
+    Webm,
+}
+
+/// Creates a buffered output file, exiting when it can't be
+fn create(path: &str) -> BufWriter<File> {
+    match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("error creating {}: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
+fn main() {
+    let mut capture = None;
+    let mut output = None;
+    let mut filter = frametrace::RtpFilter::default();
+    let mut options = frametrace::LoggerOptions::default();
+    let mut ivf_paths = vec![];
+    //  options given that only apply to RTP
+    let mut rtp_args = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        if arg.starts_with("--") && arg != "--output" {
+            rtp_args.push(arg.clone());
+        }
+        match arg.as_str() {
+            "--port" => filter
+                .ports
+                .push(value().parse().unwrap_or_else(|_| usage())),
+            "--payload-type" => filter
+                .payload_types
+                .push(value().parse().unwrap_or_else(|_| usage())),
+            "--srtp" => {
+                let (ssrc, context) = srtp_stream(&value()).unwrap_or_else(|| usage());
+                options.srtp.add_stream(ssrc, context);
+            }
+            "--ivf" => {
+                let arg = value();
+                let (ssrc, path) = arg
+                    .split_once(':')
+                    .and_then(|(s, path)| Some((ssrc(s)?, path.to_string())))
+                    .unwrap_or_else(|| usage());
+                ivf_paths.push((ssrc, path));
+            }
+            "--modes" => options.macroblock_modes = true,
+            "--output" => output = Some(value()),
+            _ if capture.is_none() && !arg.starts_with("--") => capture = Some(arg),
+            _ => usage(),
+        }
+    }
+    let capture = capture.unwrap_or_else(|| usage());
+
+    let data = match std::fs::read(&capture) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("error reading {}: {}", capture, e);
+            exit(1);
+        }
+    };
+    let input = if data.starts_with(b"#!rtpplay1.0 ") {
+        Input::RtpDump
+    } else if data.starts_with(b"DKIF") {
+        Input::Ivf
+    } else if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Input::Webm
+    } else {
+        Input::Capture
+    };
+    //  before creating any outputs, which would be left empty
+    let rejected = match input {
+        Input::Capture => None,
+        Input::RtpDump => rtp_args
+            .iter()
+            .find(|arg| *arg == "--port")
+            .map(|arg| (arg, "rtpdump")),
+        Input::Ivf => rtp_args.first().map(|arg| (arg, "IVF")),
+        Input::Webm => rtp_args.first().map(|arg| (arg, "WebM")),
+    };
+    if let Some((arg, kind)) = rejected {
+        eprintln!("{} doesn't apply to {} files", arg, kind);
+        exit(2);
+    }
+
+    let ivf_outputs: HashMap<u32, Box<dyn Write + Send>> = ivf_paths
+        .iter()
+        .map(|(ssrc, path)| (*ssrc, Box::new(create(path)) as Box<dyn Write + Send>))
+        .collect();
+    //  log to stdout unless an output file is given
+    let w: Box<dyn Write> = match output {
+        Some(output) => Box::new(create(&output)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    let result = match input {
+        Input::Capture => frametrace::log_capture_with_ivf(&data, &filter, options, w, ivf_outputs),
+        Input::RtpDump => frametrace::log_rtpdump_with_ivf(&data, &filter, options, w, ivf_outputs),
+        Input::Ivf => frametrace::log_ivf(&data, w),
+        Input::Webm => frametrace::log_webm(&data, w),
+    };
+    if let Err(e) = result {
+        eprintln!("error logging {}: {}", capture, e);
+        exit(1);
+    }
+}
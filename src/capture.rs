@@ -0,0 +1,444 @@
+//! UDP datagrams from pcap and pcapng captures,
+//! https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcap and
+//! https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcapng
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use nom::bytes::complete::take;
+use nom::number::Endianness;
+use nom::IResult;
+
+use crate::{is_rtcp, Error};
+
+fn truncated(_: nom::Err<nom::error::Error<&[u8]>>) -> Error {
+    Error::TruncatedCapture
+}
+
+fn read_u16(data: &[u8], endianness: Endianness) -> IResult<&[u8], u16> {
+    nom::number::complete::u16(endianness)(data)
+}
+
+fn read_u32(data: &[u8], endianness: Endianness) -> IResult<&[u8], u32> {
+    nom::number::complete::u32(endianness)(data)
+}
+
+/// Network byte order u16 at `offset`, `None` past the end of `data`
+fn be_u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// A captured UDP datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpDatagram {
+    /// Since the unix epoch
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Link layer headers of the supported link types,
+/// https://www.tcpdump.org/linktypes.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkType {
+    /// BSD loopback, a 4 byte address family in host byte order
+    Null,
+    Ethernet,
+    /// IPv4 or IPv6 without a link layer header
+    Raw,
+    /// Linux "any" device captures
+    LinuxSll,
+    LinuxSll2,
+}
+
+impl LinkType {
+    fn new(link_type: u32) -> Result<Self, Error> {
+        match link_type {
+            0 => Ok(Self::Null),
+            1 => Ok(Self::Ethernet),
+            101 | 228 | 229 => Ok(Self::Raw),
+            113 => Ok(Self::LinuxSll),
+            276 => Ok(Self::LinuxSll2),
+            _ => Err(Error::UnsupportedLinkType(link_type)),
+        }
+    }
+
+    /// The UDP datagram carried by a captured packet, `None` for anything
+    /// else, fragments and packets truncated by the snap length
+    fn udp_datagram(self, packet: &[u8], timestamp: Duration) -> Option<UdpDatagram> {
+        let (ethertype, ip) = match self {
+            Self::Null => (None, packet.get(4..)?),
+            Self::Raw => (None, packet),
+            Self::Ethernet => {
+                let mut offset = 12;
+                let mut ethertype = be_u16_at(packet, offset)?;
+                //  skip 802.1Q and 802.1ad tags
+                while ethertype == 0x8100 || ethertype == 0x88a8 {
+                    offset += 4;
+                    ethertype = be_u16_at(packet, offset)?;
+                }
+                (Some(ethertype), packet.get(offset + 2..)?)
+            }
+            Self::LinuxSll => (Some(be_u16_at(packet, 14)?), packet.get(16..)?),
+            Self::LinuxSll2 => (Some(be_u16_at(packet, 0)?), packet.get(20..)?),
+        };
+        if ethertype.is_some_and(|ethertype| ethertype != 0x0800 && ethertype != 0x86dd) {
+            return None;
+        }
+
+        let (source, destination, udp) = match ip.first()? >> 4 {
+            4 => {
+                let header_length = (ip[0] & 0xf) as usize * 4;
+                let total_length = be_u16_at(ip, 2)? as usize;
+                //  fragments other than the last have MF set, and those
+                //  after the first an offset
+                let fragment = be_u16_at(ip, 6)?;
+                if *ip.get(9)? != 17 || fragment & 0x3fff != 0 {
+                    return None;
+                }
+                let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+                let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+                (
+                    IpAddr::from(Ipv4Addr::from(source)),
+                    IpAddr::from(Ipv4Addr::from(destination)),
+                    ip.get(header_length..total_length.min(ip.len()))?,
+                )
+            }
+            6 => {
+                //  extension headers aren't followed
+                if *ip.get(6)? != 17 {
+                    return None;
+                }
+                let payload_length = be_u16_at(ip, 4)? as usize;
+                let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+                let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+                (
+                    IpAddr::from(Ipv6Addr::from(source)),
+                    IpAddr::from(Ipv6Addr::from(destination)),
+                    ip.get(40..(40 + payload_length).min(ip.len()))?,
+                )
+            }
+            _ => return None,
+        };
+
+        let length = be_u16_at(udp, 4)? as usize;
+        Some(UdpDatagram {
+            timestamp,
+            source: SocketAddr::new(source, be_u16_at(udp, 0)?),
+            destination: SocketAddr::new(destination, be_u16_at(udp, 2)?),
+            payload: udp.get(8..length.min(udp.len()))?.to_vec(),
+        })
+    }
+}
+
+/// Reads the UDP datagrams of a pcap or pcapng capture in capture order
+pub fn udp_datagrams(capture: &[u8]) -> Result<Vec<UdpDatagram>, Error> {
+    match capture.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng_datagrams(capture),
+        Some(_) => pcap_datagrams(capture),
+        None => Err(Error::TruncatedCapture),
+    }
+}
+
+fn pcap_datagrams(capture: &[u8]) -> Result<Vec<UdpDatagram>, Error> {
+    let (data, magic) = read_u32(capture, Endianness::Little).map_err(truncated)?;
+    let (endianness, nanoseconds) = match magic {
+        0xa1b2c3d4 => (Endianness::Little, false),
+        0xa1b23c4d => (Endianness::Little, true),
+        0xd4c3b2a1 => (Endianness::Big, false),
+        0x4d3cb2a1 => (Endianness::Big, true),
+        _ => return Err(Error::UnsupportedCaptureFormat(magic)),
+    };
+    //  version, reserved fields and snap length precede the link type
+    let (data, _) = take(16usize)(data).map_err(truncated)?;
+    let (mut data, link_type) = read_u32(data, endianness).map_err(truncated)?;
+    let link_type = LinkType::new(link_type & 0xffff)?;
+
+    let mut datagrams = vec![];
+    while !data.is_empty() {
+        let (rest, seconds) = read_u32(data, endianness).map_err(truncated)?;
+        let (rest, fraction) = read_u32(rest, endianness).map_err(truncated)?;
+        let (rest, captured_length) = read_u32(rest, endianness).map_err(truncated)?;
+        let (rest, _original_length) = read_u32(rest, endianness).map_err(truncated)?;
+        let (rest, packet) = take(captured_length as usize)(rest).map_err(truncated)?;
+        data = rest;
+
+        let fraction = if nanoseconds {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+        let timestamp = Duration::from_secs(seconds as u64) + fraction;
+        datagrams.extend(link_type.udp_datagram(packet, timestamp));
+    }
+    Ok(datagrams)
+}
+
+/// Converts a timestamp in units of `resolution`, as given by the if_tsresol
+/// option, to a duration. `resolution` must be at most 10^19 or 2^63 units
+/// per second.
+fn pcapng_timestamp(timestamp: u64, resolution: u8) -> Duration {
+    let units_per_second: u128 = if resolution & 0x80 == 0 {
+        10u128.pow(resolution as u32)
+    } else {
+        1u128 << (resolution & 0x7f)
+    };
+    let nanos = timestamp as u128 * 1_000_000_000 / units_per_second;
+    Duration::from_nanos(nanos as u64)
+}
+
+fn pcapng_datagrams(capture: &[u8]) -> Result<Vec<UdpDatagram>, Error> {
+    const SECTION_HEADER: u32 = 0x0a0d0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const ENHANCED_PACKET: u32 = 6;
+
+    let mut data = capture;
+    let mut endianness = Endianness::Little;
+    //  link type and timestamp resolution of each interface in the section,
+    //  packets on unsupported link types are skipped
+    let mut interfaces: Vec<(Option<LinkType>, u8)> = vec![];
+    let mut datagrams = vec![];
+    while !data.is_empty() {
+        if data.get(..4) == Some(&[0x0a, 0x0d, 0x0d, 0x0a]) {
+            //  each section sets its byte order
+            endianness = match data.get(8..12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => Endianness::Big,
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => Endianness::Little,
+                Some(magic) => {
+                    let magic = u32::from_be_bytes(magic.try_into().unwrap());
+                    return Err(Error::UnsupportedCaptureFormat(magic));
+                }
+                None => return Err(Error::TruncatedCapture),
+            };
+            interfaces.clear();
+        }
+
+        let (rest, block_type) = read_u32(data, endianness).map_err(truncated)?;
+        let (_, total_length) = read_u32(rest, endianness).map_err(truncated)?;
+        if total_length < 12 {
+            return Err(Error::TruncatedCapture);
+        }
+        let (rest, block) = take(total_length as usize)(data).map_err(truncated)?;
+        data = rest;
+        let body = &block[8..block.len() - 4];
+
+        match block_type {
+            SECTION_HEADER => {}
+            INTERFACE_DESCRIPTION => {
+                let (options, link_type) = read_u16(body, endianness).map_err(truncated)?;
+                //  reserved and snap length
+                let (mut options, _) = take(6usize)(options).map_err(truncated)?;
+                let mut resolution = 6;
+                while options.len() >= 4 {
+                    let (rest, code) = read_u16(options, endianness).map_err(truncated)?;
+                    let (rest, length) = read_u16(rest, endianness).map_err(truncated)?;
+                    let padded = (length as usize + 3) & !3;
+                    let (rest, value) = take(padded)(rest).map_err(truncated)?;
+                    options = rest;
+                    match code {
+                        0 => break,
+                        9 if length == 1 => resolution = value[0],
+                        _ => {}
+                    }
+                }
+                //  finer resolutions than a u64 of units per second can hold
+                let max_exponent = if resolution & 0x80 == 0 { 19 } else { 63 };
+                if resolution & 0x7f > max_exponent {
+                    return Err(Error::UnsupportedTimestampResolution(resolution));
+                }
+                interfaces.push((LinkType::new(link_type as u32).ok(), resolution));
+            }
+            ENHANCED_PACKET => {
+                let (rest, interface) = read_u32(body, endianness).map_err(truncated)?;
+                let (rest, high) = read_u32(rest, endianness).map_err(truncated)?;
+                let (rest, low) = read_u32(rest, endianness).map_err(truncated)?;
+                let (rest, captured_length) = read_u32(rest, endianness).map_err(truncated)?;
+                let (rest, _original_length) = read_u32(rest, endianness).map_err(truncated)?;
+                let (_, packet) = take(captured_length as usize)(rest).map_err(truncated)?;
+
+                if let Some((Some(link_type), resolution)) = interfaces.get(interface as usize) {
+                    let timestamp = pcapng_timestamp((high as u64) << 32 | low as u64, *resolution);
+                    datagrams.extend(link_type.udp_datagram(packet, timestamp));
+                }
+            }
+            //  simple packet blocks lack timestamps, and the other blocks
+            //  don't hold packets
+            _ => {}
+        }
+    }
+    Ok(datagrams)
+}
+
+/// Picks the RTP and RTCP datagrams out of a capture
+#[derive(Debug, Clone, Default)]
+pub struct RtpFilter {
+    /// Source or destination ports to keep, any port when empty
+    pub ports: Vec<u16>,
+    /// Payload types of the RTP packets to keep, any when empty. RTCP is
+    /// kept regardless.
+    pub payload_types: Vec<u8>,
+}
+
+impl RtpFilter {
+    /// Whether `datagram` passes the filter and looks like RTP or RTCP, which
+    /// unlike STUN, DTLS and TURN channels start with version 2,
+    /// https://datatracker.ietf.org/doc/html/rfc7983#section-7
+    pub fn matches(&self, datagram: &UdpDatagram) -> bool {
+        let port_matches = self.ports.is_empty()
+            || self.ports.contains(&datagram.source.port())
+            || self.ports.contains(&datagram.destination.port());
+        port_matches && self.matches_payload(&datagram.payload)
+    }
+
+    /// Like `matches`, for packets whose ports aren't known
+    pub fn matches_payload(&self, payload: &[u8]) -> bool {
+        if payload.len() < 8 || payload[0] >> 6 != 2 {
+            return false;
+        }
+        if is_rtcp(payload) {
+            return true;
+        }
+        payload.len() >= 12
+            && (self.payload_types.is_empty() || self.payload_types.contains(&(payload[1] & 0x7f)))
+    }
+}
+
+#[cfg(test)]
+fn test_ipv4_udp(payload: &[u8]) -> Vec<u8> {
+    let total_length = (20 + 8 + payload.len()) as u16;
+    let udp_length = (8 + payload.len()) as u16;
+    let mut ip = vec![0x45, 0];
+    ip.extend(total_length.to_be_bytes());
+    ip.extend([0, 0, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+    //  5000 to 6000
+    ip.extend([0x13, 0x88, 0x17, 0x70]);
+    ip.extend(udp_length.to_be_bytes());
+    ip.extend([0, 0]);
+    ip.extend(payload);
+    ip
+}
+
+/// Little endian microsecond pcap of ethernet frames carrying each payload
+/// over IPv4 UDP, paired with its timestamp in microseconds
+#[cfg(test)]
+pub(crate) fn test_pcap(packets: &[(u64, &[u8])]) -> Vec<u8> {
+    let mut pcap = vec![];
+    pcap.extend(0xa1b2c3d4u32.to_le_bytes());
+    pcap.extend([2, 0, 4, 0]);
+    pcap.extend([0; 8]);
+    pcap.extend(65535u32.to_le_bytes());
+    pcap.extend(1u32.to_le_bytes());
+    for (timestamp, payload) in packets {
+        let mut frame = vec![0; 12];
+        frame.extend([0x08, 0x00]);
+        frame.extend(test_ipv4_udp(payload));
+        pcap.extend(((timestamp / 1_000_000) as u32).to_le_bytes());
+        pcap.extend(((timestamp % 1_000_000) as u32).to_le_bytes());
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend(frame);
+    }
+    pcap
+}
+
+#[test]
+fn read_pcap() {
+    let mut pcap = test_pcap(&[(1_500_000, &[0x80, 96, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1])]);
+    //  an arp frame is skipped
+    let arp = [[0; 12].as_slice(), &[0x08, 0x06], &[0; 28]].concat();
+    pcap.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+    pcap.extend((arp.len() as u32).to_le_bytes());
+    pcap.extend((arp.len() as u32).to_le_bytes());
+    pcap.extend(arp);
+
+    let datagrams = udp_datagrams(&pcap).unwrap();
+    assert_eq!(datagrams.len(), 1);
+    let datagram = &datagrams[0];
+    assert_eq!(datagram.timestamp, Duration::from_millis(1500));
+    assert_eq!(datagram.source, "10.0.0.1:5000".parse().unwrap());
+    assert_eq!(datagram.destination, "10.0.0.2:6000".parse().unwrap());
+    assert_eq!(datagram.payload.len(), 12);
+
+    assert!(RtpFilter::default().matches(datagram));
+    let filter = RtpFilter {
+        ports: vec![6000],
+        payload_types: vec![97],
+    };
+    assert!(!filter.matches(datagram));
+
+    assert!(matches!(
+        udp_datagrams(&pcap[..pcap.len() - 1]),
+        Err(Error::TruncatedCapture)
+    ));
+}
+
+#[test]
+fn read_pcapng() {
+    let block = |block_type: u32, body: &[u8]| {
+        let total_length = (body.len() + 12) as u32;
+        [
+            block_type.to_le_bytes().as_slice(),
+            &total_length.to_le_bytes(),
+            body,
+            &total_length.to_le_bytes(),
+        ]
+        .concat()
+    };
+    //  linux sll carrying IPv6, on an interface with nanosecond timestamps
+    let mut sll = vec![0; 14];
+    sll.extend([0x86, 0xdd]);
+    sll.extend([0x60, 0, 0, 0, 0, 12, 17, 64]);
+    sll.extend([0; 15]);
+    sll.push(1);
+    sll.extend([0; 15]);
+    sll.push(2);
+    sll.extend([0x13, 0x88, 0x17, 0x70, 0, 12, 0, 0, 0xab, 0xcd, 0xef, 0x01]);
+
+    let mut interface = vec![113, 0, 0, 0, 0, 0, 0, 0];
+    interface.extend([9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+    let timestamp = 2_000_000_001u64;
+    let mut packet = vec![0, 0, 0, 0];
+    packet.extend(((timestamp >> 32) as u32).to_le_bytes());
+    packet.extend((timestamp as u32).to_le_bytes());
+    packet.extend((sll.len() as u32).to_le_bytes());
+    packet.extend((sll.len() as u32).to_le_bytes());
+    packet.extend(&sll);
+    packet.resize((packet.len() + 3) & !3, 0);
+
+    let mut section = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+    section.extend((-1i64).to_le_bytes());
+    let capture = [
+        block(0x0a0d0d0a, &section),
+        block(1, &interface),
+        block(6, &packet),
+    ]
+    .concat();
+
+    let datagrams = udp_datagrams(&capture).unwrap();
+    assert_eq!(
+        datagrams,
+        [UdpDatagram {
+            timestamp: Duration::new(2, 1),
+            source: "[::1]:5000".parse().unwrap(),
+            destination: "[::2]:6000".parse().unwrap(),
+            payload: vec![0xab, 0xcd, 0xef, 0x01],
+        }]
+    );
+
+    for resolution in [0x7f, 0x80 | 64] {
+        interface[12] = resolution;
+        let capture = [
+            block(0x0a0d0d0a, &section),
+            block(1, &interface),
+            block(6, &packet),
+        ]
+        .concat();
+        assert!(matches!(
+            udp_datagrams(&capture),
+            Err(Error::UnsupportedTimestampResolution(r)) if r == resolution
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+//! VP8 payload descriptor, https://datatracker.ietf.org/doc/html/rfc7741#section-4.2
+
+use rtp::codecs::vp8::Vp8Packet;
+use serde::Serialize;
+
+/// Payload descriptor fields, `None` when their extension isn't present
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct PayloadDescriptor {
+    /// N bit, set when no other frame depends on this one
+    pub non_reference: bool,
+    pub picture_id: Option<u16>,
+    /// 7 or 15, as selected by the M bit
+    pub picture_id_bits: Option<u8>,
+    pub tl0_pic_idx: Option<u8>,
+    /// TID, the temporal layer
+    pub temporal_layer: Option<u8>,
+    /// Y bit, set when the frame only depends on base layer frames
+    pub layer_sync: Option<bool>,
+    /// KEYIDX, incremented on each key frame
+    pub key_idx: Option<u8>,
+}
+
+impl PayloadDescriptor {
+    /// Takes the descriptor `vp8_pkt` depacketized from `payload`, which
+    /// also holds the M bit it doesn't keep
+    pub fn new(vp8_pkt: &Vp8Packet, payload: &[u8]) -> Self {
+        //  picture id follows the required and extended control bytes
+        let long_picture_id = payload.get(2).is_some_and(|b| b & 0x80 != 0);
+        let present = |bit: u8| bit == 1;
+
+        Self {
+            non_reference: present(vp8_pkt.n),
+            picture_id: present(vp8_pkt.i).then_some(vp8_pkt.picture_id),
+            picture_id_bits: present(vp8_pkt.i).then_some(if long_picture_id { 15 } else { 7 }),
+            tl0_pic_idx: present(vp8_pkt.l).then_some(vp8_pkt.tl0_pic_idx),
+            temporal_layer: present(vp8_pkt.t).then_some(vp8_pkt.tid),
+            layer_sync: present(vp8_pkt.t).then_some(vp8_pkt.y == 1),
+            key_idx: present(vp8_pkt.k).then_some(vp8_pkt.key_idx),
+        }
+    }
+}
+
+#[test]
+fn parse_extended_descriptor() {
+    use rtp::packetizer::Depacketizer;
+
+    //  X and S, then I, L and T, a 15-bit picture id, tl0picidx, and TID 2
+    //  with Y set
+    let payload = bytes::Bytes::from_static(&[0x90, 0xe0, 0x81, 0x23, 0x05, 0xa0, 0x00]);
+    let mut vp8_pkt = Vp8Packet::default();
+    vp8_pkt.depacketize(&payload).unwrap();
+
+    assert_eq!(
+        PayloadDescriptor::new(&vp8_pkt, &payload),
+        PayloadDescriptor {
+            non_reference: false,
+            picture_id: Some(0x123),
+            picture_id_bits: Some(15),
+            tl0_pic_idx: Some(5),
+            temporal_layer: Some(2),
+            layer_sync: Some(true),
+            key_idx: None,
+        }
+    );
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+/// Errors returned while parsing RTP packets down through the VP8 frame header
+#[derive(Debug)]
+pub enum Error {
+    /// Frame is shorter than its frame tag (3 bytes, 10 for key frames)
+    TruncatedFrameTag,
+    /// Key frame start code wasn't `9d 01 2a`
+    BadStartCode([u8; 3]),
+    /// Frame tag version is greater than 3
+    UnsupportedVersion(u8),
+    /// Key frame color_space bit is set, which RFC 6386 reserves
+    ReservedColorSpace,
+    /// Bool decoder ran out of input
+    BoolDecoderUnderrun,
+    /// Frame is shorter than the first partition size in its frame tag
+    TruncatedFirstPartition,
+    /// Frame ends within the DCT partition size table
+    TruncatedPartitionTable,
+    /// DCT partition at the given index extends past the end of the frame
+    TruncatedPartition(usize),
+    /// Inter frame arrived before any key frame, so its dimensions are unknown
+    MissingKeyFrame,
+    /// RTCP packet is shorter than its header or length says
+    TruncatedRtcpPacket,
+    /// RTCP version isn't 2
+    UnsupportedRtcpVersion(u8),
+    /// Capture ends within a header or packet
+    TruncatedCapture,
+    /// Capture isn't pcap or pcapng, going by its magic number
+    UnsupportedCaptureFormat(u32),
+    /// Capture's link layer isn't Ethernet, Linux SLL, loopback or raw IP
+    UnsupportedLinkType(u32),
+    /// pcapng interface's if_tsresol is finer than 10^19 or 2^63 units per
+    /// second
+    UnsupportedTimestampResolution(u8),
+    /// SRTP master key isn't 16 bytes or its salt is the wrong length for the
+    /// profile
+    InvalidSrtpKey,
+    /// SRTP or SRTCP packet is shorter than its header and tag
+    TruncatedSrtpPacket,
+    /// SRTP or SRTCP authentication tag didn't match
+    SrtpAuthentication,
+    /// IVF file ends within a header or frame
+    TruncatedIvfFile,
+    /// IVF header size field is smaller than the 32 bytes of the header
+    InvalidIvfHeaderSize(u16),
+    /// IVF file signature wasn't `DKIF`
+    BadIvfSignature([u8; 4]),
+    /// IVF file holds frames of a codec other than VP8
+    UnsupportedIvfCodec([u8; 4]),
+    /// WebM file ends within an element
+    TruncatedWebmFile,
+    /// WebM element id or size is malformed, or unknown where it's needed
+    InvalidWebmElement,
+    /// WebM block holds several laced frames
+    UnsupportedWebmLacing,
+    /// rtpdump file ends within a header or packet
+    TruncatedRtpDump,
+    /// rtpdump file doesn't start with `#!rtpplay1.0`
+    BadRtpDumpSignature,
+    RtpUnmarshal(webrtc_util::Error),
+    Depacketize(rtp::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TruncatedFrameTag => write!(f, "truncated vp8 frame tag"),
+            Error::BadStartCode(start_code) => {
+                write!(f, "invalid vp8 key frame start code {:02x?}", start_code)
+            }
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported vp8 version {}", version)
+            }
+            Error::ReservedColorSpace => write!(f, "reserved vp8 color space"),
+            Error::BoolDecoderUnderrun => write!(f, "bool decoder ran out of input"),
+            Error::TruncatedFirstPartition => write!(f, "truncated vp8 first partition"),
+            Error::TruncatedPartitionTable => write!(f, "truncated vp8 partition size table"),
+            Error::TruncatedPartition(idx) => write!(f, "truncated vp8 dct partition {}", idx),
+            Error::MissingKeyFrame => write!(f, "vp8 inter frame before any key frame"),
+            Error::TruncatedRtcpPacket => write!(f, "truncated rtcp packet"),
+            Error::UnsupportedRtcpVersion(version) => {
+                write!(f, "unsupported rtcp version {}", version)
+            }
+            Error::TruncatedCapture => write!(f, "truncated capture"),
+            Error::UnsupportedCaptureFormat(magic) => {
+                write!(f, "unsupported capture format, magic number {:08x}", magic)
+            }
+            Error::UnsupportedLinkType(link_type) => {
+                write!(f, "unsupported capture link type {}", link_type)
+            }
+            Error::UnsupportedTimestampResolution(resolution) => {
+                write!(
+                    f,
+                    "unsupported capture timestamp resolution {:02x}",
+                    resolution
+                )
+            }
+            Error::InvalidSrtpKey => write!(f, "invalid srtp master key or salt length"),
+            Error::TruncatedSrtpPacket => write!(f, "truncated srtp packet"),
+            Error::SrtpAuthentication => write!(f, "srtp authentication failed"),
+            Error::TruncatedIvfFile => write!(f, "truncated ivf file"),
+            Error::InvalidIvfHeaderSize(size) => write!(f, "invalid ivf header size {}", size),
+            Error::BadIvfSignature(signature) => {
+                write!(f, "invalid ivf signature {:02x?}", signature)
+            }
+            Error::UnsupportedIvfCodec(fourcc) => {
+                write!(
+                    f,
+                    "unsupported ivf codec {}",
+                    String::from_utf8_lossy(fourcc)
+                )
+            }
+            Error::TruncatedWebmFile => write!(f, "truncated webm file"),
+            Error::InvalidWebmElement => write!(f, "invalid webm element"),
+            Error::UnsupportedWebmLacing => write!(f, "unsupported laced webm block"),
+            Error::TruncatedRtpDump => write!(f, "truncated rtpdump file"),
+            Error::BadRtpDumpSignature => write!(f, "invalid rtpdump signature"),
+            Error::RtpUnmarshal(e) => write!(f, "error unmarshaling rtp packet: {}", e),
+            Error::Depacketize(e) => write!(f, "error depacketizing vp8 payload: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RtpUnmarshal(e) => Some(e),
+            Error::Depacketize(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! RTP header extensions, https://datatracker.ietf.org/doc/html/rfc8285
+//!
+//! The rtp crate parses both the one-byte and two-byte forms into ids and
+//! payloads, the ids are mapped to extensions through the URIs negotiated
+//! in SDP.
+
+use rtp::header::Header;
+use serde::Serialize;
+
+/// Extensions that can be decoded, identified by their URI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderExtensionKind {
+    AbsSendTime,
+    TransportCc,
+    VideoOrientation,
+    PlayoutDelay,
+    VideoContentType,
+    VideoTiming,
+    AbsCaptureTime,
+}
+
+impl HeaderExtensionKind {
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        Some(match uri {
+            "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time" => Self::AbsSendTime,
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01" => {
+                Self::TransportCc
+            }
+            "urn:3gpp:video-orientation" => Self::VideoOrientation,
+            "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay" => Self::PlayoutDelay,
+            "http://www.webrtc.org/experiments/rtp-hdrext/video-content-type" => {
+                Self::VideoContentType
+            }
+            "http://www.webrtc.org/experiments/rtp-hdrext/video-timing" => Self::VideoTiming,
+            "http://www.webrtc.org/experiments/rtp-hdrext/abs-capture-time" => Self::AbsCaptureTime,
+            _ => return None,
+        })
+    }
+}
+
+/// Maps the extension ids of a stream to the extensions they carry
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderExtensionRegistry {
+    kinds: [Option<HeaderExtensionKind>; 256],
+}
+
+impl Default for HeaderExtensionRegistry {
+    fn default() -> Self {
+        Self { kinds: [None; 256] }
+    }
+}
+
+impl HeaderExtensionRegistry {
+    /// Returns false for URIs of extensions that can't be decoded
+    pub fn register(&mut self, id: u8, uri: &str) -> bool {
+        let kind = HeaderExtensionKind::from_uri(uri);
+        self.kinds[id as usize] = kind;
+        kind.is_some()
+    }
+
+    /// Decodes the registered extensions of `header`. Extensions whose
+    /// payload has an unexpected length are left out.
+    pub fn decode(&self, header: &Header) -> HeaderExtensions {
+        let mut extensions = HeaderExtensions::default();
+        for extension in &header.extensions {
+            let data = &extension.payload[..];
+            match self.kinds[extension.id as usize] {
+                Some(HeaderExtensionKind::AbsSendTime) => {
+                    extensions.abs_send_time = AbsSendTime::parse(data);
+                }
+                Some(HeaderExtensionKind::TransportCc) => {
+                    extensions.transport_cc_sequence_number =
+                        data.try_into().ok().map(u16::from_be_bytes);
+                }
+                Some(HeaderExtensionKind::VideoOrientation) => {
+                    extensions.video_orientation = VideoOrientation::parse(data);
+                }
+                Some(HeaderExtensionKind::PlayoutDelay) => {
+                    extensions.playout_delay = PlayoutDelay::parse(data);
+                }
+                Some(HeaderExtensionKind::VideoContentType) => {
+                    extensions.video_content_type = VideoContentType::parse(data);
+                }
+                Some(HeaderExtensionKind::VideoTiming) => {
+                    extensions.video_timing = VideoTiming::parse(data);
+                }
+                Some(HeaderExtensionKind::AbsCaptureTime) => {
+                    extensions.abs_capture_time = AbsCaptureTime::parse(data);
+                }
+                None => {}
+            }
+        }
+        extensions
+    }
+}
+
+/// Decoded header extensions, `None` when absent
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct HeaderExtensions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abs_send_time: Option<AbsSendTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport_cc_sequence_number: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_orientation: Option<VideoOrientation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playout_delay: Option<PlayoutDelay>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_content_type: Option<VideoContentType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_timing: Option<VideoTiming>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abs_capture_time: Option<AbsCaptureTime>,
+}
+
+impl HeaderExtensions {
+    /// Fills the extensions missing from `self` with those of `other`, e.g.
+    /// to combine the packets of a frame
+    pub fn merge(&mut self, other: &Self) {
+        self.abs_send_time = self.abs_send_time.or(other.abs_send_time);
+        self.transport_cc_sequence_number = self
+            .transport_cc_sequence_number
+            .or(other.transport_cc_sequence_number);
+        self.video_orientation = self.video_orientation.or(other.video_orientation);
+        self.playout_delay = self.playout_delay.or(other.playout_delay);
+        self.video_content_type = self.video_content_type.or(other.video_content_type);
+        self.video_timing = self.video_timing.or(other.video_timing);
+        self.abs_capture_time = self.abs_capture_time.or(other.abs_capture_time);
+    }
+}
+
+/// Send time in seconds, 6.18 fixed point wrapping every 64 seconds,
+/// http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct AbsSendTime(pub f64);
+
+impl AbsSendTime {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let bytes: [u8; 3] = data.try_into().ok()?;
+        let fixed = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        Some(Self(fixed as f64 / (1 << 18) as f64))
+    }
+}
+
+/// Coordination of video orientation (CVO), 3GPP TS 26.114 section 7.4.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VideoOrientation {
+    /// Clockwise rotation the receiver applies before display
+    pub rotation_degrees: u16,
+    /// Horizontal flip applied before the rotation
+    pub flip: bool,
+    pub back_camera: bool,
+}
+
+impl VideoOrientation {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let [byte]: [u8; 1] = data.try_into().ok()?;
+        Some(Self {
+            rotation_degrees: (byte & 0x3) as u16 * 90,
+            flip: byte & 0x4 != 0,
+            back_camera: byte & 0x8 != 0,
+        })
+    }
+}
+
+/// Playout delay limits the sender asks for,
+/// http://www.webrtc.org/experiments/rtp-hdrext/playout-delay
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PlayoutDelay {
+    pub min_ms: u16,
+    pub max_ms: u16,
+}
+
+impl PlayoutDelay {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let bytes: [u8; 3] = data.try_into().ok()?;
+        //  two 12-bit values in units of 10ms
+        let packed = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        Some(Self {
+            min_ms: (packed >> 12) as u16 * 10,
+            max_ms: (packed & 0xfff) as u16 * 10,
+        })
+    }
+}
+
+/// http://www.webrtc.org/experiments/rtp-hdrext/video-content-type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoContentType {
+    Unspecified,
+    Screenshare,
+}
+
+impl VideoContentType {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let [byte]: [u8; 1] = data.try_into().ok()?;
+        //  the upper bits used to carry simulcast and experiment ids
+        Some(if byte & 0x1 != 0 {
+            Self::Screenshare
+        } else {
+            Self::Unspecified
+        })
+    }
+}
+
+/// Milliseconds from capture to each step of sending the frame,
+/// http://www.webrtc.org/experiments/rtp-hdrext/video-timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VideoTiming {
+    /// Why timing was sent, 1 for the periodic timer and 2 for an outlier
+    /// frame size. Missing from the older 12 byte form.
+    pub flags: Option<u8>,
+    pub encode_start_ms: u16,
+    pub encode_finish_ms: u16,
+    pub packetization_finish_ms: u16,
+    pub pacer_exit_ms: u16,
+    /// Filled in by SFUs along the path
+    pub network_timestamp_ms: u16,
+    pub network2_timestamp_ms: u16,
+}
+
+impl VideoTiming {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (flags, deltas) = match data.len() {
+            13 => (Some(data[0]), &data[1..]),
+            12 => (None, data),
+            _ => return None,
+        };
+        let delta = |i: usize| u16::from_be_bytes([deltas[i * 2], deltas[i * 2 + 1]]);
+        Some(Self {
+            flags,
+            encode_start_ms: delta(0),
+            encode_finish_ms: delta(1),
+            packetization_finish_ms: delta(2),
+            pacer_exit_ms: delta(3),
+            network_timestamp_ms: delta(4),
+            network2_timestamp_ms: delta(5),
+        })
+    }
+}
+
+/// Capture time on the original sender's clock,
+/// http://www.webrtc.org/experiments/rtp-hdrext/abs-capture-time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AbsCaptureTime {
+    /// NTP timestamp, 32.32 fixed point seconds
+    pub capture_timestamp: u64,
+    /// Offset from the capturer's clock to the sender's, 32.32 fixed point
+    /// seconds
+    pub estimated_capture_clock_offset: Option<i64>,
+}
+
+impl AbsCaptureTime {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (timestamp, offset) = match data.len() {
+            8 => (data, None),
+            16 => (&data[..8], Some(&data[8..])),
+            _ => return None,
+        };
+        Some(Self {
+            capture_timestamp: u64::from_be_bytes(timestamp.try_into().ok()?),
+            estimated_capture_clock_offset: offset
+                .map(|offset| offset.try_into().map(i64::from_be_bytes))
+                .transpose()
+                .ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_header(profile: u16, extensions: &[(u8, &[u8])]) -> Header {
+    use webrtc_util::{Marshal, Unmarshal};
+
+    let header = Header {
+        version: 2,
+        extension: true,
+        extension_profile: profile,
+        extensions: extensions
+            .iter()
+            .map(|(id, payload)| rtp::header::Extension {
+                id: *id,
+                payload: bytes::Bytes::copy_from_slice(payload),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    //  round trip so the extensions are parsed like received packets
+    let raw = header.marshal().unwrap();
+    Header::unmarshal(&mut &raw[..]).unwrap()
+}
+
+#[test]
+fn decode_one_and_two_byte_extensions() {
+    let mut registry = HeaderExtensionRegistry::default();
+    assert!(registry.register(
+        3,
+        "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01"
+    ));
+    assert!(registry.register(4, "urn:3gpp:video-orientation"));
+    assert!(registry.register(
+        5,
+        "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay"
+    ));
+    assert!(!registry.register(6, "urn:ietf:params:rtp-hdrext:sdes:mid"));
+
+    let expected = HeaderExtensions {
+        transport_cc_sequence_number: Some(0x1234),
+        video_orientation: Some(VideoOrientation {
+            rotation_degrees: 270,
+            flip: false,
+            back_camera: true,
+        }),
+        playout_delay: Some(PlayoutDelay {
+            min_ms: 100,
+            max_ms: 2000,
+        }),
+        ..Default::default()
+    };
+    let extensions: [(u8, &[u8]); 4] = [
+        (3, &[0x12, 0x34]),
+        (4, &[0x0b]),
+        //  10 and 200 in units of 10ms
+        (5, &[0x00, 0xa0, 0xc8]),
+        (6, b"0"),
+    ];
+    for profile in [0xbede, 0x1000] {
+        let header = test_header(profile, &extensions);
+        assert_eq!(registry.decode(&header), expected);
+    }
+}
+
+#[test]
+fn decode_timing_extensions() {
+    assert_eq!(
+        AbsSendTime::parse(&[0x40, 0x00, 0x00]),
+        Some(AbsSendTime(16.0))
+    );
+    assert_eq!(
+        VideoTiming::parse(&[1, 0, 1, 0, 2, 0, 3, 0, 4, 0, 0, 0, 0]),
+        Some(VideoTiming {
+            flags: Some(1),
+            encode_start_ms: 1,
+            encode_finish_ms: 2,
+            packetization_finish_ms: 3,
+            pacer_exit_ms: 4,
+            network_timestamp_ms: 0,
+            network2_timestamp_ms: 0,
+        })
+    );
+    let mut capture_time = 0x0000_0001_8000_0000u64.to_be_bytes().to_vec();
+    capture_time.extend((-1i64).to_be_bytes());
+    assert_eq!(
+        AbsCaptureTime::parse(&capture_time),
+        Some(AbsCaptureTime {
+            capture_timestamp: 0x0000_0001_8000_0000,
+            estimated_capture_clock_offset: Some(-1),
+        })
+    );
+}
@@ -0,0 +1,172 @@
+//! Correlation of RTCP feedback with the RTP packets and frames answering it
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rtp::header::Header;
+use serde::Serialize;
+
+//  NACKed and missing sequence numbers are forgotten after this long, well
+//  before they could be confused with the same ones after wrapping around
+const NACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+//  sequence number jumps larger than this are taken as the stream restarting
+//  rather than packets going missing
+const MAX_GAP: i16 = 512;
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// A NACKed packet that arrived afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NackRecovery {
+    pub sequence_number: u16,
+    /// From the first NACK of the packet to its arrival
+    pub delay_ms: f64,
+}
+
+/// Feedback answered by a frame
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FrameFeedback {
+    /// From the first PLI or FIR still unanswered to this key frame
+    pub key_frame_request_delay_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nack_recoveries: Vec<NackRecovery>,
+    /// Packets of the frame that arrived as RTX retransmissions
+    pub rtx_recovered_packets: usize,
+    /// Longest a retransmitted packet of the frame took to arrive after a
+    /// later packet showed it missing
+    pub rtx_delay_ms: Option<f64>,
+}
+
+/// Tracks the key frame requests, NACKs and retransmissions of one stream
+#[derive(Debug, Default)]
+pub struct FeedbackTracker {
+    key_frame_requested: Option<Instant>,
+    nacked: HashMap<u16, Instant>,
+    /// Arrival and NACK delay of recovered packets not yet logged with
+    /// their frame
+    recovered: HashMap<u16, (Instant, Duration)>,
+    highest: Option<u16>,
+    /// Sequence numbers skipped over and when that was noticed
+    missing: HashMap<u16, Instant>,
+    /// Arrival of retransmitted packets not yet logged with their frame,
+    /// and how long they had been missing
+    retransmitted: HashMap<u16, (Instant, Option<Duration>)>,
+}
+
+impl FeedbackTracker {
+    /// Records a PLI or FIR sent at `now`
+    pub fn request_key_frame(&mut self, now: Instant) {
+        self.key_frame_requested.get_or_insert(now);
+    }
+
+    /// Records a NACK of `sequence_numbers` sent at `now`
+    pub fn nack(&mut self, sequence_numbers: &[u16], now: Instant) {
+        self.expire(now);
+        for sequence_number in sequence_numbers {
+            self.nacked.entry(*sequence_number).or_insert(now);
+        }
+    }
+
+    /// Records a packet arriving at `now`, before it's reordered.
+    /// `retransmitted` is set for packets unwrapped from RTX.
+    pub fn receive(&mut self, sequence_number: u16, retransmitted: bool, now: Instant) {
+        if let Some(nacked) = self.nacked.remove(&sequence_number) {
+            let delay = now.saturating_duration_since(nacked);
+            if delay < NACK_TIMEOUT {
+                self.recovered.insert(sequence_number, (now, delay));
+            }
+        }
+
+        let missing_since = self.missing.remove(&sequence_number);
+        if retransmitted {
+            let delay = missing_since.map(|since| now.saturating_duration_since(since));
+            self.retransmitted.insert(sequence_number, (now, delay));
+            return;
+        }
+
+        let highest = *self.highest.get_or_insert(sequence_number);
+        let gap = sequence_number.wrapping_sub(highest) as i16;
+        if gap <= 0 {
+            return;
+        }
+        self.highest = Some(sequence_number);
+        if gap > 1 && gap <= MAX_GAP {
+            self.expire(now);
+            for offset in 1..gap as u16 {
+                self.missing.insert(highest.wrapping_add(offset), now);
+            }
+        }
+    }
+
+    /// Feedback answered by a frame assembled from `packet_headers` at `now`
+    pub fn frame(
+        &mut self,
+        packet_headers: &[Header],
+        key_frame: bool,
+        now: Instant,
+    ) -> FrameFeedback {
+        let key_frame_request_delay_ms = if key_frame {
+            self.key_frame_requested
+                .take()
+                .map(|requested| millis(now.saturating_duration_since(requested)))
+        } else {
+            None
+        };
+        let mut feedback = FrameFeedback {
+            key_frame_request_delay_ms,
+            ..Default::default()
+        };
+        for header in packet_headers {
+            let sequence_number = header.sequence_number;
+            if let Some((_, delay)) = self.recovered.remove(&sequence_number) {
+                feedback.nack_recoveries.push(NackRecovery {
+                    sequence_number,
+                    delay_ms: millis(delay),
+                });
+            }
+            if let Some((_, delay)) = self.retransmitted.remove(&sequence_number) {
+                feedback.rtx_recovered_packets += 1;
+                if let Some(delay) = delay.map(millis) {
+                    feedback.rtx_delay_ms =
+                        Some(feedback.rtx_delay_ms.map_or(delay, |d| d.max(delay)));
+                }
+            }
+        }
+        //  packets arriving after their frame was logged are never removed
+        //  above
+        self.expire(now);
+        feedback
+    }
+
+    /// Forgets sequence numbers noted `NACK_TIMEOUT` or longer before `now`
+    fn expire(&mut self, now: Instant) {
+        let expired = |at: Instant| now.saturating_duration_since(at) >= NACK_TIMEOUT;
+        self.nacked.retain(|_, nacked| !expired(*nacked));
+        self.recovered.retain(|_, (arrival, _)| !expired(*arrival));
+        self.missing.retain(|_, since| !expired(*since));
+        self.retransmitted
+            .retain(|_, (arrival, _)| !expired(*arrival));
+    }
+}
+
+#[test]
+fn expire_unclaimed_recoveries() {
+    let mut tracker = FeedbackTracker::default();
+    let now = Instant::now();
+    tracker.receive(10, false, now);
+    tracker.nack(&[12], now);
+    tracker.receive(13, false, now);
+    //  11 and 12 arrive after the frame holding them was logged without them
+    tracker.receive(11, true, now);
+    tracker.receive(12, false, now);
+    assert_eq!(tracker.retransmitted.len(), 1);
+    assert_eq!(tracker.recovered.len(), 1);
+
+    let feedback = tracker.frame(&[], false, now + NACK_TIMEOUT);
+    assert_eq!(feedback, FrameFeedback::default());
+    assert!(tracker.retransmitted.is_empty());
+    assert!(tracker.recovered.is_empty());
+}
@@ -0,0 +1,244 @@
+//! IVF, the container of libvpx's tools, ffmpeg and the vp8-test-vectors.
+//!
+//! A 32 byte file header, then each frame prefixed by its size and
+//! timestamp, all little endian.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::Error;
+
+const SIGNATURE: &[u8; 4] = b"DKIF";
+const HEADER_SIZE: usize = 32;
+const FRAME_HEADER_SIZE: usize = 12;
+//  offset of the frame count, patched in once writing finishes
+const FRAME_COUNT_OFFSET: u64 = 24;
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IvfHeader {
+    /// Codec, `VP80` for VP8
+    pub fourcc: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    /// Frame timestamps count `timebase_numerator / timebase_denominator`
+    /// seconds
+    pub timebase_denominator: u32,
+    pub timebase_numerator: u32,
+    /// Zero when the writer couldn't seek back to fill it in
+    pub frame_count: u32,
+}
+
+impl IvfHeader {
+    /// Header for VP8 frames timestamped with the 90kHz RTP clock
+    pub fn vp8(width: u16, height: u16) -> Self {
+        Self {
+            fourcc: *b"VP80",
+            width,
+            height,
+            timebase_denominator: 90000,
+            timebase_numerator: 1,
+            frame_count: 0,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::TruncatedIvfFile);
+        }
+        if &data[..4] != SIGNATURE {
+            return Err(Error::BadIvfSignature(data[..4].try_into().unwrap()));
+        }
+        Ok(Self {
+            fourcc: data[8..12].try_into().unwrap(),
+            width: le_u16(data, 12),
+            height: le_u16(data, 14),
+            timebase_denominator: le_u32(data, 16),
+            timebase_numerator: le_u32(data, 20),
+            frame_count: le_u32(data, 24),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(SIGNATURE);
+        //  version 0
+        header[6..8].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        header[8..12].copy_from_slice(&self.fourcc);
+        header[12..14].copy_from_slice(&self.width.to_le_bytes());
+        header[14..16].copy_from_slice(&self.height.to_le_bytes());
+        header[16..20].copy_from_slice(&self.timebase_denominator.to_le_bytes());
+        header[20..24].copy_from_slice(&self.timebase_numerator.to_le_bytes());
+        header[24..28].copy_from_slice(&self.frame_count.to_le_bytes());
+        header
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IvfFrame<'a> {
+    /// In units of the header's timebase
+    pub timestamp: u64,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the frames of an IVF file
+#[derive(Debug, Clone)]
+pub struct IvfReader<'a> {
+    header: IvfHeader,
+    data: &'a [u8],
+}
+
+impl<'a> IvfReader<'a> {
+    pub fn new(ivf: &'a [u8]) -> Result<Self, Error> {
+        let header = IvfHeader::parse(ivf)?;
+        //  the header records its own size, which may grow in later versions
+        let header_size = le_u16(ivf, 6);
+        if (header_size as usize) < HEADER_SIZE {
+            return Err(Error::InvalidIvfHeaderSize(header_size));
+        }
+        let data = ivf
+            .get(header_size as usize..)
+            .ok_or(Error::TruncatedIvfFile)?;
+        Ok(Self { header, data })
+    }
+
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+}
+
+impl<'a> Iterator for IvfReader<'a> {
+    type Item = Result<IvfFrame<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let frame = (|| {
+            let header = self
+                .data
+                .get(..FRAME_HEADER_SIZE)
+                .ok_or(Error::TruncatedIvfFile)?;
+            let size = le_u32(header, 0) as usize;
+            let timestamp = u64::from_le_bytes(header[4..].try_into().unwrap());
+            let end = FRAME_HEADER_SIZE + size;
+            let data = self
+                .data
+                .get(FRAME_HEADER_SIZE..end)
+                .ok_or(Error::TruncatedIvfFile)?;
+            self.data = &self.data[end..];
+            Ok(IvfFrame { timestamp, data })
+        })();
+        //  nothing after a truncated frame can be found
+        if frame.is_err() {
+            self.data = &[];
+        }
+        Some(frame)
+    }
+}
+
+/// Writes frames to an IVF file
+#[derive(Debug)]
+pub struct IvfWriter<W> {
+    w: W,
+    frame_count: u32,
+}
+
+impl<W: Write> IvfWriter<W> {
+    /// Writes `header` to `w`, its frame count is filled in by `finish`
+    pub fn new(mut w: W, header: IvfHeader) -> io::Result<Self> {
+        w.write_all(&header.to_bytes())?;
+        Ok(Self { w, frame_count: 0 })
+    }
+
+    pub fn write_frame(&mut self, timestamp: u64, frame: &[u8]) -> io::Result<()> {
+        let size = u32::try_from(frame.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ivf frame too large"))?;
+        self.w.write_all(&size.to_le_bytes())?;
+        self.w.write_all(&timestamp.to_le_bytes())?;
+        self.w.write_all(frame)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Returns the writer, leaving the header's frame count as it was
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// Fills in the header's frame count and returns the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let end = self.w.stream_position()?;
+        self.w.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.w.write_all(&self.frame_count.to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(end))?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[test]
+fn write_and_read_frames() {
+    let mut writer = IvfWriter::new(io::Cursor::new(vec![]), IvfHeader::vp8(640, 480)).unwrap();
+    writer.write_frame(0, &[1, 2, 3]).unwrap();
+    writer.write_frame(3000, &[]).unwrap();
+    writer.write_frame(6000, &[4; 300]).unwrap();
+    let ivf = writer.finish().unwrap().into_inner();
+    assert_eq!(ivf.len(), HEADER_SIZE + 3 * FRAME_HEADER_SIZE + 303);
+
+    let reader = IvfReader::new(&ivf).unwrap();
+    assert_eq!(
+        *reader.header(),
+        IvfHeader {
+            frame_count: 3,
+            ..IvfHeader::vp8(640, 480)
+        }
+    );
+    let frames: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(
+        frames,
+        [
+            IvfFrame {
+                timestamp: 0,
+                data: &[1, 2, 3]
+            },
+            IvfFrame {
+                timestamp: 3000,
+                data: &[]
+            },
+            IvfFrame {
+                timestamp: 6000,
+                data: &[4; 300]
+            },
+        ]
+    );
+
+    //  a frame cut short ends the file
+    let mut reader = IvfReader::new(&ivf[..ivf.len() - 1]).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert!(matches!(reader.next(), Some(Err(Error::TruncatedIvfFile))));
+    assert!(reader.next().is_none());
+    assert!(matches!(
+        IvfReader::new(b"RIFF"),
+        Err(Error::TruncatedIvfFile)
+    ));
+    assert!(matches!(
+        IvfReader::new(&[b"RIFF".as_slice(), &[0; 28]].concat()),
+        Err(Error::BadIvfSignature(signature)) if &signature == b"RIFF"
+    ));
+    let mut short_header = ivf.clone();
+    short_header[6] = 12;
+    assert!(matches!(
+        IvfReader::new(&short_header),
+        Err(Error::InvalidIvfHeaderSize(12))
+    ));
+}
@@ -1,97 +1,1410 @@
-use std::{io::Write, sync::mpsc::sync_channel};
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::mpsc::{sync_channel, RecvTimeoutError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use rtp::{codecs::vp8::Vp8Packet, packetizer::Depacketizer};
 use serde::Serialize;
 use webrtc_util::Unmarshal;
 
+mod assembler;
+mod capture;
+mod descriptor;
+mod error;
+mod extensions;
+mod feedback;
+mod ivf;
+mod picture_id;
+mod reorder;
+mod rtcp;
+mod rtpdump;
+mod rtx;
+mod srtp;
+mod temporal;
 pub mod vp8;
+mod webm;
+
+pub use assembler::{AssembledFrame, Vp8FrameAssembler};
+pub use capture::{udp_datagrams, RtpFilter, UdpDatagram};
+pub use descriptor::PayloadDescriptor;
+pub use error::Error;
+pub use extensions::{
+    AbsCaptureTime, AbsSendTime, HeaderExtensionKind, HeaderExtensionRegistry, HeaderExtensions,
+    PlayoutDelay, VideoContentType, VideoOrientation, VideoTiming,
+};
+pub use feedback::{FeedbackTracker, FrameFeedback, NackRecovery};
+pub use ivf::{IvfFrame, IvfHeader, IvfReader, IvfWriter};
+pub use picture_id::{PictureIdTracker, UnrolledPictureId};
+pub use reorder::{ReorderBuffer, ReorderOptions, ReorderStats, SequenceUnroller};
+pub use rtcp::{is_rtcp, FirEntry, PacketStatus, ReceptionReport, RtcpPacket, SdesChunk, SdesItem};
+pub use rtpdump::{RtpDumpHeader, RtpDumpPacket, RtpDumpReader, RtpDumpWriter};
+pub use rtx::RtxRegistry;
+pub use srtp::{SrtpContext, SrtpProfile, SrtpSession};
+pub use temporal::{LayerViolation, TemporalLayerChecker};
+pub use webm::{WebmBlock, WebmFile, WebmTrack};
 
 /// Structure of a video log line (json)
 #[derive(Serialize)]
 pub struct FrameLogLine {
+    pub ssrc: u32,
+    pub payload_type: u8,
     pub rtp_sequence_number: u16,
     pub pts: u32,
-    pub picture_id: Option<u16>,
+    //  picture_id and the other payload descriptor fields of the frame's
+    //  first packet
+    #[serde(flatten)]
+    pub descriptor: PayloadDescriptor,
+    pub unrolled_picture_id: Option<UnrolledPictureId>,
+    //  picture id reset on an inter frame, e.g. by an SFU switching streams
+    pub unexpected_picture_id_reset: bool,
+    //  depacketized bytes and rtp packets the frame was assembled from
+    pub frame_size: usize,
+    pub packet_count: usize,
+    //  packets lost within the frame, complete when none were and both its
+    //  first packet and marker arrived
+    pub missing_packets: usize,
+    pub complete: bool,
+    //  complete and every frame in its reference chain back to the last key
+    //  frame was complete too
+    pub decodable: bool,
     //  only set on keyframes, could remember last frame's resolution but then dropped frames wouldn't be accounted for
     pub resolution: Option<(u32, u32)>,
     pub show_frame: bool,
     pub keyframe: bool,
+    pub modify_last_frame: bool,
+    pub modify_golden_frame: bool,
+    pub modify_altref_frame: bool,
+    //  number of entropy probabilities this frame updates
+    pub token_prob_updates: usize,
+    pub mv_prob_updates: usize,
+    //  frames held by the reference buffers this frame predicts from
+    pub references: vp8::ReferenceSources,
+    //  breaks of the temporal layer structure caused by this frame
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub layer_violations: Vec<LayerViolation>,
+    //  macroblock counts by prediction mode, only when
+    //  `LoggerOptions::macroblock_modes` is set and the first partition could
+    //  be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modes: Option<vp8::ModeStats>,
+    //  registered header extensions, the first packet carrying each one
+    //  taking precedence
+    pub header_extensions: HeaderExtensions,
+    //  key frame requests and NACKs this frame answers
+    #[serde(flatten)]
+    pub feedback: FrameFeedback,
+    //  packet reordering counters of the stream so far
+    pub reorder: ReorderStats,
+    //  seconds since the unix epoch the packet completing the frame was
+    //  captured at, only when logging a capture
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_time: Option<f64>,
+}
+
+/// Structure of an RTCP log line (json), one per packet of a compound packet
+#[derive(Serialize)]
+pub struct RtcpLogLine {
+    pub rtcp: RtcpPacket,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_time: Option<f64>,
+}
+
+/// VP8 fields of `FrameLogLine`, logged for frames read from IVF and WebM
+/// files
+#[derive(Serialize)]
+pub struct Vp8FrameFields {
+    pub frame_size: usize,
+    //  every frame in its reference chain back to the last key frame parsed
+    pub decodable: bool,
+    pub resolution: Option<(u32, u32)>,
+    pub show_frame: bool,
+    pub keyframe: bool,
+    pub modify_last_frame: bool,
     pub modify_golden_frame: bool,
     pub modify_altref_frame: bool,
+    pub token_prob_updates: usize,
+    pub mv_prob_updates: usize,
+    pub references: vp8::ReferenceSources,
+}
+
+/// Structure of a log line (json) for a frame read from an IVF file
+#[derive(Serialize)]
+pub struct IvfFrameLogLine {
+    pub frame_index: usize,
+    //  in the file's timebase
+    pub pts: u64,
+    #[serde(flatten)]
+    pub vp8: Vp8FrameFields,
+}
+
+/// Structure of a log line (json) for a frame read from a WebM file
+#[derive(Serialize)]
+pub struct WebmFrameLogLine {
+    pub track: u64,
+    pub timestamp_ms: f64,
+    //  keyframe flag of the block, which should agree with the bitstream
+    pub container_keyframe: bool,
+    pub keyframe_mismatch: bool,
+    #[serde(flatten)]
+    pub vp8: Vp8FrameFields,
 }
 
-/// Handles parsing RTP packets down through VP8 compressed frame header
+/// Handles parsing assembled RTP frames down through VP8 compressed frame header
 pub struct RtpVp8FrameInfo {
     rtp_header: rtp::header::Header,
-    vp8_rtp_header: Vp8Packet,
+    descriptor: PayloadDescriptor,
     vp8_frame: vp8::FrameInfo,
+    frame_size: usize,
+    packet_count: usize,
+    missing_packets: usize,
+    complete: bool,
 }
 
 impl RtpVp8FrameInfo {
-    pub fn parse(mut pkt: &[u8]) -> anyhow::Result<Option<Self>> {
-        let rtp_packet = rtp::packet::Packet::unmarshal(&mut pkt)?;
-
-        let mut vp8_pkt = Vp8Packet::default();
-        let vp8_frame = vp8_pkt.depacketize(&bytes::Bytes::from(rtp_packet.payload.to_vec()))?;
-
-        if vp8_pkt.s == 1 && vp8_pkt.pid == 0 {
-            let frame_info = vp8::FrameInfo::parse(&vp8_frame)?;
-            Ok(Some(Self {
-                vp8_rtp_header: vp8_pkt,
-                rtp_header: rtp_packet.header,
-                vp8_frame: frame_info,
-            }))
-        } else {
-            return Ok(None);
+    /// Parses the frame started by a single RTP packet, returning `None` when
+    /// the packet doesn't start a frame. Only the start of the frame's first
+    /// partition has to be in the packet.
+    #[deprecated(note = "assemble frames with `Vp8FrameAssembler` and use `from_frame`")]
+    pub fn parse(mut pkt: &[u8]) -> Result<Option<Self>, Error> {
+        let packet = rtp::packet::Packet::unmarshal(&mut pkt).map_err(Error::RtpUnmarshal)?;
+        let mut assembler = Vp8FrameAssembler::default();
+        let mut frames = assembler.push(packet)?;
+        frames.extend(assembler.flush());
+        match frames.pop() {
+            Some(frame) => Self::from_frame(frame),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `None` for frames whose first packet was lost, as their VP8
+    /// header is missing
+    pub fn from_frame(frame: AssembledFrame) -> Result<Option<Self>, Error> {
+        let descriptor = match frame.descriptor {
+            Some(descriptor) => descriptor,
+            None => return Ok(None),
+        };
+        let vp8_frame = vp8::FrameInfo::parse(&frame.data)?;
+
+        Ok(Some(Self {
+            frame_size: frame.data.len(),
+            packet_count: frame.packet_count,
+            missing_packets: frame.missing_packets,
+            complete: frame.complete,
+            rtp_header: frame.rtp_header,
+            descriptor,
+            vp8_frame,
+        }))
+    }
+
+    pub fn vp8_frame(&self) -> &vp8::FrameInfo {
+        &self.vp8_frame
+    }
+
+    pub fn picture_id(&self) -> Option<u16> {
+        self.descriptor.picture_id
+    }
+
+    pub fn descriptor(&self) -> &PayloadDescriptor {
+        &self.descriptor
+    }
+
+    /// Identifies this frame in the reference buffers it writes
+    pub fn frame_source(&self) -> vp8::FrameSource {
+        vp8::FrameSource {
+            pts: self.rtp_header.timestamp,
+            picture_id: self.picture_id(),
         }
     }
 
-    pub fn to_log_line(&self) -> FrameLogLine {
+    pub fn complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn to_log_line(
+        &self,
+        references: vp8::ReferenceSources,
+        decodable: bool,
+        unrolled_picture_id: Option<UnrolledPictureId>,
+        layer_violations: Vec<LayerViolation>,
+        header_extensions: HeaderExtensions,
+        reorder: ReorderStats,
+    ) -> FrameLogLine {
+        let keyframe = self.vp8_frame.tag.frame_type.is_key_frame();
         FrameLogLine {
+            ssrc: self.rtp_header.ssrc,
+            payload_type: self.rtp_header.payload_type,
             rtp_sequence_number: self.rtp_header.sequence_number,
             pts: self.rtp_header.timestamp,
-            picture_id: if self.vp8_rtp_header.i == 1 {
-                Some(self.vp8_rtp_header.picture_id)
-            } else {
-                None
-            },
+            descriptor: self.descriptor,
+            unrolled_picture_id,
+            unexpected_picture_id_reset: unrolled_picture_id.is_some_and(|id| id.reset)
+                && !keyframe,
+            frame_size: self.frame_size,
+            packet_count: self.packet_count,
+            missing_packets: self.missing_packets,
+            complete: self.complete,
+            decodable,
             resolution: self.vp8_frame.tag.frame_type.resolution(),
             show_frame: self.vp8_frame.tag.show_frame,
-            keyframe: self.vp8_frame.tag.frame_type.is_key_frame(),
-            modify_golden_frame: self.vp8_frame.header.frame_buffer_update.golden,
-            modify_altref_frame: self.vp8_frame.header.frame_buffer_update.altref,
+            keyframe,
+            modify_last_frame: self.vp8_frame.header.frame_buffer_update.last,
+            modify_golden_frame: self.vp8_frame.header.frame_buffer_update.modifies_golden(),
+            modify_altref_frame: self.vp8_frame.header.frame_buffer_update.modifies_altref(),
+            token_prob_updates: self.vp8_frame.header.token_prob_updates.count(),
+            mv_prob_updates: self
+                .vp8_frame
+                .header
+                .inter_probs
+                .as_ref()
+                .map(|probs| probs.mv_prob_updates.count())
+                .unwrap_or(0),
+            references,
+            layer_violations,
+            header_extensions,
+            feedback: FrameFeedback::default(),
+            reorder,
+            modes: None,
+            capture_time: None,
         }
     }
 }
 
-/// Spawns a thread that listens to the returned Sender, writing logs to the provided `w`.
+/// Options for `spawn_rtp_logger_with_options`
+#[derive(Debug, Clone, Default)]
+pub struct LoggerOptions {
+    pub reorder: ReorderOptions,
+    /// Extensions negotiated for the streams, decoded onto the log lines
+    pub header_extensions: HeaderExtensionRegistry,
+    /// Retransmission streams, merged into the streams they repair
+    pub rtx: RtxRegistry,
+    /// Keys of SRTP streams, unprotected before they're parsed
+    pub srtp: SrtpSession,
+    /// Decode each frame's macroblock headers, logging their prediction modes
+    /// and checking layer references against them
+    pub macroblock_modes: bool,
+}
+
+/// Complete frames of a stream written to an IVF file, from its first key
+/// frame on, which gives the file's resolution
+enum IvfRecording {
+    Waiting(Box<dyn Write + Send>),
+    Writing {
+        writer: IvfWriter<Box<dyn Write + Send>>,
+        /// RTP timestamp of the last frame written and the IVF timestamp it
+        /// was unrolled to
+        last: (u32, u64),
+    },
+}
+
+impl IvfRecording {
+    fn record(self, frame: &AssembledFrame) -> std::io::Result<Self> {
+        let rtp_timestamp = frame.rtp_header.timestamp;
+        let (mut writer, timestamp) = match self {
+            IvfRecording::Waiting(w) => {
+                let dimensions = vp8::FrameTag::parse(&frame.data)
+                    .ok()
+                    .and_then(|(_, tag)| tag.frame_type.dimensions());
+                match dimensions {
+                    Some((width, height)) => (IvfWriter::new(w, IvfHeader::vp8(width, height))?, 0),
+                    None => return Ok(IvfRecording::Waiting(w)),
+                }
+            }
+            IvfRecording::Writing { writer, last } => {
+                let elapsed = rtp_timestamp.wrapping_sub(last.0) as i32;
+                (writer, last.1.saturating_add_signed(elapsed as i64))
+            }
+        };
+        writer.write_frame(timestamp, &frame.data)?;
+        Ok(IvfRecording::Writing {
+            writer,
+            last: (rtp_timestamp, timestamp),
+        })
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            IvfRecording::Waiting(mut w) => w.flush(),
+            IvfRecording::Writing { writer, .. } => writer.into_inner().flush(),
+        }
+    }
+}
+
+/// Turns one SSRC's packets into log lines
+struct StreamLogger {
+    reorder: ReorderBuffer,
+    assembler: Vp8FrameAssembler,
+    stream_state: vp8::Vp8StreamState,
+    reference_tracker: vp8::ReferenceTracker,
+    layer_checker: TemporalLayerChecker,
+    picture_ids: PictureIdTracker,
+    header_extensions: HeaderExtensionRegistry,
+    feedback: FeedbackTracker,
+    ivf: Option<IvfRecording>,
+    macroblock_modes: bool,
+}
+
+impl StreamLogger {
+    fn new(options: &LoggerOptions) -> Self {
+        Self {
+            reorder: ReorderBuffer::new(options.reorder),
+            assembler: Vp8FrameAssembler::default(),
+            stream_state: vp8::Vp8StreamState::default(),
+            reference_tracker: vp8::ReferenceTracker::default(),
+            layer_checker: TemporalLayerChecker::default(),
+            picture_ids: PictureIdTracker::default(),
+            header_extensions: options.header_extensions,
+            feedback: FeedbackTracker::default(),
+            ivf: None,
+            macroblock_modes: options.macroblock_modes,
+        }
+    }
+
+    /// Log lines of the frames completed by packets released from the
+    /// reorder buffer, with the arrival of the packet completing each
+    fn log_packets(
+        &mut self,
+        packets: Vec<(Instant, rtp::packet::Packet)>,
+    ) -> Vec<(Instant, FrameLogLine)> {
+        let mut lines = vec![];
+        for (arrival, packet) in packets {
+            match self.assembler.push(packet) {
+                Ok(frames) => lines.extend(
+                    frames
+                        .into_iter()
+                        .filter_map(|f| Some((arrival, self.log_frame(f, arrival)?))),
+                ),
+                Err(e) => eprintln!("error parsing rtp packet: {:?}", e),
+            }
+        }
+        lines
+    }
+
+    fn log_frame(&mut self, mut frame: AssembledFrame, now: Instant) -> Option<FrameLogLine> {
+        if frame.lost_before > 0 {
+            self.lose_frame();
+        }
+        //  macroblock modes tell which buffers the frame predicts from, the
+        //  header alone keeps the stream state going when they can't be read
+        let modes = match frame.descriptor {
+            Some(_) if self.macroblock_modes => {
+                match self.stream_state.push_with_macroblocks(&frame.data) {
+                    Ok(stream_frame) => stream_frame
+                        .macroblocks
+                        .map(|macroblocks| vp8::ModeStats::from_macroblocks(&macroblocks)),
+                    Err(_) => {
+                        let _ = self.stream_state.push(&frame.data);
+                        None
+                    }
+                }
+            }
+            Some(_) => {
+                let _ = self.stream_state.push(&frame.data);
+                None
+            }
+            None => None,
+        };
+        //  gaps in incomplete frames would only confuse decoders
+        if frame.complete && frame.descriptor.is_some() {
+            if let Some(recording) = self.ivf.take() {
+                match recording.record(&frame) {
+                    Ok(recording) => self.ivf = Some(recording),
+                    Err(e) => eprintln!("error writing ivf file: {:?}", e),
+                }
+            }
+        }
+        let packet_headers = std::mem::take(&mut frame.packet_headers);
+        let mut header_extensions = HeaderExtensions::default();
+        for header in &packet_headers {
+            header_extensions.merge(&self.header_extensions.decode(header));
+        }
+
+        match RtpVp8FrameInfo::from_frame(frame) {
+            Ok(Some(info)) => {
+                let header = &info.vp8_frame.header;
+                let decodable =
+                    info.complete && self.reference_tracker.decodable(header, modes.as_ref());
+                let references = self.reference_tracker.update(
+                    info.frame_source(),
+                    header,
+                    modes.as_ref(),
+                    info.complete,
+                );
+                let layer_violations =
+                    self.layer_checker
+                        .check(&info.descriptor, header, modes.as_ref());
+                let unrolled_picture_id = self.picture_ids.update(&info.descriptor);
+                let key_frame = header.is_key_frame();
+                Some(FrameLogLine {
+                    feedback: self.feedback.frame(&packet_headers, key_frame, now),
+                    modes,
+                    ..info.to_log_line(
+                        references,
+                        decodable,
+                        unrolled_picture_id,
+                        layer_violations,
+                        header_extensions,
+                        self.reorder.stats(),
+                    )
+                })
+            }
+            //  without a header the frame may have written any buffer
+            Ok(Option::None) => {
+                self.lose_frame();
+                None
+            }
+            Err(e) => {
+                eprintln!("error parsing vp8 frame: {:?}", e);
+                self.lose_frame();
+                None
+            }
+        }
+    }
+
+    fn lose_frame(&mut self) {
+        self.reference_tracker.lose_frame();
+        self.layer_checker.lose_frame();
+    }
+
+    /// Log lines of the frames still buffered once the stream ends
+    fn finish(&mut self, now: Instant) -> Vec<(Instant, FrameLogLine)> {
+        let packets = self.reorder.flush();
+        let mut lines = self.log_packets(packets);
+        if let Some(frame) = self.assembler.flush() {
+            lines.extend(self.log_frame(frame, now).map(|line| (now, line)));
+        }
+        if let Some(Err(e)) = self.ivf.take().map(IvfRecording::finish) {
+            eprintln!("error writing ivf file: {:?}", e);
+        }
+        lines
+    }
+}
+
+/// Demultiplexes packets by SSRC, writing each stream's log lines to `w`
+struct RtpLogger<W> {
+    w: W,
+    options: LoggerOptions,
+    streams: HashMap<u32, StreamLogger>,
+    /// An instant and the capture timestamp it stands for when logging a
+    /// capture
+    capture_clock: Option<(Instant, Duration)>,
+    /// IVF files to record streams to, handed over when they start
+    ivf_outputs: HashMap<u32, Box<dyn Write + Send>>,
+    /// Records the datagrams pushed, with the instant the recording started
+    rtpdump: Option<(Instant, RtpDumpWriter<Box<dyn Write + Send>>)>,
+}
+
+impl<W: Write> RtpLogger<W> {
+    fn new(w: W, options: LoggerOptions) -> Self {
+        Self {
+            w,
+            options,
+            streams: HashMap::new(),
+            capture_clock: None,
+            ivf_outputs: HashMap::new(),
+            rtpdump: None,
+        }
+    }
+
+    fn capture_time(&self, now: Instant) -> Option<f64> {
+        self.capture_clock.map(|(instant, timestamp)| {
+            (timestamp + now.saturating_duration_since(instant)).as_secs_f64()
+        })
+    }
+
+    /// Releases the packets every stream held past `max_hold`, then logs a
+    /// datagram, so quiet streams aren't held up by busy ones
+    fn receive(&mut self, data: &[u8], now: Instant) -> std::io::Result<()> {
+        self.poll(now)?;
+        self.push_datagram(data, now)
+    }
+
+    /// Logs an RTP or RTCP packet, told apart like when multiplexed
+    fn push_datagram(&mut self, data: &[u8], now: Instant) -> std::io::Result<()> {
+        //  recorded as received, still protected when srtp
+        if let Some((start, rtpdump)) = &mut self.rtpdump {
+            let offset = now.saturating_duration_since(*start);
+            if let Err(e) = rtpdump.write_packet(offset, data) {
+                eprintln!("error writing rtpdump file: {:?}", e);
+                self.rtpdump = None;
+            }
+        }
+        let unprotected = match self.options.srtp.unprotect(data) {
+            Ok(unprotected) => unprotected,
+            Err(e) => {
+                eprintln!("error unprotecting srtp packet: {:?}", e);
+                return Ok(());
+            }
+        };
+        let data = unprotected.as_deref().unwrap_or(data);
+        if is_rtcp(data) {
+            return self.push_rtcp(data, now);
+        }
+        match rtp::packet::Packet::unmarshal(&mut &data[..]) {
+            Ok(packet) => self.push(packet, now),
+            Err(e) => {
+                eprintln!("error parsing rtp packet: {:?}", Error::RtpUnmarshal(e));
+                Ok(())
+            }
+        }
+    }
+
+    fn stream(&mut self, ssrc: u32) -> &mut StreamLogger {
+        let options = &self.options;
+        let ivf_outputs = &mut self.ivf_outputs;
+        self.streams.entry(ssrc).or_insert_with(|| StreamLogger {
+            ivf: ivf_outputs.remove(&ssrc).map(IvfRecording::Waiting),
+            ..StreamLogger::new(options)
+        })
+    }
+
+    fn push(&mut self, packet: rtp::packet::Packet, now: Instant) -> std::io::Result<()> {
+        //  retransmissions rejoin the stream they repair
+        let (packet, retransmitted) = if self.options.rtx.is_rtx(&packet) {
+            match self.options.rtx.unwrap(packet) {
+                Some(packet) => (packet, true),
+                None => return Ok(()),
+            }
+        } else {
+            (packet, false)
+        };
+        let stream = self.stream(packet.header.ssrc);
+        stream
+            .feedback
+            .receive(packet.header.sequence_number, retransmitted, now);
+        let packets = stream.reorder.push(packet, now);
+        let lines = stream.log_packets(packets);
+        self.write_frames(lines)
+    }
+
+    /// Logs a compound RTCP packet, keeping the feedback it carries to
+    /// correlate with the frames answering it
+    fn push_rtcp(&mut self, data: &[u8], now: Instant) -> std::io::Result<()> {
+        let packets = match RtcpPacket::parse_compound(data) {
+            Ok(packets) => packets,
+            Err(e) => {
+                eprintln!("error parsing rtcp packet: {:?}", e);
+                return Ok(());
+            }
+        };
+        for packet in &packets {
+            match packet {
+                RtcpPacket::Nack {
+                    media_ssrc, lost, ..
+                } => self.stream(*media_ssrc).feedback.nack(lost, now),
+                RtcpPacket::PictureLossIndication { media_ssrc, .. } => {
+                    self.stream(*media_ssrc).feedback.request_key_frame(now)
+                }
+                RtcpPacket::FullIntraRequest { entries, .. } => {
+                    for entry in entries {
+                        self.stream(entry.ssrc).feedback.request_key_frame(now);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let capture_time = self.capture_time(now);
+        let lines: Vec<_> = packets
+            .into_iter()
+            .map(|rtcp| RtcpLogLine { rtcp, capture_time })
+            .collect();
+        self.write(lines)
+    }
+
+    /// Releases packets held for lost ones in every stream
+    fn poll(&mut self, now: Instant) -> std::io::Result<()> {
+        let mut lines = vec![];
+        for stream in self.streams.values_mut() {
+            let packets = stream.reorder.poll(now);
+            lines.extend(stream.log_packets(packets));
+        }
+        self.write_frames(lines)
+    }
+
+    /// Flushes the rtpdump recording, which is otherwise only flushed once
+    /// every sender is dropped
+    fn flush_rtpdump(&mut self) {
+        if let Some((_, rtpdump)) = &mut self.rtpdump {
+            if let Err(e) = rtpdump.flush() {
+                eprintln!("error writing rtpdump file: {:?}", e);
+                self.rtpdump = None;
+            }
+        }
+    }
+
+    fn finish(&mut self, now: Instant) -> std::io::Result<()> {
+        let lines = self
+            .streams
+            .values_mut()
+            .flat_map(|stream| stream.finish(now))
+            .collect();
+        if let Some((_, rtpdump)) = self.rtpdump.take() {
+            if let Err(e) = rtpdump.into_inner().flush() {
+                eprintln!("error writing rtpdump file: {:?}", e);
+            }
+        }
+        self.write_frames(lines)
+    }
+
+    fn write_frames(&mut self, lines: Vec<(Instant, FrameLogLine)>) -> std::io::Result<()> {
+        let lines = lines
+            .into_iter()
+            .map(|(arrival, line)| FrameLogLine {
+                capture_time: self.capture_time(arrival),
+                ..line
+            })
+            .collect();
+        self.write(lines)
+    }
+
+    fn write<L: Serialize>(&mut self, lines: Vec<L>) -> std::io::Result<()> {
+        for line in lines {
+            let mut json = serde_json::to_vec(&line)?;
+            json.push(b'\n');
+            self.w.write_all(&json)?;
+        }
+        Ok(())
+    }
+}
+
+/// Spawns a thread that listens to the returned Sender for RTP and RTCP packets, writing logs to the provided `w`.
 pub fn spawn_rtp_logger<W: Write + Send + Sync + 'static>(
-    mut w: W,
+    w: W,
+) -> std::sync::mpsc::SyncSender<Vec<u8>> {
+    spawn_rtp_logger_with_options(w, LoggerOptions::default())
+}
+
+/// Like `spawn_rtp_logger`, with packet reordering, header extensions and RTX
+/// streams configured by `options`
+pub fn spawn_rtp_logger_with_options<W: Write + Send + Sync + 'static>(
+    w: W,
+    options: LoggerOptions,
+) -> std::sync::mpsc::SyncSender<Vec<u8>> {
+    spawn_logger(w, options, None)
+}
+
+/// How often a buffered rtpdump recording is flushed, so little is lost when
+/// the process is killed
+const RTPDUMP_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Like `spawn_rtp_logger_with_options`, also recording the packets received
+/// to an rtpdump file with their arrival times, to be replayed by
+/// `log_rtpdump`. Only packets the logger receives are recorded, not ones
+/// dropped because its channel was full.
+pub fn spawn_rtp_logger_recording<W: Write + Send + Sync + 'static>(
+    w: W,
+    options: LoggerOptions,
+    rtpdump: Box<dyn Write + Send>,
+) -> std::sync::mpsc::SyncSender<Vec<u8>> {
+    spawn_logger(w, options, Some(rtpdump))
+}
+
+fn spawn_logger<W: Write + Send + Sync + 'static>(
+    w: W,
+    options: LoggerOptions,
+    rtpdump: Option<Box<dyn Write + Send>>,
 ) -> std::sync::mpsc::SyncSender<Vec<u8>> {
     let (tx, rx) = sync_channel::<Vec<u8>>(128);
 
     std::thread::spawn(move || {
-        while let Ok(rtp_pkt) = rx.recv() {
-            match RtpVp8FrameInfo::parse(&rtp_pkt) {
-                Ok(Some(info)) => {
-                    if let Ok(mut json) = serde_json::to_vec(&info.to_log_line()) {
-                        json.push(b'\n');
-                        match w.write_all(&json) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                eprintln!("error writing to log {:?}", e);
-                                return;
-                            }
-                        }
-                    }
-                }
-                Ok(Option::None) => {}
-                Err(e) => {
-                    eprintln!("error parsing rtp packet: {:?}", e);
-                }
+        let max_hold = options.reorder.max_hold;
+        let mut logger = RtpLogger::new(w, options);
+        if let Some(rtpdump) = rtpdump {
+            let header = RtpDumpHeader {
+                start: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+                //  packets come from the sfu rather than the network
+                source: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            };
+            match RtpDumpWriter::new(rtpdump, header) {
+                Ok(rtpdump) => logger.rtpdump = Some((Instant::now(), rtpdump)),
+                Err(e) => eprintln!("error writing rtpdump file: {:?}", e),
             }
         }
+        let mut flushed = Instant::now();
+        loop {
+            //  wake up after max_hold to release packets held for lost ones
+            let result = match rx.recv_timeout(max_hold) {
+                //  rtcp shares the channel
+                Ok(pkt) => logger.receive(&pkt, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => logger.poll(Instant::now()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if let Err(e) = result {
+                eprintln!("error writing to log {:?}", e);
+                return;
+            }
+            if flushed.elapsed() >= RTPDUMP_FLUSH_INTERVAL {
+                logger.flush_rtpdump();
+                flushed = Instant::now();
+            }
+        }
+        if let Err(e) = logger.finish(Instant::now()) {
+            eprintln!("error writing to log {:?}", e);
+        }
     });
 
     tx
 }
+
+/// Logs the RTP and RTCP packets of a pcap or pcapng capture that pass
+/// `filter`, as if they arrived at their capture timestamps
+pub fn log_capture<W: Write>(
+    capture: &[u8],
+    filter: &RtpFilter,
+    options: LoggerOptions,
+    w: W,
+) -> Result<(), Error> {
+    log_capture_with_ivf(capture, filter, options, w, HashMap::new())
+}
+
+/// Like `log_capture`, also writing the complete frames of the SSRCs in
+/// `ivf_outputs` to IVF files. Their frame counts are left at zero, as the
+/// outputs needn't be seekable.
+pub fn log_capture_with_ivf<W: Write>(
+    capture: &[u8],
+    filter: &RtpFilter,
+    options: LoggerOptions,
+    w: W,
+    ivf_outputs: HashMap<u32, Box<dyn Write + Send>>,
+) -> Result<(), Error> {
+    let datagrams: Vec<_> = udp_datagrams(capture)?
+        .into_iter()
+        .filter(|datagram| filter.matches(datagram))
+        .collect();
+    let mut logger = RtpLogger::new(w, options);
+    logger.ivf_outputs = ivf_outputs;
+    replay(
+        logger,
+        datagrams
+            .iter()
+            .map(|datagram| (datagram.timestamp, &datagram.payload[..])),
+    )
+}
+
+/// Logs the RTP and RTCP packets of an rtpdump file, as if they arrived at
+/// their recorded times
+pub fn log_rtpdump<W: Write>(rtpdump: &[u8], options: LoggerOptions, w: W) -> Result<(), Error> {
+    log_rtpdump_with_ivf(rtpdump, &RtpFilter::default(), options, w, HashMap::new())
+}
+
+/// Like `log_rtpdump`, only logging the packets passing `filter` and
+/// writing the complete frames of the SSRCs in `ivf_outputs` to IVF files.
+/// The filter's ports are ignored, as the file doesn't record them.
+pub fn log_rtpdump_with_ivf<W: Write>(
+    rtpdump: &[u8],
+    filter: &RtpFilter,
+    options: LoggerOptions,
+    w: W,
+    ivf_outputs: HashMap<u32, Box<dyn Write + Send>>,
+) -> Result<(), Error> {
+    let reader = RtpDumpReader::new(rtpdump)?;
+    let start = reader.header().start;
+    let packets = reader
+        .map(|packet| packet.map(|packet| (start + packet.offset, packet.data)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut logger = RtpLogger::new(w, options);
+    logger.ivf_outputs = ivf_outputs;
+    replay(
+        logger,
+        packets
+            .into_iter()
+            .filter(|(_, data)| filter.matches_payload(data)),
+    )
+}
+
+/// Pushes datagrams timestamped since the unix epoch to `logger`, as if they
+/// arrived at those times
+fn replay<'a, W: Write>(
+    mut logger: RtpLogger<W>,
+    datagrams: impl IntoIterator<Item = (Duration, &'a [u8])>,
+) -> Result<(), Error> {
+    let mut datagrams = datagrams.into_iter().peekable();
+    let first = match datagrams.peek() {
+        Some((timestamp, _)) => *timestamp,
+        None => return Ok(()),
+    };
+
+    let start = Instant::now();
+    logger.capture_clock = Some((start, first));
+    let mut now = start;
+    for (timestamp, payload) in datagrams {
+        //  capture timestamps can step backwards, instants can't
+        now = now.max(start + timestamp.saturating_sub(first));
+        logger.receive(payload, now).map_err(Error::Io)?;
+    }
+    logger.finish(now).map_err(Error::Io)
+}
+
+/// Tracks the reference buffers of frames read from a file in decode order
+#[derive(Default)]
+struct FileStreamLogger {
+    reference_tracker: vp8::ReferenceTracker,
+}
+
+impl FileStreamLogger {
+    /// `pts` tells frames in the reference buffers apart
+    fn log_frame(&mut self, frame: &[u8], pts: u32) -> Option<Vp8FrameFields> {
+        let vp8_frame = match vp8::FrameInfo::parse(frame) {
+            Ok(vp8_frame) => vp8_frame,
+            Err(e) => {
+                eprintln!("error parsing vp8 frame: {:?}", e);
+                self.reference_tracker.lose_frame();
+                return None;
+            }
+        };
+        let header = &vp8_frame.header;
+        let decodable = self.reference_tracker.decodable(header, None);
+        let source = vp8::FrameSource {
+            pts,
+            picture_id: None,
+        };
+        Some(Vp8FrameFields {
+            frame_size: frame.len(),
+            decodable,
+            resolution: vp8_frame.tag.frame_type.resolution(),
+            show_frame: vp8_frame.tag.show_frame,
+            keyframe: header.is_key_frame(),
+            modify_last_frame: header.frame_buffer_update.last,
+            modify_golden_frame: header.frame_buffer_update.modifies_golden(),
+            modify_altref_frame: header.frame_buffer_update.modifies_altref(),
+            token_prob_updates: header.token_prob_updates.count(),
+            mv_prob_updates: header
+                .inter_probs
+                .as_ref()
+                .map(|probs| probs.mv_prob_updates.count())
+                .unwrap_or(0),
+            references: self.reference_tracker.update(source, header, None, true),
+        })
+    }
+}
+
+fn write_line<W: Write, L: Serialize>(w: &mut W, line: &L) -> Result<(), Error> {
+    let mut json = serde_json::to_vec(line).map_err(|e| Error::Io(e.into()))?;
+    json.push(b'\n');
+    w.write_all(&json).map_err(Error::Io)
+}
+
+/// Logs the VP8 frames of an IVF file
+pub fn log_ivf<W: Write>(ivf: &[u8], mut w: W) -> Result<(), Error> {
+    let reader = IvfReader::new(ivf)?;
+    if &reader.header().fourcc != b"VP80" {
+        return Err(Error::UnsupportedIvfCodec(reader.header().fourcc));
+    }
+    let mut stream = FileStreamLogger::default();
+    for (frame_index, frame) in reader.enumerate() {
+        let frame = frame?;
+        if let Some(vp8) = stream.log_frame(frame.data, frame.timestamp as u32) {
+            let line = IvfFrameLogLine {
+                frame_index,
+                pts: frame.timestamp,
+                vp8,
+            };
+            write_line(&mut w, &line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Logs the frames of the VP8 tracks of a WebM file
+pub fn log_webm<W: Write>(webm: &[u8], mut w: W) -> Result<(), Error> {
+    let file = WebmFile::parse(webm)?;
+    let mut streams: HashMap<u64, FileStreamLogger> = file
+        .tracks
+        .iter()
+        .filter(|track| track.codec_id == "V_VP8")
+        .map(|track| (track.number, FileStreamLogger::default()))
+        .collect();
+    for block in &file.blocks {
+        let stream = match streams.get_mut(&block.track) {
+            Some(stream) => stream,
+            None => continue,
+        };
+        if let Some(vp8) = stream.log_frame(block.data, block.timestamp as u32) {
+            let line = WebmFrameLogLine {
+                track: block.track,
+                timestamp_ms: (block.timestamp as f64) * file.timestamp_scale as f64 / 1e6,
+                container_keyframe: block.keyframe,
+                keyframe_mismatch: block.keyframe != vp8.keyframe,
+                vp8,
+            };
+            write_line(&mut w, &line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Output kept by a test after handing it to the logger
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// RTP packet with payload type 96, whose `payload` starts with its VP8
+/// payload descriptor
+#[cfg(test)]
+pub(crate) fn test_packet(
+    ssrc: u32,
+    sequence_number: u16,
+    timestamp: u32,
+    marker: bool,
+    payload: &[u8],
+) -> rtp::packet::Packet {
+    rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            ssrc,
+            payload_type: 96,
+            sequence_number,
+            timestamp,
+            marker,
+            ..Default::default()
+        },
+        payload: payload.to_vec().into(),
+    }
+}
+
+/// Packet holding all of `frame`, after a single byte payload descriptor
+/// with S set
+#[cfg(test)]
+fn frame_packet(
+    ssrc: u32,
+    sequence_number: u16,
+    timestamp: u32,
+    frame: &[u8],
+) -> rtp::packet::Packet {
+    test_packet(
+        ssrc,
+        sequence_number,
+        timestamp,
+        true,
+        &[&[0x10], frame].concat(),
+    )
+}
+
+/// Parses the json lines of a log
+#[cfg(test)]
+fn log_lines(log: &[u8]) -> Vec<serde_json::Value> {
+    log.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn log_streams_by_ssrc() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let mut logger = RtpLogger::new(vec![], LoggerOptions::default());
+    let now = Instant::now();
+
+    //  interleaved streams with unrelated sequence numbers, which would look
+    //  like loss and reordering within a single stream
+    for (packet, arrival) in [
+        (frame_packet(1, 100, 0, &key_frame), 0),
+        (frame_packet(2, 5000, 0, &key_frame), 0),
+        (frame_packet(1, 101, 3000, &inter_frame), 100),
+        (frame_packet(2, 5001, 3000, &inter_frame), 100),
+    ] {
+        logger
+            .push(packet, now + Duration::from_millis(arrival))
+            .unwrap();
+    }
+    logger.finish(now).unwrap();
+
+    let lines = log_lines(&logger.w);
+    let ssrcs: Vec<_> = lines.iter().map(|line| line["ssrc"].as_u64()).collect();
+    //  each stream's first packets are held for max_hold
+    assert_eq!(ssrcs, [Some(1), Some(1), Some(2), Some(2)]);
+    for line in &lines {
+        assert_eq!(line["payload_type"], 96);
+        assert_eq!(line["decodable"], true);
+        assert_eq!(line["reorder"]["lost"], 0);
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn parse_single_packet() {
+    use webrtc_util::Marshal;
+
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let packet = frame_packet(1, 100, 0, &key_frame).marshal().unwrap();
+    let info = RtpVp8FrameInfo::parse(&packet).unwrap().unwrap();
+    assert!(info.vp8_frame().header.is_key_frame());
+    assert_eq!(info.frame_source().pts, 0);
+
+    //  continuation packets carry no frame header
+    let continuation = [&[0x00][..], &key_frame].concat();
+    let packet = test_packet(1, 101, 0, true, &continuation)
+        .marshal()
+        .unwrap();
+    assert!(RtpVp8FrameInfo::parse(&packet).unwrap().is_none());
+}
+
+#[test]
+fn log_macroblock_modes() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let now = Instant::now();
+    for macroblock_modes in [false, true] {
+        let options = LoggerOptions {
+            macroblock_modes,
+            ..Default::default()
+        };
+        let mut logger = RtpLogger::new(vec![], options);
+        logger
+            .push(frame_packet(1, 100, 0, &key_frame), now)
+            .unwrap();
+        logger.finish(now).unwrap();
+
+        let lines = log_lines(&logger.w);
+        if macroblock_modes {
+            let modes = &lines[0]["modes"];
+            assert!(modes["macroblocks"].as_u64().unwrap() > 0);
+            assert_eq!(modes["intra"], modes["macroblocks"]);
+        } else {
+            assert!(lines[0].get("modes").is_none());
+        }
+    }
+}
+
+#[test]
+fn correlate_feedback_with_frames() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let mut logger = RtpLogger::new(vec![], LoggerOptions::default());
+    let now = Instant::now();
+    let ms = |ms| now + Duration::from_millis(ms);
+
+    let pli = [0x81, 206, 0, 2, 0, 0, 0, 9, 0, 0, 0, 1];
+    let nack = [0x81, 205, 0, 3, 0, 0, 0, 9, 0, 0, 0, 1, 0, 101, 0, 0];
+    logger.push_rtcp(&pli, now).unwrap();
+    logger
+        .push(frame_packet(1, 100, 0, &key_frame), ms(20))
+        .unwrap();
+    logger.push_rtcp(&nack, ms(20)).unwrap();
+    logger
+        .push(frame_packet(1, 102, 6000, &inter_frame), ms(20))
+        .unwrap();
+    logger
+        .push(frame_packet(1, 101, 3000, &inter_frame), ms(50))
+        .unwrap();
+    logger.finish(ms(50)).unwrap();
+
+    let lines = log_lines(&logger.w);
+    //  the frames are held until the stream ends, delays count from their
+    //  arrival
+    assert_eq!(lines[0]["rtcp"]["type"], "picture_loss_indication");
+    assert_eq!(lines[1]["rtcp"]["lost"], serde_json::json!([101]));
+    assert_eq!(lines[2]["key_frame_request_delay_ms"], 20.0);
+    assert_eq!(
+        lines[3]["nack_recoveries"],
+        serde_json::json!([{"sequence_number": 101, "delay_ms": 30.0}])
+    );
+    assert!(lines[4].get("nack_recoveries").is_none());
+}
+
+#[test]
+fn merge_rtx_retransmissions() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let mut options = LoggerOptions::default();
+    options.rtx.register_payload_type(97, 96);
+    options.rtx.register_ssrc(2, 1);
+    let mut logger = RtpLogger::new(vec![], options);
+    let now = Instant::now();
+
+    //  101 is lost, then retransmitted on the rtx stream with its osn
+    let mut rtx = frame_packet(2, 7, 3000, &inter_frame);
+    rtx.header.payload_type = 97;
+    rtx.payload = [&[0, 101][..], &rtx.payload].concat().into();
+    for (packet, arrival) in [
+        (frame_packet(1, 100, 0, &key_frame), 0),
+        (frame_packet(1, 102, 6000, &inter_frame), 0),
+        (rtx, 40),
+    ] {
+        logger
+            .push(packet, now + Duration::from_millis(arrival))
+            .unwrap();
+    }
+    logger.finish(now).unwrap();
+
+    let lines = log_lines(&logger.w);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1]["rtp_sequence_number"], 101);
+    assert_eq!(lines[1]["ssrc"], 1);
+    assert_eq!(lines[1]["rtx_recovered_packets"], 1);
+    assert_eq!(lines[1]["rtx_delay_ms"], 40.0);
+    assert_eq!(lines[1]["decodable"], true);
+    assert_eq!(lines[2]["rtx_recovered_packets"], 0);
+    assert_eq!(lines[2]["reorder"]["lost"], 0);
+}
+
+#[test]
+fn padding_between_frames() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let mut logger = RtpLogger::new(vec![], LoggerOptions::default());
+    let now = Instant::now();
+
+    //  a padding-only packet, as sent when probing for bandwidth
+    for packet in [
+        frame_packet(1, 100, 0, &key_frame),
+        test_packet(1, 101, 0, false, &[]),
+        frame_packet(1, 102, 3000, &inter_frame),
+    ] {
+        logger.push(packet, now).unwrap();
+    }
+    logger.finish(now).unwrap();
+
+    let lines = log_lines(&logger.w);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["complete"], true);
+    assert_eq!(lines[1]["decodable"], true);
+    assert_eq!(lines[1]["reorder"]["lost"], 0);
+}
+
+#[test]
+fn release_quiet_stream() {
+    use webrtc_util::Marshal;
+
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let mut logger = RtpLogger::new(vec![], LoggerOptions::default());
+    let now = Instant::now();
+
+    //  stream 1 goes quiet after losing 101 while stream 2 keeps sending
+    let mut datagrams = vec![
+        (frame_packet(1, 100, 0, &key_frame), 0),
+        (frame_packet(1, 102, 6000, &inter_frame), 0),
+    ];
+    for i in 0..10 {
+        let frame = if i == 0 { &key_frame } else { &inter_frame };
+        datagrams.push((
+            frame_packet(2, 5000 + i, i as u32 * 3000, frame),
+            i as u64 * 30,
+        ));
+    }
+    for (packet, arrival) in datagrams {
+        logger
+            .receive(
+                &packet.marshal().unwrap(),
+                now + Duration::from_millis(arrival),
+            )
+            .unwrap();
+    }
+
+    //  released once max_hold passed, without waiting for the stream to end
+    let lines = log_lines(&logger.w);
+    let quiet: Vec<_> = lines.iter().filter(|line| line["ssrc"] == 1).collect();
+    assert_eq!(quiet.len(), 2);
+    assert_eq!(quiet[1]["reorder"]["lost"], 1);
+}
+
+#[test]
+fn log_capture_timestamps() {
+    use webrtc_util::Marshal;
+
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let packets = [
+        frame_packet(1, 100, 0, &key_frame).marshal().unwrap(),
+        frame_packet(1, 101, 3000, &inter_frame).marshal().unwrap(),
+    ];
+    let capture = capture::test_pcap(&[(1_000_000, &packets[0]), (1_033_000, &packets[1])]);
+
+    let mut log = vec![];
+    log_capture(
+        &capture,
+        &RtpFilter::default(),
+        LoggerOptions::default(),
+        &mut log,
+    )
+    .unwrap();
+    let lines = log_lines(&log);
+    let capture_times: Vec<_> = lines
+        .iter()
+        .map(|line| line["capture_time"].as_f64().unwrap())
+        .collect();
+    assert_eq!(capture_times, [1.0, 1.033]);
+}
+
+#[test]
+fn log_ivf_frames() {
+    let mut log = vec![];
+    log_ivf(&std::fs::read("test_frames.ivf").unwrap(), &mut log).unwrap();
+    let lines = log_lines(&log);
+    assert_eq!(lines.len(), 31);
+    assert_eq!(lines[0]["keyframe"], true);
+    assert_eq!(lines[0]["resolution"], serde_json::json!([474, 270]));
+    assert_eq!(lines[30]["frame_index"], 30);
+    assert_eq!(lines[30]["pts"], 30);
+    assert!(lines.iter().all(|line| line["decodable"] == true));
+}
+
+#[test]
+fn record_capture_to_ivf() {
+    use webrtc_util::Marshal;
+
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let packets = [
+        //  an inter frame before the first key frame is left out
+        frame_packet(1, 99, u32::MAX - 5999, &inter_frame),
+        frame_packet(1, 100, u32::MAX - 2999, &key_frame),
+        frame_packet(1, 101, 0, &inter_frame),
+        frame_packet(2, 500, 0, &key_frame),
+    ]
+    .map(|packet| packet.marshal().unwrap());
+    let capture = capture::test_pcap(&[
+        (1_000_000, &packets[0]),
+        (1_033_000, &packets[1]),
+        (1_066_000, &packets[2]),
+        (1_066_000, &packets[3]),
+    ]);
+
+    let output = SharedBuffer::default();
+    log_capture_with_ivf(
+        &capture,
+        &RtpFilter::default(),
+        LoggerOptions::default(),
+        vec![],
+        HashMap::from([(1, Box::new(output.clone()) as Box<dyn Write + Send>)]),
+    )
+    .unwrap();
+
+    let ivf = output.0.lock().unwrap().clone();
+    let reader = IvfReader::new(&ivf).unwrap();
+    let (width, height) = vp8::FrameTag::parse(&key_frame)
+        .unwrap()
+        .1
+        .frame_type
+        .dimensions()
+        .unwrap();
+    assert_eq!(*reader.header(), IvfHeader::vp8(width, height));
+    let frames: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(
+        frames,
+        [
+            IvfFrame {
+                timestamp: 0,
+                data: &key_frame
+            },
+            IvfFrame {
+                timestamp: 3000,
+                data: &inter_frame
+            },
+        ]
+    );
+}
+
+#[test]
+fn log_webm_keyframe_mismatch() {
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let webm = webm::test_webm(&[
+        (1, 0, true, &key_frame),
+        (2, 0, true, &[0xfc]),
+        (1, 33, false, &inter_frame),
+        //  a recorder flagging an inter frame as a keyframe
+        (1, 66, true, &inter_frame),
+    ]);
+
+    let mut log = vec![];
+    log_webm(&webm, &mut log).unwrap();
+    let lines = log_lines(&log);
+    let frames: Vec<_> = lines
+        .iter()
+        .map(|line| {
+            (
+                line["track"].as_u64().unwrap(),
+                line["timestamp_ms"].as_f64().unwrap(),
+                line["container_keyframe"].as_bool().unwrap(),
+                line["keyframe"].as_bool().unwrap(),
+                line["keyframe_mismatch"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (1, 1000.0, true, true, false),
+            (1, 1033.0, false, false, false),
+            (1, 1066.0, true, false, true),
+        ]
+    );
+}
+
+#[test]
+fn record_and_replay_rtpdump() {
+    use webrtc_util::Marshal;
+
+    let key_frame = vp8::encode_frame(true, None, None, false, None);
+    let inter_frame = vp8::encode_frame(false, None, None, false, None);
+    let header = RtpDumpHeader {
+        start: Duration::from_secs(1_600_000_000),
+        source: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+    };
+    let output = SharedBuffer::default();
+    let mut logger = RtpLogger::new(vec![], LoggerOptions::default());
+    let start = Instant::now();
+    logger.rtpdump = Some((
+        start,
+        RtpDumpWriter::new(Box::new(output.clone()) as Box<dyn Write + Send>, header).unwrap(),
+    ));
+    for (offset, packet) in [
+        (0, frame_packet(1, 100, 0, &key_frame)),
+        (33, frame_packet(1, 101, 3000, &inter_frame)),
+    ] {
+        let now = start + Duration::from_millis(offset);
+        logger
+            .push_datagram(&packet.marshal().unwrap(), now)
+            .unwrap();
+    }
+    logger.finish(start).unwrap();
+
+    let mut replayed = vec![];
+    let rtpdump = output.0.lock().unwrap().clone();
+    log_rtpdump(&rtpdump, LoggerOptions::default(), &mut replayed).unwrap();
+    let (recorded, mut replayed) = (log_lines(&logger.w), log_lines(&replayed));
+    let capture_times: Vec<_> = replayed
+        .iter_mut()
+        .map(|line| line.as_object_mut().unwrap().remove("capture_time"))
+        .collect();
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(replayed, recorded);
+    assert_eq!(
+        capture_times,
+        [
+            Some(serde_json::json!(1_600_000_000.0)),
+            Some(serde_json::json!(1_600_000_000.033))
+        ]
+    );
+
+    //  packets of other payload types are left out
+    let mut filtered = vec![];
+    let filter = RtpFilter {
+        payload_types: vec![97],
+        ..Default::default()
+    };
+    log_rtpdump_with_ivf(
+        &rtpdump,
+        &filter,
+        LoggerOptions::default(),
+        &mut filtered,
+        HashMap::new(),
+    )
+    .unwrap();
+    assert!(filtered.is_empty());
+}
@@ -0,0 +1,89 @@
+//! Unrolling of the 7 or 15-bit picture ids in VP8 payload descriptors
+
+use serde::Serialize;
+
+use crate::PayloadDescriptor;
+
+/// A frame's position in the stream according to its picture id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UnrolledPictureId {
+    /// Increases with every frame, including ones that were lost
+    pub frame_counter: u64,
+    /// Frames lost since the previous frame with a picture id
+    pub missing_frames: u64,
+    /// Picture id went backwards, repeated or changed width, which encoders
+    /// only do on key frames
+    pub reset: bool,
+}
+
+/// Unrolls picture ids into a frame counter. Frames must be passed in decode
+/// order, so a picture id going backwards is a reset rather than reordering.
+#[derive(Debug, Clone, Default)]
+pub struct PictureIdTracker {
+    /// Picture id, its width in bits and its frame counter
+    last: Option<(u16, u8, u64)>,
+}
+
+impl PictureIdTracker {
+    /// Returns `None` for frames without a picture id
+    pub fn update(&mut self, descriptor: &PayloadDescriptor) -> Option<UnrolledPictureId> {
+        let (picture_id, bits) = descriptor.picture_id.zip(descriptor.picture_id_bits)?;
+        let modulus = 1u32 << bits;
+
+        let unrolled = match self.last {
+            None => UnrolledPictureId {
+                frame_counter: 0,
+                missing_frames: 0,
+                reset: false,
+            },
+            Some((last_id, last_bits, counter)) => {
+                let delta = (picture_id as u32).wrapping_sub(last_id as u32) % modulus;
+                //  ids more than half the range ahead are taken to be behind
+                if last_bits != bits || delta == 0 || delta >= modulus / 2 {
+                    UnrolledPictureId {
+                        frame_counter: counter + 1,
+                        missing_frames: 0,
+                        reset: true,
+                    }
+                } else {
+                    UnrolledPictureId {
+                        frame_counter: counter + delta as u64,
+                        missing_frames: delta as u64 - 1,
+                        reset: false,
+                    }
+                }
+            }
+        };
+
+        self.last = Some((picture_id, bits, unrolled.frame_counter));
+        Some(unrolled)
+    }
+}
+
+#[test]
+fn unroll_picture_ids() {
+    let mut tracker = PictureIdTracker::default();
+    let mut update = |picture_id, bits| {
+        let unrolled = tracker
+            .update(&PayloadDescriptor {
+                picture_id: Some(picture_id),
+                picture_id_bits: Some(bits),
+                ..Default::default()
+            })
+            .unwrap();
+        (
+            unrolled.frame_counter,
+            unrolled.missing_frames,
+            unrolled.reset,
+        )
+    };
+
+    assert_eq!(update(126, 7), (0, 0, false));
+    assert_eq!(update(127, 7), (1, 0, false));
+    assert_eq!(update(0, 7), (2, 0, false));
+    assert_eq!(update(3, 7), (5, 2, false));
+    assert_eq!(update(1, 7), (6, 0, true));
+    assert_eq!(update(0x7fff, 15), (7, 0, true));
+    assert_eq!(update(1, 15), (9, 1, false));
+    assert_eq!(tracker.update(&PayloadDescriptor::default()), None);
+}
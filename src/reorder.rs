@@ -0,0 +1,343 @@
+//! Reordering of RTP packets by sequence number before frame assembly
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use rtp::packet::Packet;
+use serde::Serialize;
+
+/// Extends 16-bit RTP sequence numbers to 64 bits, treating each number as
+/// the closest one to the highest seen so far
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequenceUnroller {
+    highest: Option<i64>,
+}
+
+impl SequenceUnroller {
+    pub fn unroll(&mut self, sequence_number: u16) -> i64 {
+        let extended = match self.highest {
+            Some(highest) => highest + sequence_number.wrapping_sub(highest as u16) as i16 as i64,
+            None => sequence_number as i64,
+        };
+        self.highest = Some(self.highest.map_or(extended, |h| h.max(extended)));
+        extended
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReorderOptions {
+    /// Longest a packet waits for the ones before it before they're
+    /// considered lost
+    pub max_hold: Duration,
+    /// Most packets held at once, beyond which missing ones are considered
+    /// lost
+    pub max_depth: usize,
+}
+
+impl Default for ReorderOptions {
+    fn default() -> Self {
+        Self {
+            max_hold: Duration::from_millis(100),
+            max_depth: 128,
+        }
+    }
+}
+
+/// Counters since the start of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ReorderStats {
+    pub packets: u64,
+    pub duplicates: u64,
+    /// Packets arriving after one with a higher sequence number
+    pub reordered: u64,
+    /// Largest number of sequence numbers a reordered packet arrived behind
+    /// the highest one
+    pub max_reorder_distance: u64,
+    /// Packets skipped after waiting `max_hold` or exceeding `max_depth`
+    pub lost: u64,
+    /// Packets arriving after they were considered lost, which are dropped
+    pub late: u64,
+    /// Times the sequence numbers jumped back further than `max_depth`, e.g.
+    /// when the sender restarted, and the stream was picked up again
+    pub resyncs: u64,
+}
+
+/// Holds packets until those before them arrive, releasing them in sequence
+/// number order
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    options: ReorderOptions,
+    unroller: SequenceUnroller,
+    highest: Option<i64>,
+    /// Next sequence number to release, unknown until the first packets have
+    /// been held for `max_hold`
+    next: Option<i64>,
+    held: BTreeMap<i64, (Instant, Packet)>,
+    /// Recently skipped sequence numbers, to tell late packets from
+    /// duplicates
+    skipped: BTreeSet<i64>,
+    stats: ReorderStats,
+}
+
+//  how far behind the next sequence number skipped ones are remembered
+const SKIPPED_WINDOW: i64 = 1 << 10;
+
+impl ReorderBuffer {
+    pub fn new(options: ReorderOptions) -> Self {
+        Self {
+            options,
+            unroller: SequenceUnroller::default(),
+            highest: None,
+            next: None,
+            held: BTreeMap::new(),
+            skipped: BTreeSet::new(),
+            stats: ReorderStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ReorderStats {
+        self.stats
+    }
+
+    /// Adds a packet that arrived at `now`, returning the packets that are
+    /// ready in sequence number order along with their arrival
+    pub fn push(&mut self, packet: Packet, now: Instant) -> Vec<(Instant, Packet)> {
+        let mut extended = self.unroller.unroll(packet.header.sequence_number);
+        self.stats.packets += 1;
+
+        let mut ready = vec![];
+        if self.next.is_some_and(|next| extended < next) {
+            if self.skipped.remove(&extended) {
+                self.stats.late += 1;
+                return self.poll(now);
+            }
+            //  further back than reordering goes, the sender started over
+            let max_depth = self.options.max_depth as i64;
+            if self
+                .highest
+                .is_none_or(|highest| highest - extended <= max_depth)
+            {
+                self.stats.duplicates += 1;
+                return self.poll(now);
+            }
+            ready = self.resync();
+            extended = self.unroller.unroll(packet.header.sequence_number);
+        }
+        if self.held.contains_key(&extended) {
+            self.stats.duplicates += 1;
+            return self.poll(now);
+        }
+
+        match self.highest {
+            Some(highest) if extended < highest => {
+                self.stats.reordered += 1;
+                let distance = (highest - extended) as u64;
+                self.stats.max_reorder_distance = self.stats.max_reorder_distance.max(distance);
+            }
+            _ => self.highest = Some(extended),
+        }
+
+        self.held.insert(extended, (now, packet));
+        ready.extend(self.poll(now));
+        ready
+    }
+
+    /// Releases the packets whose predecessors are ready or considered lost
+    /// by `now`, called when no packet has arrived for a while
+    pub fn poll(&mut self, now: Instant) -> Vec<(Instant, Packet)> {
+        //  the first packet to arrive needn't be the first one sent
+        if self.next.is_none() {
+            if self.held.len() <= self.options.max_depth && !self.oldest_expired(now) {
+                return vec![];
+            }
+            self.next = self.held.keys().next().copied();
+        }
+        let mut ready = self.release_in_order();
+        while self.held.len() > self.options.max_depth || self.oldest_expired(now) {
+            self.skip_to_held();
+            ready.extend(self.release_in_order());
+        }
+        ready
+    }
+
+    /// Releases every held packet, skipping over missing ones, e.g. once the
+    /// stream ends
+    pub fn flush(&mut self) -> Vec<(Instant, Packet)> {
+        if self.next.is_none() {
+            self.next = self.held.keys().next().copied();
+        }
+        let mut ready = self.release_in_order();
+        while !self.held.is_empty() {
+            self.skip_to_held();
+            ready.extend(self.release_in_order());
+        }
+        ready
+    }
+
+    /// Releases every held packet and starts over as if no packet had
+    /// arrived yet
+    fn resync(&mut self) -> Vec<(Instant, Packet)> {
+        let ready = self.flush();
+        self.stats.resyncs += 1;
+        self.unroller = SequenceUnroller::default();
+        self.highest = None;
+        self.next = None;
+        self.skipped.clear();
+        ready
+    }
+
+    fn release_in_order(&mut self) -> Vec<(Instant, Packet)> {
+        let mut ready = vec![];
+        if let Some(next) = self.next.as_mut() {
+            while let Some(packet) = self.held.remove(next) {
+                ready.push(packet);
+                *next += 1;
+            }
+        }
+        ready
+    }
+
+    fn oldest_expired(&self, now: Instant) -> bool {
+        self.held
+            .values()
+            .any(|(arrival, _)| now.saturating_duration_since(*arrival) >= self.options.max_hold)
+    }
+
+    /// Considers the packets before the first held one lost
+    fn skip_to_held(&mut self) {
+        let (first, next) = match (self.held.keys().next(), self.next.as_mut()) {
+            (Some(first), Some(next)) => (*first, next),
+            _ => return,
+        };
+        self.stats.lost += (first - *next) as u64;
+        self.skipped.extend(*next..first);
+        *next = first;
+
+        let keep = self.skipped.split_off(&(first - SKIPPED_WINDOW));
+        self.skipped = keep;
+    }
+}
+
+/// Packet that's told apart from others only by its sequence number
+#[cfg(test)]
+fn test_packet(sequence_number: u16) -> Packet {
+    crate::test_packet(0, sequence_number, 0, false, &[])
+}
+
+#[cfg(test)]
+fn sequence_numbers(packets: Vec<(Instant, Packet)>) -> Vec<u16> {
+    packets
+        .iter()
+        .map(|(_, packet)| packet.header.sequence_number)
+        .collect()
+}
+
+#[test]
+fn unroll_sequence_numbers() {
+    let mut unroller = SequenceUnroller::default();
+    let extended: Vec<i64> = [65534, 65535, 0, 65535, 1, 32768, 2]
+        .into_iter()
+        .map(|seq| unroller.unroll(seq))
+        .collect();
+    assert_eq!(extended, [65534, 65535, 65536, 65535, 65537, 98304, 65538]);
+}
+
+#[test]
+fn reorder_across_wraparound() {
+    let mut buffer = ReorderBuffer::new(ReorderOptions::default());
+    let now = Instant::now();
+
+    assert!(buffer.push(test_packet(65534), now).is_empty());
+    assert_eq!(
+        sequence_numbers(buffer.poll(now + Duration::from_millis(100))),
+        [65534]
+    );
+    assert!(buffer.push(test_packet(0), now).is_empty());
+    assert!(buffer.push(test_packet(1), now).is_empty());
+    assert_eq!(
+        sequence_numbers(buffer.push(test_packet(65535), now)),
+        [65535, 0, 1]
+    );
+    assert!(buffer.push(test_packet(0), now).is_empty());
+
+    let stats = buffer.stats();
+    assert_eq!(stats.reordered, 1);
+    assert_eq!(stats.max_reorder_distance, 2);
+    assert_eq!(stats.duplicates, 1);
+    assert_eq!(stats.lost, 0);
+}
+
+#[test]
+fn skip_lost_packets() {
+    let mut buffer = ReorderBuffer::new(ReorderOptions {
+        max_hold: Duration::from_millis(100),
+        max_depth: 2,
+    });
+    let now = Instant::now();
+
+    assert!(buffer.push(test_packet(10), now).is_empty());
+    assert!(buffer.push(test_packet(12), now).is_empty());
+    //  waiting past max_hold gives up on 11
+    let later = now + Duration::from_millis(100);
+    assert_eq!(sequence_numbers(buffer.poll(later)), [10, 12]);
+    assert!(buffer.push(test_packet(11), later).is_empty());
+
+    //  a third held packet exceeds max_depth, giving up on 13 and 14
+    assert!(buffer.push(test_packet(15), later).is_empty());
+    assert!(buffer.push(test_packet(16), later).is_empty());
+    assert_eq!(
+        sequence_numbers(buffer.push(test_packet(17), later)),
+        [15, 16, 17]
+    );
+
+    let stats = buffer.stats();
+    assert_eq!(stats.lost, 3);
+    assert_eq!(stats.late, 1);
+    assert_eq!(stats.duplicates, 0);
+}
+
+#[test]
+fn hold_first_packets() {
+    let mut buffer = ReorderBuffer::new(ReorderOptions::default());
+    let now = Instant::now();
+
+    //  the first packet to arrive isn't taken for the first one sent
+    assert!(buffer.push(test_packet(11), now).is_empty());
+    assert!(buffer.push(test_packet(10), now).is_empty());
+    assert!(buffer.poll(now + Duration::from_millis(50)).is_empty());
+    assert_eq!(
+        sequence_numbers(buffer.poll(now + Duration::from_millis(100))),
+        [10, 11]
+    );
+    assert_eq!(sequence_numbers(buffer.push(test_packet(12), now)), [12]);
+
+    let stats = buffer.stats();
+    assert_eq!(stats.reordered, 1);
+    assert_eq!(stats.duplicates, 0);
+    assert_eq!(stats.lost, 0);
+}
+
+#[test]
+fn resync_after_backward_jump() {
+    let mut buffer = ReorderBuffer::new(ReorderOptions::default());
+    let now = Instant::now();
+    let later = now + Duration::from_millis(100);
+
+    assert!(buffer.push(test_packet(1000), now).is_empty());
+    assert_eq!(sequence_numbers(buffer.poll(later)), [1000]);
+    assert!(buffer.push(test_packet(1002), later).is_empty());
+    //  the sender restarting releases what was held and starts over
+    assert_eq!(sequence_numbers(buffer.push(test_packet(6), later)), [1002]);
+    assert!(buffer.push(test_packet(5), later).is_empty());
+    let later = later + Duration::from_millis(100);
+    assert_eq!(sequence_numbers(buffer.poll(later)), [5, 6]);
+    assert_eq!(sequence_numbers(buffer.push(test_packet(7), later)), [7]);
+    //  while going back less than max_depth is still a duplicate
+    assert!(buffer.push(test_packet(6), later).is_empty());
+
+    let stats = buffer.stats();
+    assert_eq!(stats.resyncs, 1);
+    assert_eq!(stats.duplicates, 1);
+    assert_eq!(stats.lost, 1);
+    assert_eq!(stats.late, 0);
+}
@@ -0,0 +1,513 @@
+//! RTCP compound packets, https://datatracker.ietf.org/doc/html/rfc3550#section-6
+//!
+//! Along with the feedback messages of
+//! https://datatracker.ietf.org/doc/html/rfc4585#section-6 and
+//! https://datatracker.ietf.org/doc/html/rfc5104#section-4.3.1, REMB and
+//! transport-cc feedback.
+
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{be_i16, be_u16, be_u24, be_u32, be_u64, be_u8};
+use nom::IResult;
+use serde::Serialize;
+
+use crate::Error;
+
+fn truncated(_: nom::Err<nom::error::Error<&[u8]>>) -> Error {
+    Error::TruncatedRtcpPacket
+}
+
+/// Whether multiplexed `data` is RTCP rather than RTP, going by the packet
+/// types RTP payload types must not collide with,
+/// https://datatracker.ietf.org/doc/html/rfc5761#section-4
+pub fn is_rtcp(data: &[u8]) -> bool {
+    data.get(1)
+        .is_some_and(|packet_type| (192..=223).contains(packet_type))
+}
+
+/// Reception statistics of one source in a sender or receiver report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReceptionReport {
+    pub ssrc: u32,
+    /// Fraction of packets lost since the previous report, out of 256
+    pub fraction_lost: u8,
+    pub cumulative_lost: i32,
+    pub extended_highest_sequence_number: u32,
+    /// Interarrival jitter in RTP timestamp units
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the last sender report
+    pub last_sender_report: u32,
+    /// Since the last sender report, in units of 1/65536 seconds
+    pub delay_since_last_sender_report: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SdesItem {
+    /// 1 for CNAME, 2 for NAME, ...
+    pub item_type: u8,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SdesChunk {
+    pub ssrc: u32,
+    pub items: Vec<SdesItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FirEntry {
+    pub ssrc: u32,
+    /// Incremented for each new request
+    pub sequence_number: u8,
+}
+
+/// Whether and when a packet arrived according to transport-cc feedback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PacketStatus {
+    /// Transport-wide sequence number
+    pub sequence_number: u16,
+    /// Microseconds on the receiver's clock, `None` when not received
+    pub arrival_time_us: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RtcpPacket {
+    SenderReport {
+        ssrc: u32,
+        /// 32.32 fixed point seconds since 1900
+        ntp_timestamp: u64,
+        rtp_timestamp: u32,
+        packet_count: u32,
+        octet_count: u32,
+        reports: Vec<ReceptionReport>,
+    },
+    ReceiverReport {
+        ssrc: u32,
+        reports: Vec<ReceptionReport>,
+    },
+    SourceDescription {
+        chunks: Vec<SdesChunk>,
+    },
+    Goodbye {
+        sources: Vec<u32>,
+        reason: Option<String>,
+    },
+    /// Generic NACK
+    Nack {
+        sender_ssrc: u32,
+        media_ssrc: u32,
+        lost: Vec<u16>,
+    },
+    PictureLossIndication {
+        sender_ssrc: u32,
+        media_ssrc: u32,
+    },
+    FullIntraRequest {
+        sender_ssrc: u32,
+        entries: Vec<FirEntry>,
+    },
+    /// https://datatracker.ietf.org/doc/html/draft-alvestrand-rmcat-remb-03
+    ReceiverEstimatedMaxBitrate {
+        sender_ssrc: u32,
+        /// Bits per second
+        bitrate: u64,
+        ssrcs: Vec<u32>,
+    },
+    /// https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01
+    TransportFeedback {
+        sender_ssrc: u32,
+        media_ssrc: u32,
+        feedback_packet_count: u8,
+        packets: Vec<PacketStatus>,
+    },
+    /// Packet types and feedback messages that aren't parsed
+    Unknown {
+        packet_type: u8,
+        /// Report count or feedback message type
+        count: u8,
+    },
+}
+
+impl RtcpPacket {
+    /// Parses every packet of a compound packet
+    pub fn parse_compound(mut data: &[u8]) -> Result<Vec<Self>, Error> {
+        let mut packets = vec![];
+        while !data.is_empty() {
+            let (rest, packet) = Self::parse(data)?;
+            packets.push(packet);
+            data = rest;
+        }
+        Ok(packets)
+    }
+
+    /// Parses the first packet of `data`, returning the packets after it
+    pub fn parse(data: &[u8]) -> Result<(&[u8], Self), Error> {
+        let (data, first) = be_u8(data).map_err(truncated)?;
+        let version = first >> 6;
+        if version != 2 {
+            return Err(Error::UnsupportedRtcpVersion(version));
+        }
+        let padded = first & 0x20 != 0;
+        let count = first & 0x1f;
+        let (data, packet_type) = be_u8(data).map_err(truncated)?;
+        //  length in 32-bit words minus one, which the header fills
+        let (data, length) = be_u16(data).map_err(truncated)?;
+        let (rest, mut body) = take(length as usize * 4)(data).map_err(truncated)?;
+        if padded {
+            let padding = body.last().copied().unwrap_or(0) as usize;
+            body = &body[..body.len().saturating_sub(padding)];
+        }
+
+        let (_, packet) = match (packet_type, count) {
+            (200, _) => sender_report(body, count),
+            (201, _) => receiver_report(body, count),
+            (202, _) => source_description(body, count),
+            (203, _) => goodbye(body, count),
+            (205, 1) => nack(body),
+            (205, 15) => transport_feedback(body),
+            (206, 1) => picture_loss_indication(body),
+            (206, 4) => full_intra_request(body),
+            (206, 15) => application_feedback(body, packet_type, count),
+            _ => Ok((body, Self::Unknown { packet_type, count })),
+        }
+        .map_err(truncated)?;
+        Ok((rest, packet))
+    }
+}
+
+fn reception_report(data: &[u8]) -> IResult<&[u8], ReceptionReport> {
+    let (data, ssrc) = be_u32(data)?;
+    let (data, fraction_lost) = be_u8(data)?;
+    let (data, cumulative_lost) = be_u24(data)?;
+    let (data, extended_highest_sequence_number) = be_u32(data)?;
+    let (data, jitter) = be_u32(data)?;
+    let (data, last_sender_report) = be_u32(data)?;
+    let (data, delay_since_last_sender_report) = be_u32(data)?;
+    Ok((
+        data,
+        ReceptionReport {
+            ssrc,
+            fraction_lost,
+            //  sign extend the 24-bit count
+            cumulative_lost: (cumulative_lost << 8) as i32 >> 8,
+            extended_highest_sequence_number,
+            jitter,
+            last_sender_report,
+            delay_since_last_sender_report,
+        },
+    ))
+}
+
+fn sender_report(data: &[u8], report_count: u8) -> IResult<&[u8], RtcpPacket> {
+    let (data, ssrc) = be_u32(data)?;
+    let (data, ntp_timestamp) = be_u64(data)?;
+    let (data, rtp_timestamp) = be_u32(data)?;
+    let (data, packet_count) = be_u32(data)?;
+    let (data, octet_count) = be_u32(data)?;
+    let (data, reports) = count(reception_report, report_count as usize)(data)?;
+    Ok((
+        data,
+        RtcpPacket::SenderReport {
+            ssrc,
+            ntp_timestamp,
+            rtp_timestamp,
+            packet_count,
+            octet_count,
+            reports,
+        },
+    ))
+}
+
+fn receiver_report(data: &[u8], report_count: u8) -> IResult<&[u8], RtcpPacket> {
+    let (data, ssrc) = be_u32(data)?;
+    let (data, reports) = count(reception_report, report_count as usize)(data)?;
+    Ok((data, RtcpPacket::ReceiverReport { ssrc, reports }))
+}
+
+fn sdes_chunk(chunk: &[u8]) -> IResult<&[u8], SdesChunk> {
+    let (mut data, ssrc) = be_u32(chunk)?;
+    let mut items = vec![];
+    loop {
+        let (rest, item_type) = be_u8(data)?;
+        data = rest;
+        //  a null item ends the chunk
+        if item_type == 0 {
+            break;
+        }
+        let (rest, length) = be_u8(data)?;
+        let (rest, value) = take(length as usize)(rest)?;
+        data = rest;
+        items.push(SdesItem {
+            item_type,
+            value: String::from_utf8_lossy(value).into_owned(),
+        });
+    }
+    //  chunks are padded to a 32-bit boundary
+    let consumed = chunk.len() - data.len();
+    let (data, _) = take((4 - consumed % 4) % 4)(data)?;
+    Ok((data, SdesChunk { ssrc, items }))
+}
+
+fn source_description(data: &[u8], source_count: u8) -> IResult<&[u8], RtcpPacket> {
+    let (data, chunks) = count(sdes_chunk, source_count as usize)(data)?;
+    Ok((data, RtcpPacket::SourceDescription { chunks }))
+}
+
+fn goodbye(data: &[u8], source_count: u8) -> IResult<&[u8], RtcpPacket> {
+    let (data, sources) = count(be_u32, source_count as usize)(data)?;
+    let (data, reason) = if data.is_empty() {
+        (data, None)
+    } else {
+        let (data, length) = be_u8(data)?;
+        let (data, reason) = take(length as usize)(data)?;
+        (data, Some(String::from_utf8_lossy(reason).into_owned()))
+    };
+    Ok((data, RtcpPacket::Goodbye { sources, reason }))
+}
+
+fn nack(data: &[u8]) -> IResult<&[u8], RtcpPacket> {
+    let (mut data, (sender_ssrc, media_ssrc)) = nom::sequence::pair(be_u32, be_u32)(data)?;
+    let mut lost = vec![];
+    while !data.is_empty() {
+        //  a lost packet id followed by a bitmask of the 16 after it
+        let (rest, packet_id) = be_u16(data)?;
+        let (rest, bitmask) = be_u16(rest)?;
+        data = rest;
+        lost.push(packet_id);
+        lost.extend(
+            (0..16)
+                .filter(|bit| bitmask & (1 << bit) != 0)
+                .map(|bit| packet_id.wrapping_add(bit + 1)),
+        );
+    }
+    Ok((
+        data,
+        RtcpPacket::Nack {
+            sender_ssrc,
+            media_ssrc,
+            lost,
+        },
+    ))
+}
+
+fn picture_loss_indication(data: &[u8]) -> IResult<&[u8], RtcpPacket> {
+    let (data, (sender_ssrc, media_ssrc)) = nom::sequence::pair(be_u32, be_u32)(data)?;
+    Ok((
+        data,
+        RtcpPacket::PictureLossIndication {
+            sender_ssrc,
+            media_ssrc,
+        },
+    ))
+}
+
+fn full_intra_request(data: &[u8]) -> IResult<&[u8], RtcpPacket> {
+    //  the media source is unused, requests name their ssrc
+    let (mut data, (sender_ssrc, _)) = nom::sequence::pair(be_u32, be_u32)(data)?;
+    let mut entries = vec![];
+    while !data.is_empty() {
+        let (rest, ssrc) = be_u32(data)?;
+        let (rest, sequence_number) = be_u8(rest)?;
+        let (rest, _reserved) = be_u24(rest)?;
+        data = rest;
+        entries.push(FirEntry {
+            ssrc,
+            sequence_number,
+        });
+    }
+    Ok((
+        data,
+        RtcpPacket::FullIntraRequest {
+            sender_ssrc,
+            entries,
+        },
+    ))
+}
+
+fn application_feedback(data: &[u8], packet_type: u8, fmt: u8) -> IResult<&[u8], RtcpPacket> {
+    let (rest, (sender_ssrc, _)) = nom::sequence::pair(be_u32, be_u32)(data)?;
+    let (rest, identifier) = take(4usize)(rest)?;
+    if identifier != b"REMB" {
+        return Ok((
+            data,
+            RtcpPacket::Unknown {
+                packet_type,
+                count: fmt,
+            },
+        ));
+    }
+    let (rest, ssrc_count) = be_u8(rest)?;
+    //  6-bit exponent and 18-bit mantissa
+    let (rest, bitrate) = be_u24(rest)?;
+    let (rest, ssrcs) = count(be_u32, ssrc_count as usize)(rest)?;
+    Ok((
+        rest,
+        RtcpPacket::ReceiverEstimatedMaxBitrate {
+            sender_ssrc,
+            bitrate: ((bitrate & 0x3ffff) as u64) << (bitrate >> 18),
+            ssrcs,
+        },
+    ))
+}
+
+fn transport_feedback(data: &[u8]) -> IResult<&[u8], RtcpPacket> {
+    let (data, (sender_ssrc, media_ssrc)) = nom::sequence::pair(be_u32, be_u32)(data)?;
+    let (data, base_sequence_number) = be_u16(data)?;
+    let (data, status_count) = be_u16(data)?;
+    //  signed 24-bit, in multiples of 64ms
+    let (data, reference_time) = be_u24(data)?;
+    let (mut data, feedback_packet_count) = be_u8(data)?;
+
+    //  0 not received, 1 received with a small delta, 2 with a large one
+    let status_count = status_count as usize;
+    let mut symbols = Vec::with_capacity(status_count);
+    while symbols.len() < status_count {
+        let (rest, chunk) = be_u16(data)?;
+        data = rest;
+        let remaining = status_count - symbols.len();
+        if chunk & 0x8000 == 0 {
+            //  run length chunk
+            let symbol = ((chunk >> 13) & 0x3) as u8;
+            let run_length = (chunk & 0x1fff) as usize;
+            symbols.resize(symbols.len() + run_length.min(remaining), symbol);
+        } else if chunk & 0x4000 == 0 {
+            //  status vector chunk of 14 one-bit symbols
+            symbols.extend(
+                (0..14)
+                    .rev()
+                    .map(|i| ((chunk >> i) & 0x1) as u8)
+                    .take(remaining),
+            );
+        } else {
+            //  status vector chunk of 7 two-bit symbols
+            symbols.extend(
+                (0..7)
+                    .rev()
+                    .map(|i| ((chunk >> (i * 2)) & 0x3) as u8)
+                    .take(remaining),
+            );
+        }
+    }
+
+    //  receive deltas are in multiples of 250us
+    let mut arrival_time_us = ((reference_time << 8) as i32 >> 8) as i64 * 64_000;
+    let mut packets = Vec::with_capacity(status_count);
+    for (i, symbol) in symbols.into_iter().enumerate() {
+        let delta = match symbol {
+            1 => {
+                let (rest, delta) = be_u8(data)?;
+                data = rest;
+                Some(delta as i64)
+            }
+            2 => {
+                let (rest, delta) = be_i16(data)?;
+                data = rest;
+                Some(delta as i64)
+            }
+            _ => None,
+        };
+        packets.push(PacketStatus {
+            sequence_number: base_sequence_number.wrapping_add(i as u16),
+            arrival_time_us: delta.map(|delta| {
+                arrival_time_us += delta * 250;
+                arrival_time_us
+            }),
+        });
+    }
+
+    Ok((
+        data,
+        RtcpPacket::TransportFeedback {
+            sender_ssrc,
+            media_ssrc,
+            feedback_packet_count,
+            packets,
+        },
+    ))
+}
+
+#[test]
+fn parse_compound_packet() {
+    let data = [
+        //  receiver report without report blocks
+        0x80, 201, 0, 1, 0, 0, 0, 1, //
+        //  sdes with a cname of "ab", a null item and padding
+        0x81, 202, 0, 3, 0, 0, 0, 1, 1, 2, b'a', b'b', 0, 0, 0, 0, //
+        //  nack of 5, 6 and 8
+        0x81, 205, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 5, 0, 0x05, //
+        //  pli
+        0x81, 206, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, //
+        //  remb of 3 << 2 bps for one ssrc
+        0x8f, 206, 0, 5, 0, 0, 0, 1, 0, 0, 0, 0, b'R', b'E', b'M', b'B', 1, 0x08, 0x00, 0x03, 0, 0,
+        0, 2,
+    ];
+    assert_eq!(
+        RtcpPacket::parse_compound(&data).unwrap(),
+        [
+            RtcpPacket::ReceiverReport {
+                ssrc: 1,
+                reports: vec![],
+            },
+            RtcpPacket::SourceDescription {
+                chunks: vec![SdesChunk {
+                    ssrc: 1,
+                    items: vec![SdesItem {
+                        item_type: 1,
+                        value: "ab".to_string(),
+                    }],
+                }],
+            },
+            RtcpPacket::Nack {
+                sender_ssrc: 1,
+                media_ssrc: 2,
+                lost: vec![5, 6, 8],
+            },
+            RtcpPacket::PictureLossIndication {
+                sender_ssrc: 1,
+                media_ssrc: 2,
+            },
+            RtcpPacket::ReceiverEstimatedMaxBitrate {
+                sender_ssrc: 1,
+                bitrate: 12,
+                ssrcs: vec![2],
+            },
+        ]
+    );
+    assert!(is_rtcp(&data));
+    assert!(matches!(
+        RtcpPacket::parse_compound(&data[..20]),
+        Err(Error::TruncatedRtcpPacket)
+    ));
+}
+
+#[test]
+fn parse_transport_feedback() {
+    let data = [
+        0x8f, 205, 0, 6, 0, 0, 0, 1, 0, 0, 0, 2, //
+        //  base sequence number 100, 4 statuses, reference time 1, count 7
+        0, 100, 0, 4, 0, 0, 1, 7, //
+        //  two-bit vector: small, not received, large, small
+        0xd2, 0x40, //
+        //  deltas of 1, -2 and 4 in units of 250us, then padding
+        1, 0xff, 0xfe, 4, 0, 0,
+    ];
+    let (rest, packet) = RtcpPacket::parse(&data).unwrap();
+    assert!(rest.is_empty());
+    let arrivals = |packet| match packet {
+        RtcpPacket::TransportFeedback { packets, .. } => packets
+            .iter()
+            .map(|p| (p.sequence_number, p.arrival_time_us))
+            .collect::<Vec<_>>(),
+        _ => panic!("not transport feedback"),
+    };
+    assert_eq!(
+        arrivals(packet),
+        [
+            (100, Some(64_250)),
+            (101, None),
+            (102, Some(63_750)),
+            (103, Some(64_750)),
+        ]
+    );
+}
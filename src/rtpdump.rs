@@ -0,0 +1,216 @@
+//! rtpdump files, the RTPplay format of rtptools
+//! (https://github.com/irtlab/rtptools) that Wireshark reads and writes too.
+//!
+//! A `#!rtpplay1.0 address/port` line and a binary header with the start of
+//! the recording, then each packet prefixed by its length and arrival offset
+//! in milliseconds, all big endian.
+
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+
+use crate::{is_rtcp, Error};
+
+const SIGNATURE: &[u8] = b"#!rtpplay1.0 ";
+const HEADER_SIZE: usize = 16;
+const PACKET_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpDumpHeader {
+    /// Since the unix epoch, with microsecond precision
+    pub start: Duration,
+    /// Where the packets were sent from
+    pub source: SocketAddrV4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpDumpPacket<'a> {
+    /// Arrival since the start of the recording, in whole milliseconds
+    pub offset: Duration,
+    /// RTP or RTCP packet
+    pub data: &'a [u8],
+}
+
+/// Iterates over the packets of an rtpdump file
+#[derive(Debug, Clone)]
+pub struct RtpDumpReader<'a> {
+    header: RtpDumpHeader,
+    data: &'a [u8],
+}
+
+impl<'a> RtpDumpReader<'a> {
+    pub fn new(dump: &'a [u8]) -> Result<Self, Error> {
+        if !dump.starts_with(SIGNATURE) {
+            return Err(Error::BadRtpDumpSignature);
+        }
+        //  the address line repeats what the binary header holds
+        let line_end = dump
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or(Error::TruncatedRtpDump)?;
+        let data = &dump[line_end + 1..];
+        let header = data.get(..HEADER_SIZE).ok_or(Error::TruncatedRtpDump)?;
+        let be_u32 =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        Ok(Self {
+            header: RtpDumpHeader {
+                //  microseconds aren't checked to be below a second
+                start: Duration::from_secs(be_u32(0) as u64)
+                    + Duration::from_micros(be_u32(4) as u64),
+                source: SocketAddrV4::new(
+                    Ipv4Addr::from(be_u32(8)),
+                    u16::from_be_bytes([header[12], header[13]]),
+                ),
+            },
+            data: &data[HEADER_SIZE..],
+        })
+    }
+
+    pub fn header(&self) -> &RtpDumpHeader {
+        &self.header
+    }
+}
+
+impl<'a> Iterator for RtpDumpReader<'a> {
+    type Item = Result<RtpDumpPacket<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let packet = (|| {
+            let header = self
+                .data
+                .get(..PACKET_HEADER_SIZE)
+                .ok_or(Error::TruncatedRtpDump)?;
+            //  length includes the packet header, the original length that
+            //  follows it is zero for rtcp
+            let length = u16::from_be_bytes([header[0], header[1]]) as usize;
+            let offset = u32::from_be_bytes(header[4..8].try_into().unwrap());
+            let data = self
+                .data
+                .get(PACKET_HEADER_SIZE..length.max(PACKET_HEADER_SIZE))
+                .ok_or(Error::TruncatedRtpDump)?;
+            self.data = &self.data[PACKET_HEADER_SIZE + data.len()..];
+            Ok(RtpDumpPacket {
+                offset: Duration::from_millis(offset as u64),
+                data,
+            })
+        })();
+        //  nothing after a truncated packet can be found
+        if packet.is_err() {
+            self.data = &[];
+        }
+        Some(packet)
+    }
+}
+
+/// Writes packets to an rtpdump file
+#[derive(Debug)]
+pub struct RtpDumpWriter<W> {
+    w: W,
+}
+
+impl<W: Write> RtpDumpWriter<W> {
+    pub fn new(mut w: W, header: RtpDumpHeader) -> io::Result<Self> {
+        let mut file_header = format!(
+            "{}{}/{}\n",
+            String::from_utf8_lossy(SIGNATURE),
+            header.source.ip(),
+            header.source.port()
+        )
+        .into_bytes();
+        file_header.extend_from_slice(&(header.start.as_secs() as u32).to_be_bytes());
+        file_header.extend_from_slice(&header.start.subsec_micros().to_be_bytes());
+        file_header.extend_from_slice(&header.source.ip().octets());
+        file_header.extend_from_slice(&header.source.port().to_be_bytes());
+        //  padding
+        file_header.extend_from_slice(&[0, 0]);
+        w.write_all(&file_header)?;
+        Ok(Self { w })
+    }
+
+    /// Writes an RTP or RTCP packet arriving `offset` after the start of the
+    /// recording
+    pub fn write_packet(&mut self, offset: Duration, packet: &[u8]) -> io::Result<()> {
+        let length = u16::try_from(PACKET_HEADER_SIZE + packet.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "rtpdump packet too large"))?;
+        let original_length = if is_rtcp(packet) {
+            0
+        } else {
+            packet.len() as u16
+        };
+        let offset = u32::try_from(offset.as_millis()).unwrap_or(u32::MAX);
+        //  a single write, as recordings go straight to files
+        let mut record = Vec::with_capacity(length as usize);
+        record.extend_from_slice(&length.to_be_bytes());
+        record.extend_from_slice(&original_length.to_be_bytes());
+        record.extend_from_slice(&offset.to_be_bytes());
+        record.extend_from_slice(packet);
+        self.w.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+#[test]
+fn write_and_read_packets() {
+    let header = RtpDumpHeader {
+        start: Duration::new(1_600_000_000, 250_000_000),
+        source: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5004),
+    };
+    let rtp = [0x80, 0x60, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0x10];
+    let rtcp = [0x81, 0xc9, 0, 1, 0, 0, 0, 1];
+    let mut writer = RtpDumpWriter::new(vec![], header).unwrap();
+    writer.write_packet(Duration::ZERO, &rtp).unwrap();
+    writer
+        .write_packet(Duration::from_micros(33_900), &rtcp)
+        .unwrap();
+    let dump = writer.into_inner();
+    assert!(dump.starts_with(b"#!rtpplay1.0 10.0.0.1/5004\n"));
+    //  rtcp is recorded with an original length of zero
+    assert_eq!(
+        &dump[dump.len() - 16..dump.len() - 8],
+        [0, 16, 0, 0, 0, 0, 0, 33]
+    );
+
+    let reader = RtpDumpReader::new(&dump).unwrap();
+    assert_eq!(*reader.header(), header);
+    let packets: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(
+        packets,
+        [
+            RtpDumpPacket {
+                offset: Duration::ZERO,
+                data: &rtp
+            },
+            RtpDumpPacket {
+                offset: Duration::from_millis(33),
+                data: &rtcp
+            },
+        ]
+    );
+
+    let mut reader = RtpDumpReader::new(&dump[..dump.len() - 1]).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert!(matches!(reader.next(), Some(Err(Error::TruncatedRtpDump))));
+    assert!(reader.next().is_none());
+    assert!(matches!(
+        RtpDumpReader::new(b"DKIF"),
+        Err(Error::BadRtpDumpSignature)
+    ));
+
+    let mut dump = dump;
+    let micros = dump.iter().position(|b| *b == b'\n').unwrap() + 5;
+    dump[micros..micros + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(
+        RtpDumpReader::new(&dump).unwrap().header().start,
+        Duration::from_secs(1_600_000_000) + Duration::from_micros(u32::MAX as u64)
+    );
+}
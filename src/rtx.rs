@@ -0,0 +1,78 @@
+//! RTX retransmissions, https://datatracker.ietf.org/doc/html/rfc4588
+//!
+//! Retransmitted packets arrive on their own SSRC and payload type, with the
+//! original sequence number (OSN) prefixed to the original payload.
+
+use std::collections::HashMap;
+
+use rtp::packet::Packet;
+
+/// Maps RTX payload types and SSRCs to the streams they retransmit
+#[derive(Debug, Clone, Default)]
+pub struct RtxRegistry {
+    /// RTX payload type to its associated payload type (apt)
+    payload_types: HashMap<u8, u8>,
+    /// RTX SSRC to the original stream's SSRC
+    ssrcs: HashMap<u32, u32>,
+}
+
+impl RtxRegistry {
+    pub fn register_payload_type(&mut self, rtx_payload_type: u8, associated_payload_type: u8) {
+        self.payload_types
+            .insert(rtx_payload_type, associated_payload_type);
+    }
+
+    pub fn register_ssrc(&mut self, rtx_ssrc: u32, media_ssrc: u32) {
+        self.ssrcs.insert(rtx_ssrc, media_ssrc);
+    }
+
+    pub fn is_rtx(&self, packet: &Packet) -> bool {
+        self.payload_types.contains_key(&packet.header.payload_type)
+    }
+
+    /// Restores the packet an RTX packet retransmits. Returns `None` for
+    /// packets that aren't RTX, padding-only probes without an OSN, and RTX
+    /// SSRCs that weren't registered.
+    pub fn unwrap(&self, mut packet: Packet) -> Option<Packet> {
+        let payload_type = *self.payload_types.get(&packet.header.payload_type)?;
+        let ssrc = *self.ssrcs.get(&packet.header.ssrc)?;
+        if packet.payload.len() < 2 {
+            return None;
+        }
+        let osn = u16::from_be_bytes([packet.payload[0], packet.payload[1]]);
+
+        packet.header.payload_type = payload_type;
+        packet.header.ssrc = ssrc;
+        packet.header.sequence_number = osn;
+        packet.payload = packet.payload.slice(2..);
+        Some(packet)
+    }
+}
+
+#[test]
+fn unwrap_retransmission() {
+    let mut registry = RtxRegistry::default();
+    registry.register_payload_type(97, 96);
+    registry.register_ssrc(2, 1);
+
+    let rtx = |ssrc, payload: &'static [u8]| Packet {
+        header: rtp::header::Header {
+            ssrc,
+            payload_type: 97,
+            sequence_number: 7,
+            timestamp: 3000,
+            ..Default::default()
+        },
+        payload: bytes::Bytes::from_static(payload),
+    };
+    let packet = registry.unwrap(rtx(2, &[0x01, 0x00, 0x10, 1, 2])).unwrap();
+    assert_eq!(packet.header.ssrc, 1);
+    assert_eq!(packet.header.payload_type, 96);
+    assert_eq!(packet.header.sequence_number, 256);
+    assert_eq!(packet.header.timestamp, 3000);
+    assert_eq!(&packet.payload[..], [0x10, 1, 2]);
+
+    //  padding-only probes and unknown rtx streams
+    assert!(registry.unwrap(rtx(2, &[])).is_none());
+    assert!(registry.unwrap(rtx(3, &[0x01, 0x00, 0x10])).is_none());
+}
@@ -0,0 +1,283 @@
+//! Temporal scalability conformance, checking that dropping the frames above
+//! a temporal layer leaves the layers below it decodable,
+//! https://datatracker.ietf.org/doc/html/rfc7741#section-4.2
+
+use serde::Serialize;
+
+use crate::vp8::{FrameHeader, ModeStats, RefFrame};
+use crate::PayloadDescriptor;
+
+const BUFFERS: [RefFrame; 3] = [RefFrame::Last, RefFrame::Golden, RefFrame::AltRef];
+
+fn buffer_index(buffer: RefFrame) -> usize {
+    match buffer {
+        RefFrame::Last => 0,
+        RefFrame::Golden => 1,
+        RefFrame::AltRef => 2,
+    }
+}
+
+/// Breaks of the temporal layer structure, reported on the frame causing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "violation", rename_all = "snake_case")]
+pub enum LayerViolation {
+    /// Frame refreshed or copied over a buffer a lower layer predicts from
+    UpdatesLowerLayerReference {
+        temporal_layer: u8,
+        buffer: RefFrame,
+        lowest_user: u8,
+    },
+    /// Frame predicted from a buffer written by a higher layer
+    ReferencesHigherLayer {
+        temporal_layer: u8,
+        buffer: RefFrame,
+        buffer_layer: u8,
+    },
+    /// Layer sync frame predicted from a buffer not written by the base layer
+    SyncReferencesNonBaseLayer {
+        temporal_layer: u8,
+        buffer: RefFrame,
+        buffer_layer: u8,
+    },
+    /// TL0PICIDX didn't increment by exactly one on a base layer frame, or
+    /// changed on a higher layer frame
+    Tl0PicIdx {
+        temporal_layer: u8,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BufferLayers {
+    /// Layer of the frame occupying the buffer
+    writer: Option<u8>,
+    /// Lowest layer that predicted from the buffer since the last key frame
+    lowest_user: Option<u8>,
+}
+
+/// Checks the temporal layer structure of a stream's frames, passed in
+/// decode order. Frames without a TID are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct TemporalLayerChecker {
+    buffers: [BufferLayers; 3],
+    last_tl0_pic_idx: Option<u8>,
+}
+
+impl TemporalLayerChecker {
+    /// Checks a frame, `modes` telling which buffers it predicts from. The
+    /// reference checks are skipped without them.
+    pub fn check(
+        &mut self,
+        descriptor: &PayloadDescriptor,
+        header: &FrameHeader,
+        modes: Option<&ModeStats>,
+    ) -> Vec<LayerViolation> {
+        let mut violations = vec![];
+        let temporal_layer = match descriptor.temporal_layer {
+            Some(temporal_layer) => temporal_layer,
+            None => return violations,
+        };
+
+        if let Some(actual) = descriptor.tl0_pic_idx {
+            if let Some(last) = self.last_tl0_pic_idx {
+                let expected = if temporal_layer == 0 {
+                    last.wrapping_add(1)
+                } else {
+                    last
+                };
+                if actual != expected {
+                    violations.push(LayerViolation::Tl0PicIdx {
+                        temporal_layer,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            self.last_tl0_pic_idx = Some(actual);
+        }
+
+        if header.is_key_frame() {
+            self.buffers = [BufferLayers {
+                writer: Some(temporal_layer),
+                lowest_user: None,
+            }; 3];
+            return violations;
+        }
+
+        if let Some(modes) = modes {
+            let used = [modes.last, modes.golden, modes.altref];
+            for ((buffer, layers), used) in BUFFERS.iter().zip(&mut self.buffers).zip(used) {
+                if used == 0 {
+                    continue;
+                }
+                if let Some(buffer_layer) = layers.writer {
+                    if buffer_layer > temporal_layer {
+                        violations.push(LayerViolation::ReferencesHigherLayer {
+                            temporal_layer,
+                            buffer: *buffer,
+                            buffer_layer,
+                        });
+                    }
+                    if descriptor.layer_sync == Some(true) && buffer_layer > 0 {
+                        violations.push(LayerViolation::SyncReferencesNonBaseLayer {
+                            temporal_layer,
+                            buffer: *buffer,
+                            buffer_layer,
+                        });
+                    }
+                }
+                layers.lowest_user = Some(
+                    layers
+                        .lowest_user
+                        .map_or(temporal_layer, |l| l.min(temporal_layer)),
+                );
+            }
+        }
+
+        let update = &header.frame_buffer_update;
+        let modified = [
+            update.last,
+            update.modifies_golden(),
+            update.modifies_altref(),
+        ];
+        for ((buffer, layers), modified) in BUFFERS.iter().zip(&self.buffers).zip(modified) {
+            match layers.lowest_user {
+                Some(lowest_user) if modified && lowest_user < temporal_layer => {
+                    violations.push(LayerViolation::UpdatesLowerLayerReference {
+                        temporal_layer,
+                        buffer: *buffer,
+                        lowest_user,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        //  copies move the writer's layer along with its frame, altref
+        //  first like in `ReferenceTracker`
+        if let Some(source) = update.copy_to_altref {
+            self.buffers[2].writer = self.buffers[buffer_index(source)].writer;
+        }
+        if let Some(source) = update.copy_to_golden {
+            self.buffers[1].writer = self.buffers[buffer_index(source)].writer;
+        }
+        let refreshed = [update.last, update.golden, update.altref];
+        for (layers, refreshed) in self.buffers.iter_mut().zip(refreshed) {
+            if refreshed {
+                layers.writer = Some(temporal_layer);
+            }
+        }
+
+        violations
+    }
+
+    /// Records a frame lost before its header could be parsed, after which
+    /// TL0PICIDX may legitimately skip
+    pub fn lose_frame(&mut self) {
+        self.last_tl0_pic_idx = None;
+    }
+}
+
+#[cfg(test)]
+fn test_frame(
+    key_frame: bool,
+    temporal_layer: u8,
+    tl0_pic_idx: u8,
+    layer_sync: bool,
+    update: crate::vp8::FrameBufferUpdate,
+) -> (PayloadDescriptor, FrameHeader) {
+    let frame = crate::vp8::encode_frame(key_frame, None, None, false, None);
+    let mut header = crate::vp8::FrameInfo::parse(&frame).unwrap().header;
+    header.frame_buffer_update = update;
+    let descriptor = PayloadDescriptor {
+        temporal_layer: Some(temporal_layer),
+        tl0_pic_idx: Some(tl0_pic_idx),
+        layer_sync: Some(layer_sync),
+        ..Default::default()
+    };
+    (descriptor, header)
+}
+
+#[cfg(test)]
+fn test_modes(last: u32, golden: u32) -> ModeStats {
+    ModeStats {
+        last,
+        golden,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn check_two_layer_structure() {
+    use crate::vp8::FrameBufferUpdate;
+
+    let update = |last, golden| FrameBufferUpdate {
+        last,
+        golden,
+        altref: false,
+        copy_to_golden: None,
+        copy_to_altref: None,
+    };
+    let mut checker = TemporalLayerChecker::default();
+    let mut check = |frame: (PayloadDescriptor, FrameHeader), modes: ModeStats| {
+        checker.check(&frame.0, &frame.1, Some(&modes))
+    };
+
+    //  TL0 predicts from and refreshes last, TL1 refreshes golden
+    let all = FrameBufferUpdate {
+        altref: true,
+        ..update(true, true)
+    };
+    assert!(check(test_frame(true, 0, 0, false, all), test_modes(0, 0)).is_empty());
+    assert!(check(
+        test_frame(false, 1, 0, true, update(false, true)),
+        test_modes(1, 0)
+    )
+    .is_empty());
+    assert!(check(
+        test_frame(false, 0, 1, false, update(true, false)),
+        test_modes(1, 0)
+    )
+    .is_empty());
+    assert!(check(
+        test_frame(false, 1, 1, false, update(false, true)),
+        test_modes(1, 1)
+    )
+    .is_empty());
+
+    assert_eq!(
+        check(
+            test_frame(false, 1, 2, true, update(true, false)),
+            test_modes(1, 1)
+        ),
+        [
+            LayerViolation::Tl0PicIdx {
+                temporal_layer: 1,
+                expected: 1,
+                actual: 2,
+            },
+            LayerViolation::SyncReferencesNonBaseLayer {
+                temporal_layer: 1,
+                buffer: RefFrame::Golden,
+                buffer_layer: 1,
+            },
+            LayerViolation::UpdatesLowerLayerReference {
+                temporal_layer: 1,
+                buffer: RefFrame::Last,
+                lowest_user: 0,
+            },
+        ]
+    );
+    assert_eq!(
+        check(
+            test_frame(false, 0, 3, false, update(true, false)),
+            test_modes(1, 0)
+        ),
+        [LayerViolation::ReferencesHigherLayer {
+            temporal_layer: 0,
+            buffer: RefFrame::Last,
+            buffer_layer: 1,
+        }]
+    );
+}
@@ -1,6 +1,6 @@
 #![allow(unused)]
 /// Arithmetic bit-coding from https://datatracker.ietf.org/doc/html/rfc6386#section-7
-use std::io::Read;
+use crate::Error;
 
 pub struct BoolEncoder {
     output: Vec<u8>,
@@ -20,9 +20,7 @@ impl BoolEncoder {
     }
 
     fn add_one_to_output(&mut self) {
-        let mut iter = self.output.iter_mut().rev();
-
-        while let Some(b) = iter.next() {
+        for b in self.output.iter_mut().rev() {
             if b == &255 {
                 *b = 0;
             } else {
@@ -64,6 +62,44 @@ impl BoolEncoder {
         }
     }
 
+    pub fn write_bit(&mut self, value: bool) {
+        self.write_bool(128, value as u8);
+    }
+
+    pub fn write_literal(&mut self, num_bits: u32, value: u32) {
+        for bit in (0..num_bits).rev() {
+            self.write_bit((value >> bit) & 1 == 1);
+        }
+    }
+
+    pub fn write_signed_literal(&mut self, num_bits: u32, value: i32) {
+        self.write_literal(num_bits, value.unsigned_abs());
+        self.write_bit(value < 0);
+    }
+
+    /// Writes the branches leading to `value`'s leaf in `tree`
+    pub fn write_tree(&mut self, tree: &[i8], probs: &[u8], value: u8) {
+        fn path(tree: &[i8], i: usize, value: u8, branches: &mut Vec<(usize, u8)>) -> bool {
+            for branch in 0..2 {
+                let node = tree[i + branch];
+                branches.push((i, branch as u8));
+                if (node <= 0 && -node as u8 == value)
+                    || (node > 0 && path(tree, node as usize, value, branches))
+                {
+                    return true;
+                }
+                branches.pop();
+            }
+            false
+        }
+
+        let mut branches = vec![];
+        assert!(path(tree, 0, value, &mut branches), "value not in tree");
+        for (i, branch) in branches {
+            self.write_bool(probs[i >> 1] as u32, branch);
+        }
+    }
+
     pub fn flush(mut self) -> Vec<u8> {
         let mut c: i32 = self.bit_count;
         let mut v = self.bottom;
@@ -100,33 +136,84 @@ impl BoolEncoder {
 }
 
 pub struct BoolDecoder<'a> {
-    input: std::io::Cursor<&'a [u8]>,
+    input: &'a [u8],
+    /// Bytes of the original input shifted into `value` so far
+    consumed: usize,
     range: u32,
     value: u32,
     bit_count: i32,
+    /// Reads past the end of `input` return zeros instead of failing
+    zero_padded: bool,
 }
 
-impl<'a> BoolDecoder<'a> {
-    pub fn new(input: &'a [u8]) -> std::io::Result<Self> {
-        let mut input = std::io::Cursor::new(input);
+/// State of a `BoolDecoder`, from which it can resume on the same input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoolDecoderPosition {
+    consumed: usize,
+    range: u32,
+    value: u32,
+    bit_count: i32,
+}
 
-        let mut first_2 = [0u8; 2];
-        input.read_exact(&mut first_2)?;
+impl<'a> BoolDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> Result<Self, Error> {
+        if input.len() < 2 {
+            return Err(Error::BoolDecoderUnderrun);
+        }
 
         Ok(Self {
-            input,
-            value: ((first_2[0] as u32) << 8) | first_2[1] as u32,
+            input: &input[2..],
+            consumed: 2,
+            value: ((input[0] as u32) << 8) | input[1] as u32,
             range: 255,
             bit_count: 0,
+            zero_padded: false,
         })
     }
 
-    pub fn read_bit(&mut self) -> std::io::Result<bool> {
+    /// Decoder that treats `input` as followed by zeros, as libvpx does for
+    /// DCT partitions whose encoder didn't flush every bit a decoder reads
+    pub fn new_zero_padded(input: &'a [u8]) -> Self {
+        let byte = |i: usize| input.get(i).copied().unwrap_or(0) as u32;
+        Self {
+            input: input.get(2..).unwrap_or_default(),
+            consumed: 2,
+            value: (byte(0) << 8) | byte(1),
+            range: 255,
+            bit_count: 0,
+            zero_padded: true,
+        }
+    }
+
+    /// Where the decoder stands, to resume it with `resume_zero_padded`
+    pub fn position(&self) -> BoolDecoderPosition {
+        BoolDecoderPosition {
+            consumed: self.consumed,
+            range: self.range,
+            value: self.value,
+            bit_count: self.bit_count,
+        }
+    }
+
+    /// Zero padded decoder continuing from `position` of an earlier decoder
+    /// over the start of `input`
+    pub fn resume_zero_padded(input: &'a [u8], position: BoolDecoderPosition) -> Self {
+        Self {
+            input: input.get(position.consumed..).unwrap_or_default(),
+            consumed: position.consumed,
+            range: position.range,
+            value: position.value,
+            bit_count: position.bit_count,
+            zero_padded: true,
+        }
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, Error> {
         self.read_bool(128)
     }
 
     #[allow(non_snake_case)]
-    pub fn read_bool(&mut self, prob: u32) -> std::io::Result<bool> {
+    pub fn read_bool(&mut self, prob: u32) -> Result<bool, Error> {
         let split: u32 = 1 + (((self.range - 1) * prob) >> 8);
         let SPLIT: u32 = split << 8;
 
@@ -149,15 +236,21 @@ impl<'a> BoolDecoder<'a> {
             if self.bit_count == 8 {
                 /* shift in new bits 8 at a time */
                 self.bit_count = 0;
-                let mut next_byte = [0u8; 1];
-                self.input.read_exact(&mut next_byte)?;
-                self.value |= next_byte[0] as u32;
+                match self.input.split_first() {
+                    Some((next_byte, rest)) => {
+                        self.input = rest;
+                        self.consumed += 1;
+                        self.value |= *next_byte as u32;
+                    }
+                    None if self.zero_padded => {}
+                    None => return Err(Error::BoolDecoderUnderrun),
+                }
             }
         }
         Ok(retval != 0)
     }
 
-    pub fn read_literal(&mut self, num_bits: u32) -> std::io::Result<u32> {
+    pub fn read_literal(&mut self, num_bits: u32) -> Result<u32, Error> {
         let mut v: u32 = 0;
 
         for _ in 0..num_bits {
@@ -167,18 +260,28 @@ impl<'a> BoolDecoder<'a> {
         Ok(v)
     }
 
-    pub fn read_signed_literal(&mut self, num_bits: u32) -> std::io::Result<i32> {
-        let mut v: i32 = 0;
-        if num_bits == 0 {
-            return Ok(0);
+    /// Reads a value coded with `tree`, where `probs[i >> 1]` is the
+    /// probability of taking the left branch at node `i`.
+    pub fn read_tree(&mut self, tree: &[i8], probs: &[u8]) -> Result<u8, Error> {
+        let mut i = 0;
+        loop {
+            let node = tree[i + self.read_bool(probs[i >> 1] as u32)? as usize];
+            if node <= 0 {
+                return Ok(-node as u8);
+            }
+            i = node as usize;
         }
+    }
+
+    /// Reads a `num_bits` magnitude followed by a sign bit, as used by the
+    /// frame header's delta fields.
+    pub fn read_signed_literal(&mut self, num_bits: u32) -> Result<i32, Error> {
+        let magnitude = self.read_literal(num_bits)? as i32;
         if self.read_bool(128)? {
-            v = -1;
-        }
-        for _ in 0..num_bits {
-            v = (v << 1) + self.read_bool(128)? as i32;
+            Ok(-magnitude)
+        } else {
+            Ok(magnitude)
         }
-        Ok(v)
     }
 }
 
@@ -205,3 +308,39 @@ fn roundtrip() {
 
     assert_eq!(d, decoded);
 }
+
+#[test]
+fn roundtrip_literals() {
+    let mut encoder = BoolEncoder::new();
+    encoder.write_literal(7, 93);
+    encoder.write_signed_literal(4, -11);
+    encoder.write_signed_literal(6, 42);
+    encoder.write_signed_literal(4, 0);
+
+    let out = encoder.flush();
+    let mut decoder = BoolDecoder::new(&out).unwrap();
+
+    assert_eq!(decoder.read_literal(7).unwrap(), 93);
+    assert_eq!(decoder.read_signed_literal(4).unwrap(), -11);
+    assert_eq!(decoder.read_signed_literal(6).unwrap(), 42);
+    assert_eq!(decoder.read_signed_literal(4).unwrap(), 0);
+}
+
+#[test]
+fn roundtrip_tree() {
+    //  https://datatracker.ietf.org/doc/html/rfc6386#section-8.1
+    const TREE: [i8; 6] = [-0, 2, -1, 4, -2, -3];
+    const PROBS: [u8; 3] = [30, 200, 90];
+
+    let mut encoder = BoolEncoder::new();
+    for value in [3, 0, 2, 1, 1, 3] {
+        encoder.write_tree(&TREE, &PROBS, value);
+    }
+
+    let out = encoder.flush();
+    let mut decoder = BoolDecoder::new(&out).unwrap();
+
+    for value in [3, 0, 2, 1, 1, 3] {
+        assert_eq!(decoder.read_tree(&TREE, &PROBS).unwrap(), value);
+    }
+}
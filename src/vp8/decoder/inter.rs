@@ -0,0 +1,220 @@
+//! Inter prediction, https://datatracker.ietf.org/doc/html/rfc6386#section-18
+
+use super::tables::{BILINEAR_FILTERS, SUBPEL_FILTERS};
+use super::{FrameBuffer, Plane};
+use crate::vp8::MotionVector;
+
+/// Subpixel interpolation selected by the frame tag's version
+#[derive(Debug, Clone, Copy)]
+pub struct Interpolation {
+    filters: &'static [[i32; 6]; 8],
+    /// Version 3 streams drop the fractional part of chroma vectors
+    full_pixel: bool,
+}
+
+impl Interpolation {
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.1
+    pub fn new(version: u8) -> Self {
+        Self {
+            filters: if version == 0 {
+                &SUBPEL_FILTERS
+            } else {
+                &BILINEAR_FILTERS
+            },
+            full_pixel: version == 3,
+        }
+    }
+}
+
+/// Predicts a macroblock from `reference`, per subblock for split mode
+pub fn predict_macroblock(
+    reference: &FrameBuffer,
+    frame: &mut FrameBuffer,
+    mb_x: usize,
+    mb_y: usize,
+    mv: MotionVector,
+    subblock_mvs: Option<&[MotionVector; 16]>,
+    interpolation: Interpolation,
+) {
+    let Interpolation {
+        filters,
+        full_pixel,
+    } = interpolation;
+    let (x, y) = (mb_x * 16, mb_y * 16);
+    let (cx, cy) = (mb_x * 8, mb_y * 8);
+
+    match subblock_mvs {
+        None => {
+            predict_block(&reference.y, &mut frame.y, x, y, 16, mv, filters);
+            let mv = chroma_mv(mv, full_pixel);
+            predict_block(&reference.u, &mut frame.u, cx, cy, 8, mv, filters);
+            predict_block(&reference.v, &mut frame.v, cx, cy, 8, mv, filters);
+        }
+        Some(mvs) => {
+            for (b, mv) in mvs.iter().enumerate() {
+                let (bx, by) = (x + b % 4 * 4, y + b / 4 * 4);
+                predict_block(&reference.y, &mut frame.y, bx, by, 4, *mv, filters);
+            }
+            //  each chroma subblock covers 4 luma subblocks
+            for b in 0..4 {
+                let luma = b / 2 * 8 + b % 2 * 2;
+                let mv = split_chroma_mv(
+                    [mvs[luma], mvs[luma + 1], mvs[luma + 4], mvs[luma + 5]],
+                    full_pixel,
+                );
+                let (bx, by) = (cx + b % 2 * 4, cy + b / 2 * 4);
+                predict_block(&reference.u, &mut frame.u, bx, by, 4, mv, filters);
+                predict_block(&reference.v, &mut frame.v, bx, by, 4, mv, filters);
+            }
+        }
+    }
+}
+
+/// Halves a luma vector, rounding away from zero. Vectors are already in
+/// 1/8th pixels so chroma keeps full precision.
+fn chroma_mv(mv: MotionVector, full_pixel: bool) -> MotionVector {
+    let half = |v: i16| {
+        let v = v as i32;
+        let v = (v + if v < 0 { -1 } else { 1 }) / 2;
+        mask_full_pixel(v, full_pixel)
+    };
+    MotionVector {
+        row: half(mv.row),
+        col: half(mv.col),
+    }
+}
+
+/// Averages the vectors of the 4 luma subblocks covering a chroma subblock
+fn split_chroma_mv(mvs: [MotionVector; 4], full_pixel: bool) -> MotionVector {
+    let average = |sum: i32| {
+        let sum = sum + if sum < 0 { -4 } else { 4 };
+        mask_full_pixel(sum / 8, full_pixel)
+    };
+    MotionVector {
+        row: average(mvs.iter().map(|mv| mv.row as i32).sum()),
+        col: average(mvs.iter().map(|mv| mv.col as i32).sum()),
+    }
+}
+
+fn mask_full_pixel(v: i32, full_pixel: bool) -> i16 {
+    if full_pixel {
+        (v & !7) as i16
+    } else {
+        v as i16
+    }
+}
+
+/// Predicts the `size` block at `x`, `y` with a horizontal then vertical
+/// pass of the filter selected by each vector component's fraction. Reads
+/// outside the reference plane repeat its edge pixels.
+fn predict_block(
+    reference: &Plane,
+    dst: &mut Plane,
+    x: usize,
+    y: usize,
+    size: usize,
+    mv: MotionVector,
+    filters: &[[i32; 6]; 8],
+) {
+    let src_x = x as isize + (mv.col >> 3) as isize;
+    let src_y = y as isize + (mv.row >> 3) as isize;
+    let horizontal = &filters[(mv.col & 7) as usize];
+    let vertical = &filters[(mv.row & 7) as usize];
+    let apply = |taps: &[i32; 6], pixel: &dyn Fn(isize) -> i32| {
+        let sum: i32 = (0..6).map(|k| taps[k] * pixel(k as isize - 2)).sum();
+        ((sum + 64) >> 7).clamp(0, 255)
+    };
+
+    //  the vertical pass needs 2 rows above and 3 below the block
+    let mut first_pass = [[0i32; 16]; 16 + 5];
+    for (row, out) in first_pass.iter_mut().take(size + 5).enumerate() {
+        let sy = src_y + row as isize - 2;
+        for (col, out) in out.iter_mut().take(size).enumerate() {
+            let sx = src_x + col as isize;
+            *out = apply(horizontal, &|k| reference.at(sx + k, sy) as i32);
+        }
+    }
+
+    for row in 0..size {
+        let start = (y + row) * dst.width + x;
+        for (col, out) in dst.data[start..start + size].iter_mut().enumerate() {
+            *out = apply(vertical, &|k| {
+                first_pass[(row as isize + 2 + k) as usize][col]
+            }) as u8;
+        }
+    }
+}
+
+#[test]
+fn predict_from_ramp() {
+    //  luma rises 4 per column, so filters reproduce it at any fraction
+    let mut reference = FrameBuffer::new(64, 32);
+    for (i, pixel) in reference.y.data.iter_mut().enumerate() {
+        *pixel = (i % 64 * 4) as u8;
+    }
+    reference.u.data.fill(50);
+    reference.v.data.fill(70);
+    let expected =
+        |offset: usize| -> Vec<u8> { (16..32).map(|x| (x * 4 + offset) as u8).collect() };
+
+    for (version, mv, offset) in [(0, 8, 4), (0, 4, 2), (1, 4, 2), (1, 16, 8)] {
+        let mut frame = FrameBuffer::new(64, 32);
+        let mv = MotionVector { row: 0, col: mv };
+        predict_macroblock(
+            &reference,
+            &mut frame,
+            1,
+            1,
+            mv,
+            None,
+            Interpolation::new(version),
+        );
+        for row in frame.y.data.chunks_exact(64).skip(16) {
+            assert_eq!(
+                row[16..32],
+                expected(offset),
+                "version {} mv {:?}",
+                version,
+                mv
+            );
+        }
+        for row in frame.u.data.chunks_exact(32).skip(8) {
+            assert_eq!(row[8..16], [50; 8]);
+        }
+        assert!(frame
+            .v
+            .data
+            .chunks_exact(32)
+            .skip(8)
+            .all(|row| row[8..16] == [70; 8]));
+    }
+
+    //  split macroblocks predict each subblock with its own vector
+    let mut frame = FrameBuffer::new(64, 32);
+    let mut mvs = [MotionVector { row: 0, col: 0 }; 16];
+    mvs[1].col = 8;
+    predict_macroblock(
+        &reference,
+        &mut frame,
+        1,
+        1,
+        MotionVector { row: 0, col: 0 },
+        Some(&mvs),
+        Interpolation::new(0),
+    );
+    let row = &frame.y.data[16 * 64..17 * 64];
+    assert_eq!(row[16..24], [64, 68, 72, 76, 84, 88, 92, 96]);
+}
+
+#[test]
+fn chroma_vectors() {
+    let mv = |row, col| MotionVector { row, col };
+    assert_eq!(chroma_mv(mv(-3, 5), false), mv(-2, 3));
+    //  version 3 keeps whole chroma pixels only
+    assert_eq!(chroma_mv(mv(-3, 21), true), mv(-8, 8));
+    assert_eq!(split_chroma_mv([mv(1, 8); 4], false), mv(1, 4));
+    assert_eq!(
+        split_chroma_mv([mv(-4, 0), mv(-4, 0), mv(-4, 0), mv(-3, 0)], false),
+        mv(-2, 0)
+    );
+}
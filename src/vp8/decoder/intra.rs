@@ -0,0 +1,330 @@
+//! Intra prediction, https://datatracker.ietf.org/doc/html/rfc6386#section-12
+//!
+//! Macroblocks are predicted in a workspace holding the row above and the
+//! column to the left, taken from the frame before loop filtering. Edges
+//! outside the frame are 127 above and 129 to the left.
+
+use super::Plane;
+use crate::vp8::{ChromaMode, LumaMode, SubblockMode};
+
+//  workspaces have a one pixel border above and to the left, luma also
+//  has the 4 pixels above and to the right used by subblock prediction
+const LUMA_STRIDE: usize = 1 + 16 + 4;
+const CHROMA_STRIDE: usize = 1 + 8;
+
+/// Predicts a luma macroblock and adds its residual, `blocks` being the
+/// inverse transformed luma blocks
+pub fn predict_luma(
+    plane: &mut Plane,
+    mb_x: usize,
+    mb_y: usize,
+    mode: LumaMode,
+    subblocks: &[SubblockMode; 16],
+    blocks: &[[i32; 16]],
+) {
+    let mut ws = [0u8; (1 + 16) * LUMA_STRIDE];
+    let (x, y) = (mb_x * 16, mb_y * 16);
+    fill_edges(plane, &mut ws, x, y, 16, LUMA_STRIDE);
+
+    //  the rightmost macroblock repeats the last pixel above it, like the
+    //  right border libvpx extends each row by
+    if mb_y > 0 {
+        for i in 0..4 {
+            let above_right = x + 16 + i;
+            ws[17 + i] = if above_right < plane.width {
+                plane.data[(y - 1) * plane.width + above_right]
+            } else {
+                ws[16]
+            };
+        }
+    } else {
+        ws[17..LUMA_STRIDE].fill(127);
+    }
+    //  subblocks on the right of lower rows also use the pixels above and to
+    //  the right of the macroblock
+    for row in [4, 8, 12] {
+        ws.copy_within(17..LUMA_STRIDE, row * LUMA_STRIDE + 17);
+    }
+
+    match mode {
+        LumaMode::Dc => predict_dc(&mut ws, 16, LUMA_STRIDE, mb_y > 0, mb_x > 0),
+        LumaMode::V => predict_v(&mut ws, 16, 1, 1, LUMA_STRIDE),
+        LumaMode::H => predict_h(&mut ws, 16, 1, 1, LUMA_STRIDE),
+        LumaMode::Tm => predict_tm(&mut ws, 16, 1, 1, LUMA_STRIDE),
+        LumaMode::B => {
+            for (b, block) in blocks.iter().enumerate() {
+                let (x0, y0) = (1 + b % 4 * 4, 1 + b / 4 * 4);
+                predict_subblock(&mut ws, subblocks[b], x0, y0);
+                add_residual(&mut ws, block, x0, y0, LUMA_STRIDE);
+            }
+        }
+    }
+    if mode != LumaMode::B {
+        for (b, block) in blocks.iter().enumerate() {
+            add_residual(&mut ws, block, 1 + b % 4 * 4, 1 + b / 4 * 4, LUMA_STRIDE);
+        }
+    }
+
+    store(plane, &ws, x, y, 16, LUMA_STRIDE);
+}
+
+/// Predicts a macroblock of one chroma plane and adds its 4 residual blocks
+pub fn predict_chroma(
+    plane: &mut Plane,
+    mb_x: usize,
+    mb_y: usize,
+    mode: ChromaMode,
+    blocks: &[[i32; 16]],
+) {
+    let mut ws = [0u8; (1 + 8) * CHROMA_STRIDE];
+    let (x, y) = (mb_x * 8, mb_y * 8);
+    fill_edges(plane, &mut ws, x, y, 8, CHROMA_STRIDE);
+
+    match mode {
+        ChromaMode::Dc => predict_dc(&mut ws, 8, CHROMA_STRIDE, mb_y > 0, mb_x > 0),
+        ChromaMode::V => predict_v(&mut ws, 8, 1, 1, CHROMA_STRIDE),
+        ChromaMode::H => predict_h(&mut ws, 8, 1, 1, CHROMA_STRIDE),
+        ChromaMode::Tm => predict_tm(&mut ws, 8, 1, 1, CHROMA_STRIDE),
+    }
+    for (b, block) in blocks.iter().enumerate() {
+        add_residual(&mut ws, block, 1 + b % 2 * 4, 1 + b / 2 * 4, CHROMA_STRIDE);
+    }
+
+    store(plane, &ws, x, y, 8, CHROMA_STRIDE);
+}
+
+/// Copies the above row, left column and above-left pixel of the `size`
+/// block at `x`, `y` into the workspace border
+fn fill_edges(plane: &Plane, ws: &mut [u8], x: usize, y: usize, size: usize, stride: usize) {
+    let pixel = |x: usize, y: usize| plane.data[y * plane.width + x];
+
+    ws[0] = match (x, y) {
+        (_, 0) => 127,
+        (0, _) => 129,
+        _ => pixel(x - 1, y - 1),
+    };
+    for i in 0..size {
+        ws[1 + i] = if y == 0 { 127 } else { pixel(x + i, y - 1) };
+        ws[(1 + i) * stride] = if x == 0 { 129 } else { pixel(x - 1, y + i) };
+    }
+}
+
+fn store(plane: &mut Plane, ws: &[u8], x: usize, y: usize, size: usize, stride: usize) {
+    for row in 0..size {
+        let start = (y + row) * plane.width + x;
+        let ws_start = (1 + row) * stride + 1;
+        plane.data[start..start + size].copy_from_slice(&ws[ws_start..ws_start + size]);
+    }
+}
+
+fn add_residual(ws: &mut [u8], block: &[i32; 16], x0: usize, y0: usize, stride: usize) {
+    for (row, residual) in block.chunks_exact(4).enumerate() {
+        let start = (y0 + row) * stride + x0;
+        for (pixel, residual) in ws[start..start + 4].iter_mut().zip(residual) {
+            *pixel = (*pixel as i32 + residual).clamp(0, 255) as u8;
+        }
+    }
+}
+
+fn predict_v(ws: &mut [u8], size: usize, x0: usize, y0: usize, stride: usize) {
+    for y in 0..size {
+        let above = (y0 - 1) * stride + x0;
+        ws.copy_within(above..above + size, (y0 + y) * stride + x0);
+    }
+}
+
+fn predict_h(ws: &mut [u8], size: usize, x0: usize, y0: usize, stride: usize) {
+    for y in 0..size {
+        let start = (y0 + y) * stride + x0;
+        let left = ws[start - 1];
+        ws[start..start + size].fill(left);
+    }
+}
+
+/// Averages the edges that are inside the frame, 128 at the top left
+fn predict_dc(ws: &mut [u8], size: usize, stride: usize, above: bool, left: bool) {
+    let mut sum = 0;
+    let mut shift = size.trailing_zeros() - 1;
+    if above {
+        sum += ws[1..=size].iter().map(|p| *p as u32).sum::<u32>();
+        shift += 1;
+    }
+    if left {
+        sum += (1..=size).map(|y| ws[y * stride] as u32).sum::<u32>();
+        shift += 1;
+    }
+    let dc = if above || left {
+        ((sum + (1 << (shift - 1))) >> shift) as u8
+    } else {
+        128
+    };
+
+    for y in 1..=size {
+        ws[y * stride + 1..y * stride + 1 + size].fill(dc);
+    }
+}
+
+fn predict_tm(ws: &mut [u8], size: usize, x0: usize, y0: usize, stride: usize) {
+    let above_left = ws[(y0 - 1) * stride + x0 - 1] as i32;
+    for y in 0..size {
+        let left = ws[(y0 + y) * stride + x0 - 1] as i32;
+        for x in 0..size {
+            let above = ws[(y0 - 1) * stride + x0 + x] as i32;
+            ws[(y0 + y) * stride + x0 + x] = (left + above - above_left).clamp(0, 255) as u8;
+        }
+    }
+}
+
+fn avg2(a: u8, b: u8) -> u8 {
+    ((a as u16 + b as u16 + 1) >> 1) as u8
+}
+
+fn avg3(a: u8, b: u8, c: u8) -> u8 {
+    ((a as u16 + 2 * b as u16 + c as u16 + 2) >> 2) as u8
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-12.3
+fn predict_subblock(ws: &mut [u8], mode: SubblockMode, x0: usize, y0: usize) {
+    const S: usize = LUMA_STRIDE;
+    let above_left = ws[(y0 - 1) * S + x0 - 1];
+    let mut above = [0u8; 8];
+    above.copy_from_slice(&ws[(y0 - 1) * S + x0..(y0 - 1) * S + x0 + 8]);
+    let left: [u8; 4] = std::array::from_fn(|i| ws[(y0 + i) * S + x0 - 1]);
+    //  left column bottom to top, the above-left pixel, then the above row
+    let edge = [
+        left[3], left[2], left[1], left[0], above_left, above[0], above[1], above[2], above[3],
+    ];
+
+    let mut out = [[0u8; 4]; 4];
+    match mode {
+        SubblockMode::Dc => {
+            let sum = above[..4]
+                .iter()
+                .chain(&left)
+                .map(|p| *p as u32)
+                .sum::<u32>();
+            out = [[((sum + 4) >> 3) as u8; 4]; 4];
+        }
+        SubblockMode::Tm => {
+            for (y, row) in out.iter_mut().enumerate() {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let tm = left[y] as i32 + above[x] as i32 - above_left as i32;
+                    *pixel = tm.clamp(0, 255) as u8;
+                }
+            }
+        }
+        SubblockMode::Ve => {
+            let row = [
+                avg3(above_left, above[0], above[1]),
+                avg3(above[0], above[1], above[2]),
+                avg3(above[1], above[2], above[3]),
+                avg3(above[2], above[3], above[4]),
+            ];
+            out = [row; 4];
+        }
+        SubblockMode::He => {
+            let column = [
+                avg3(above_left, left[0], left[1]),
+                avg3(left[0], left[1], left[2]),
+                avg3(left[1], left[2], left[3]),
+                avg3(left[2], left[3], left[3]),
+            ];
+            for (row, value) in out.iter_mut().zip(column) {
+                *row = [value; 4];
+            }
+        }
+        SubblockMode::Ld => {
+            let diagonal: [u8; 7] =
+                std::array::from_fn(|i| avg3(above[i], above[i + 1], above[(i + 2).min(7)]));
+            for (y, row) in out.iter_mut().enumerate() {
+                row.copy_from_slice(&diagonal[y..y + 4]);
+            }
+        }
+        SubblockMode::Rd => {
+            let diagonal: [u8; 7] =
+                std::array::from_fn(|i| avg3(edge[i], edge[i + 1], edge[i + 2]));
+            for (y, row) in out.iter_mut().enumerate() {
+                row.copy_from_slice(&diagonal[3 - y..7 - y]);
+            }
+        }
+        SubblockMode::Vr => {
+            let e = edge;
+            out[3][0] = avg3(e[1], e[2], e[3]);
+            out[2][0] = avg3(e[2], e[3], e[4]);
+            out[3][1] = avg3(e[3], e[4], e[5]);
+            out[1][0] = out[3][1];
+            out[2][1] = avg2(e[4], e[5]);
+            out[0][0] = out[2][1];
+            out[3][2] = avg3(e[4], e[5], e[6]);
+            out[1][1] = out[3][2];
+            out[2][2] = avg2(e[5], e[6]);
+            out[0][1] = out[2][2];
+            out[3][3] = avg3(e[5], e[6], e[7]);
+            out[1][2] = out[3][3];
+            out[2][3] = avg2(e[6], e[7]);
+            out[0][2] = out[2][3];
+            out[1][3] = avg3(e[6], e[7], e[8]);
+            out[0][3] = avg2(e[7], e[8]);
+        }
+        SubblockMode::Vl => {
+            let a = above;
+            out[0][0] = avg2(a[0], a[1]);
+            out[1][0] = avg3(a[0], a[1], a[2]);
+            out[2][0] = avg2(a[1], a[2]);
+            out[0][1] = out[2][0];
+            out[1][1] = avg3(a[1], a[2], a[3]);
+            out[3][0] = out[1][1];
+            out[2][1] = avg2(a[2], a[3]);
+            out[0][2] = out[2][1];
+            out[3][1] = avg3(a[2], a[3], a[4]);
+            out[1][2] = out[3][1];
+            out[2][2] = avg2(a[3], a[4]);
+            out[0][3] = out[2][2];
+            out[3][2] = avg3(a[3], a[4], a[5]);
+            out[1][3] = out[3][2];
+            //  the last two break the pattern
+            out[2][3] = avg3(a[4], a[5], a[6]);
+            out[3][3] = avg3(a[5], a[6], a[7]);
+        }
+        SubblockMode::Hd => {
+            let e = edge;
+            out[3][0] = avg2(e[0], e[1]);
+            out[3][1] = avg3(e[0], e[1], e[2]);
+            out[2][0] = avg2(e[1], e[2]);
+            out[3][2] = out[2][0];
+            out[2][1] = avg3(e[1], e[2], e[3]);
+            out[3][3] = out[2][1];
+            out[2][2] = avg2(e[2], e[3]);
+            out[1][0] = out[2][2];
+            out[2][3] = avg3(e[2], e[3], e[4]);
+            out[1][1] = out[2][3];
+            out[1][2] = avg2(e[3], e[4]);
+            out[0][0] = out[1][2];
+            out[1][3] = avg3(e[3], e[4], e[5]);
+            out[0][1] = out[1][3];
+            out[0][2] = avg3(e[4], e[5], e[6]);
+            out[0][3] = avg3(e[5], e[6], e[7]);
+        }
+        SubblockMode::Hu => {
+            let l = left;
+            out[0][0] = avg2(l[0], l[1]);
+            out[0][1] = avg3(l[0], l[1], l[2]);
+            out[0][2] = avg2(l[1], l[2]);
+            out[1][0] = out[0][2];
+            out[0][3] = avg3(l[1], l[2], l[3]);
+            out[1][1] = out[0][3];
+            out[1][2] = avg2(l[2], l[3]);
+            out[2][0] = out[1][2];
+            out[1][3] = avg3(l[2], l[3], l[3]);
+            out[2][1] = out[1][3];
+            out[2][2] = l[3];
+            out[2][3] = l[3];
+            out[3] = [l[3]; 4];
+        }
+    }
+
+    for (y, row) in out.iter().enumerate() {
+        let start = (y0 + y) * S + x0;
+        ws[start..start + 4].copy_from_slice(row);
+    }
+}
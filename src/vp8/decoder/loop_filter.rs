@@ -0,0 +1,292 @@
+//! Loop filter, https://datatracker.ietf.org/doc/html/rfc6386#section-15
+
+use super::{FrameBuffer, Plane};
+use crate::vp8::FilterType;
+
+/// Loop filter inputs of a macroblock
+#[derive(Debug, Clone, Copy)]
+pub struct MacroblockFilter {
+    /// Level after segment and loop filter delta adjustments, 0 to skip
+    pub level: u8,
+    /// Whether edges between subblocks are filtered, which is skipped for
+    /// macroblocks without coefficients unless they use B_PRED or SPLITMV
+    pub inner_edges: bool,
+}
+
+/// Edge limits derived from a filter level,
+/// https://datatracker.ietf.org/doc/html/rfc6386#section-15.2
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    macroblock_edge: i32,
+    subblock_edge: i32,
+    interior: i32,
+    hev_threshold: i32,
+}
+
+impl Limits {
+    fn new(level: u8, sharpness: u8, key_frame: bool) -> Self {
+        let level = level as i32;
+        let mut interior = level >> ((sharpness > 0) as i32 + (sharpness > 4) as i32);
+        if sharpness > 0 {
+            interior = interior.min(9 - sharpness as i32);
+        }
+        let interior = interior.max(1);
+
+        let hev_threshold = match (key_frame, level) {
+            (_, 40..) => 2 + !key_frame as i32,
+            (false, 20..) => 2,
+            (_, 15..) => 1,
+            _ => 0,
+        };
+
+        Self {
+            macroblock_edge: (level + 2) * 2 + interior,
+            subblock_edge: level * 2 + interior,
+            interior,
+            hev_threshold,
+        }
+    }
+}
+
+/// Filters the whole frame in macroblock order. Each macroblock filters its
+/// left edge, inner vertical edges, top edge then inner horizontal edges.
+pub fn filter_frame(
+    frame: &mut FrameBuffer,
+    macroblocks: &[MacroblockFilter],
+    mb_cols: usize,
+    filter_type: FilterType,
+    sharpness: u8,
+    key_frame: bool,
+) {
+    for (idx, mb) in macroblocks.iter().enumerate() {
+        if mb.level == 0 {
+            continue;
+        }
+        let (mb_x, mb_y) = (idx % mb_cols, idx / mb_cols);
+        let limits = Limits::new(mb.level, sharpness, key_frame);
+
+        match filter_type {
+            FilterType::Normal => {
+                filter_normal(&mut frame.y, mb_x * 16, mb_y * 16, 16, mb, &limits);
+                filter_normal(&mut frame.u, mb_x * 8, mb_y * 8, 8, mb, &limits);
+                filter_normal(&mut frame.v, mb_x * 8, mb_y * 8, 8, mb, &limits);
+            }
+            //  the simple filter leaves chroma alone
+            FilterType::Simple => filter_simple(&mut frame.y, mb_x * 16, mb_y * 16, mb, &limits),
+        }
+    }
+}
+
+fn filter_normal(
+    plane: &mut Plane,
+    x: usize,
+    y: usize,
+    size: usize,
+    mb: &MacroblockFilter,
+    limits: &Limits,
+) {
+    let stride = plane.width;
+    let data = &mut plane.data;
+    let origin = y * stride + x;
+    //  edges are 4 pixels apart, across `step`, and run along `along`
+    let edge = |data: &mut [u8], start: usize, step: usize, along: usize, inner: bool| {
+        for i in 0..size {
+            let at = start + i * along;
+            if inner {
+                subblock_filter(data, at, step, limits);
+            } else {
+                macroblock_filter(data, at, step, limits);
+            }
+        }
+    };
+
+    if x > 0 {
+        edge(data, origin, 1, stride, false);
+    }
+    if mb.inner_edges {
+        for offset in (4..size).step_by(4) {
+            edge(data, origin + offset, 1, stride, true);
+        }
+    }
+    if y > 0 {
+        edge(data, origin, stride, 1, false);
+    }
+    if mb.inner_edges {
+        for offset in (4..size).step_by(4) {
+            edge(data, origin + offset * stride, stride, 1, true);
+        }
+    }
+}
+
+fn filter_simple(plane: &mut Plane, x: usize, y: usize, mb: &MacroblockFilter, limits: &Limits) {
+    let stride = plane.width;
+    let data = &mut plane.data;
+    let origin = y * stride + x;
+    let edge = |data: &mut [u8], start: usize, step: usize, along: usize, limit: i32| {
+        for i in 0..16 {
+            simple_filter(data, start + i * along, step, limit);
+        }
+    };
+
+    if x > 0 {
+        edge(data, origin, 1, stride, limits.macroblock_edge);
+    }
+    if mb.inner_edges {
+        for offset in [4, 8, 12] {
+            edge(data, origin + offset, 1, stride, limits.subblock_edge);
+        }
+    }
+    if y > 0 {
+        edge(data, origin, stride, 1, limits.macroblock_edge);
+    }
+    if mb.inner_edges {
+        for offset in [4, 8, 12] {
+            edge(
+                data,
+                origin + offset * stride,
+                stride,
+                1,
+                limits.subblock_edge,
+            );
+        }
+    }
+}
+
+/// The 8 pixels across an edge, p3 to q3
+fn load(data: &[u8], at: usize, step: usize) -> [i32; 8] {
+    std::array::from_fn(|i| data[at + i * step - 4 * step] as i32)
+}
+
+fn clamp_s8(v: i32) -> i32 {
+    v.clamp(-128, 127)
+}
+
+fn to_s8(pixel: i32) -> i32 {
+    pixel - 128
+}
+
+fn to_u8(value: i32) -> u8 {
+    (clamp_s8(value) + 128) as u8
+}
+
+/// Difference across the edge compared against the edge limit
+fn edge_difference(p1: i32, p0: i32, q0: i32, q1: i32) -> i32 {
+    (p0 - q0).abs() * 2 + (p1 - q1).abs() / 2
+}
+
+fn normal_threshold(p: &[i32; 8], edge_limit: i32, interior: i32) -> bool {
+    edge_difference(p[2], p[3], p[4], p[5]) <= edge_limit
+        && p.windows(2)
+            .enumerate()
+            .all(|(i, w)| i == 3 || (w[0] - w[1]).abs() <= interior)
+}
+
+fn high_edge_variance(p: &[i32; 8], threshold: i32) -> bool {
+    (p[2] - p[3]).abs() > threshold || (p[5] - p[4]).abs() > threshold
+}
+
+/// Adjusts p0 and q0, returning the filter value `a` used for the outer taps
+fn common_adjust(data: &mut [u8], at: usize, step: usize, use_outer_taps: bool) -> i32 {
+    let p1 = to_s8(data[at - 2 * step] as i32);
+    let p0 = to_s8(data[at - step] as i32);
+    let q0 = to_s8(data[at] as i32);
+    let q1 = to_s8(data[at + step] as i32);
+
+    let outer = if use_outer_taps { clamp_s8(p1 - q1) } else { 0 };
+    let a = clamp_s8(outer + 3 * (q0 - p0));
+    let f1 = clamp_s8(a + 4) >> 3;
+    let f2 = clamp_s8(a + 3) >> 3;
+    data[at] = to_u8(q0 - f1);
+    data[at - step] = to_u8(p0 + f2);
+    f1
+}
+
+fn simple_filter(data: &mut [u8], at: usize, step: usize, edge_limit: i32) {
+    let pixel = |offset: isize| data[(at as isize + offset * step as isize) as usize] as i32;
+    if edge_difference(pixel(-2), pixel(-1), pixel(0), pixel(1)) <= edge_limit {
+        common_adjust(data, at, step, true);
+    }
+}
+
+fn subblock_filter(data: &mut [u8], at: usize, step: usize, limits: &Limits) {
+    let p = load(data, at, step);
+    if !normal_threshold(&p, limits.subblock_edge, limits.interior) {
+        return;
+    }
+    let hev = high_edge_variance(&p, limits.hev_threshold);
+    let a = (common_adjust(data, at, step, hev) + 1) >> 1;
+    if !hev {
+        data[at + step] = to_u8(to_s8(p[5]) - a);
+        data[at - 2 * step] = to_u8(to_s8(p[2]) + a);
+    }
+}
+
+fn macroblock_filter(data: &mut [u8], at: usize, step: usize, limits: &Limits) {
+    let p = load(data, at, step);
+    if !normal_threshold(&p, limits.macroblock_edge, limits.interior) {
+        return;
+    }
+    if high_edge_variance(&p, limits.hev_threshold) {
+        common_adjust(data, at, step, true);
+        return;
+    }
+
+    let [_, p2, p1, p0, q0, q1, q2, _] = p.map(to_s8);
+    let w = clamp_s8(clamp_s8(p1 - q1) + 3 * (q0 - p0));
+    //  roughly 3/7, 2/7 and 1/7 of the difference across the edge
+    for (taps, distance) in [(27, 0), (18, 1), (9, 2)] {
+        let a = clamp_s8((taps * w + 63) >> 7);
+        let (p, q) = [(p0, q0), (p1, q1), (p2, q2)][distance];
+        data[at + distance * step] = to_u8(q - a);
+        data[at - (distance + 1) * step] = to_u8(p + a);
+    }
+}
+
+#[test]
+fn filter_macroblock_edge() {
+    //  two macroblocks side by side, with a step between them
+    let step_frame = |left: u8, right: u8| {
+        let mut frame = FrameBuffer::new(32, 16);
+        for row in frame.y.data.chunks_exact_mut(32) {
+            row[..16].fill(left);
+            row[16..].fill(right);
+        }
+        frame
+    };
+    let filter = |frame: &mut FrameBuffer, level: u8, filter_type: FilterType| {
+        let mb = MacroblockFilter {
+            level,
+            inner_edges: false,
+        };
+        filter_frame(frame, &[mb; 2], 2, filter_type, 0, true);
+    };
+
+    let mut frame = step_frame(60, 80);
+    filter(&mut frame, 20, FilterType::Normal);
+    for row in frame.y.data.chunks_exact(32) {
+        //  three pixels either side are smoothed symmetrically
+        assert!(row[15] > 60 && row[16] < 80, "{:?}", row);
+        for i in 0..3 {
+            assert_eq!(row[15 - i] as u32 + row[16 + i] as u32, 140);
+        }
+        assert!(row[..13].iter().all(|p| *p == 60));
+        assert!(row[19..].iter().all(|p| *p == 80));
+    }
+
+    //  the simple filter only touches the pixels next to the edge
+    let mut frame = step_frame(60, 80);
+    filter(&mut frame, 20, FilterType::Simple);
+    for row in frame.y.data.chunks_exact(32) {
+        assert!(row[15] > 60 && row[16] < 80);
+        assert!(row[..15].iter().all(|p| *p == 60));
+        assert!(row[17..].iter().all(|p| *p == 80));
+    }
+
+    //  steps beyond the edge limit are real edges, and level 0 skips
+    //  filtering
+    for (left, right, level) in [(0, 255, 20), (60, 80, 0)] {
+        let mut frame = step_frame(left, right);
+        filter(&mut frame, level, FilterType::Normal);
+        assert_eq!(frame.y.data, step_frame(left, right).y.data);
+    }
+}
@@ -0,0 +1,478 @@
+//! Software VP8 decoder, enabled by the `decoder` feature. Reconstruction
+//! follows libvpx so output can be checked against the md5s of the
+//! vp8-test-vectors.
+
+use std::sync::Arc;
+
+use self::inter::Interpolation;
+use self::loop_filter::MacroblockFilter;
+use self::residual::{DequantFactors, Residual, TokenContext};
+use super::bitcode::BoolDecoder;
+use super::{
+    EffectiveParams, FrameBufferUpdate, InterMode, LumaMode, MacroblockHeader, Prediction,
+    RefFrame, StreamFrame, Vp8StreamState,
+};
+use crate::Error;
+
+mod inter;
+mod intra;
+mod loop_filter;
+mod residual;
+mod tables;
+mod transform;
+
+/// Decoded frame cropped to its display size, with chroma planes of
+/// `(width + 1) / 2` by `(height + 1) / 2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct I420Frame {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl I420Frame {
+    pub fn chroma_width(&self) -> usize {
+        self.width.div_ceil(2)
+    }
+
+    pub fn chroma_height(&self) -> usize {
+        self.height.div_ceil(2)
+    }
+
+    /// Planes back to back, as written by `vpxdec --i420`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.y[..], &self.u, &self.v].concat()
+    }
+}
+
+/// Decodes frames in decode order, keeping the reference buffers between
+/// them
+#[derive(Default)]
+pub struct Decoder {
+    state: Vp8StreamState,
+    references: References,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the next frame, returning it unless it is hidden (show_frame
+    /// is 0), in which case it only updates the reference buffers.
+    pub fn decode(&mut self, frame: &[u8]) -> Result<Option<I420Frame>, Error> {
+        let StreamFrame {
+            info,
+            params,
+            macroblocks,
+        } = self.state.push_with_macroblocks(frame)?;
+        let macroblocks = macroblocks.expect("decoded by push_with_macroblocks");
+        let (width, height) = params.dimensions.ok_or(Error::MissingKeyFrame)?;
+        let (width, height) = (width as usize, height as usize);
+        let (mb_cols, mb_rows) = (width.div_ceil(16), height.div_ceil(16));
+
+        let partitions = info.partitions?;
+        let mut token_decoders: Vec<_> = partitions
+            .tokens
+            .iter()
+            .map(|p| BoolDecoder::new_zero_padded(&frame[p.offset..p.end()]))
+            .collect();
+        let header = &info.header;
+        let factors = params
+            .quantizer
+            .map(|q| DequantFactors::new(q, &header.quant_indices));
+        let interpolation = Interpolation::new(info.tag.version);
+
+        let mut buffer = FrameBuffer::new(mb_cols * 16, mb_rows * 16);
+        let mut above = vec![TokenContext::default(); mb_cols];
+        let mut filters = Vec::with_capacity(macroblocks.len());
+        for (mb_y, row) in macroblocks.chunks_exact(mb_cols).enumerate() {
+            let decoder = &mut token_decoders[mb_y % partitions.tokens.len()];
+            let mut left = TokenContext::default();
+            for (mb_x, mb) in row.iter().enumerate() {
+                let has_y2 = mb.has_y2();
+                let segment = mb.segment_id.unwrap_or(0) as usize;
+                let mut residual = if mb.skip_coeff {
+                    above[mb_x].reset(has_y2);
+                    left.reset(has_y2);
+                    Residual::zero()
+                } else {
+                    Residual::read(
+                        decoder,
+                        &params.token_probs,
+                        &factors[segment],
+                        has_y2,
+                        &mut above[mb_x],
+                        &mut left,
+                    )?
+                };
+                residual.inverse_transform(has_y2);
+
+                match &mb.prediction {
+                    Prediction::Intra {
+                        luma,
+                        subblocks,
+                        chroma,
+                    } => {
+                        let blocks = &residual.blocks;
+                        intra::predict_luma(
+                            &mut buffer.y,
+                            mb_x,
+                            mb_y,
+                            *luma,
+                            subblocks,
+                            &blocks[..16],
+                        );
+                        intra::predict_chroma(&mut buffer.u, mb_x, mb_y, *chroma, &blocks[16..20]);
+                        intra::predict_chroma(&mut buffer.v, mb_x, mb_y, *chroma, &blocks[20..24]);
+                    }
+                    Prediction::Inter {
+                        ref_frame,
+                        mv,
+                        subblock_mvs,
+                        ..
+                    } => {
+                        let reference = self.references.get(*ref_frame)?;
+                        inter::predict_macroblock(
+                            reference,
+                            &mut buffer,
+                            mb_x,
+                            mb_y,
+                            *mv,
+                            subblock_mvs.as_ref(),
+                            interpolation,
+                        );
+                        buffer.add_residual(mb_x, mb_y, &residual.blocks);
+                    }
+                }
+
+                filters.push(MacroblockFilter {
+                    level: filter_level(&params, mb, segment),
+                    inner_edges: !has_y2 || residual.has_coeffs,
+                });
+            }
+        }
+
+        if header.loop_filter_level != 0 {
+            loop_filter::filter_frame(
+                &mut buffer,
+                &filters,
+                mb_cols,
+                header.filter_type,
+                header.sharpness_level,
+                header.is_key_frame(),
+            );
+        }
+
+        let buffer = Arc::new(buffer);
+        self.references.apply(&buffer, &header.frame_buffer_update);
+
+        Ok(info.tag.show_frame.then(|| buffer.to_i420(width, height)))
+    }
+}
+
+impl MacroblockHeader {
+    /// Whether luma DC coefficients are coded in a separate y2 block, which
+    /// all modes but B_PRED and SPLITMV do
+    fn has_y2(&self) -> bool {
+        !matches!(
+            self.prediction,
+            Prediction::Intra {
+                luma: LumaMode::B,
+                ..
+            } | Prediction::Inter {
+                mode: InterMode::Split,
+                ..
+            }
+        )
+    }
+}
+
+/// Segment loop filter level adjusted by the deltas for the macroblock's
+/// reference frame and mode
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.6
+fn filter_level(params: &EffectiveParams, mb: &MacroblockHeader, segment: usize) -> u8 {
+    let level = params.loop_filter_level[segment];
+    let Some(deltas) = &params.loop_filter_deltas else {
+        return level;
+    };
+
+    let (ref_frame, mode) = match &mb.prediction {
+        Prediction::Intra {
+            luma: LumaMode::B, ..
+        } => (0, Some(0)),
+        //  other intra modes have no mode delta
+        Prediction::Intra { .. } => (0, None),
+        Prediction::Inter {
+            ref_frame, mode, ..
+        } => {
+            let ref_frame = match ref_frame {
+                RefFrame::Last => 1,
+                RefFrame::Golden => 2,
+                RefFrame::AltRef => 3,
+            };
+            let mode = match mode {
+                InterMode::Zero => 1,
+                InterMode::Split => 3,
+                _ => 2,
+            };
+            (ref_frame, Some(mode))
+        }
+    };
+
+    let level = level as i32
+        + deltas.ref_frame[ref_frame] as i32
+        + mode.map_or(0, |mode| deltas.mode[mode] as i32);
+    level.clamp(0, 63) as u8
+}
+
+/// Frame plane padded to whole macroblocks
+#[derive(Debug, Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width * height],
+        }
+    }
+
+    /// Pixel at `x`, `y` clamped to the plane, which matches libvpx reading
+    /// its extended borders
+    fn at(&self, x: isize, y: isize) -> u8 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.data[y * self.width + x]
+    }
+
+    /// Adds an inverse transformed block to the 4x4 pixels at `x`, `y`
+    fn add_residual(&mut self, x: usize, y: usize, block: &[i32; 16]) {
+        for (row, residual) in block.chunks_exact(4).enumerate() {
+            let start = (y + row) * self.width + x;
+            for (pixel, residual) in self.data[start..start + 4].iter_mut().zip(residual) {
+                *pixel = (*pixel as i32 + residual).clamp(0, 255) as u8;
+            }
+        }
+    }
+
+    fn crop(&self, width: usize, height: usize) -> Vec<u8> {
+        self.data
+            .chunks_exact(self.width)
+            .take(height)
+            .flat_map(|row| &row[..width])
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FrameBuffer {
+    y: Plane,
+    u: Plane,
+    v: Plane,
+}
+
+impl FrameBuffer {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            y: Plane::new(width, height),
+            u: Plane::new(width / 2, height / 2),
+            v: Plane::new(width / 2, height / 2),
+        }
+    }
+
+    /// Adds a macroblock's 16 luma, 4 u and 4 v residual blocks
+    fn add_residual(&mut self, mb_x: usize, mb_y: usize, blocks: &[[i32; 16]]) {
+        for (b, block) in blocks[..16].iter().enumerate() {
+            self.y
+                .add_residual(mb_x * 16 + b % 4 * 4, mb_y * 16 + b / 4 * 4, block);
+        }
+        for (b, block) in blocks[16..24].iter().enumerate() {
+            let plane = if b < 4 { &mut self.u } else { &mut self.v };
+            plane.add_residual(mb_x * 8 + b % 2 * 4, mb_y * 8 + b % 4 / 2 * 4, block);
+        }
+    }
+
+    fn to_i420(&self, width: usize, height: usize) -> I420Frame {
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        I420Frame {
+            width,
+            height,
+            y: self.y.crop(width, height),
+            u: self.u.crop(chroma_width, chroma_height),
+            v: self.v.crop(chroma_width, chroma_height),
+        }
+    }
+}
+
+/// Buffers held as last, golden and altref
+#[derive(Default)]
+struct References {
+    last: Option<Arc<FrameBuffer>>,
+    golden: Option<Arc<FrameBuffer>>,
+    altref: Option<Arc<FrameBuffer>>,
+}
+
+impl References {
+    fn get(&self, ref_frame: RefFrame) -> Result<&FrameBuffer, Error> {
+        let buffer = match ref_frame {
+            RefFrame::Last => &self.last,
+            RefFrame::Golden => &self.golden,
+            RefFrame::AltRef => &self.altref,
+        };
+        buffer.as_deref().ok_or(Error::MissingKeyFrame)
+    }
+
+    /// Same order as `ReferenceTracker`, copies to altref come first
+    fn apply(&mut self, buffer: &Arc<FrameBuffer>, update: &FrameBufferUpdate) {
+        let copy = |references: &Self, ref_frame| match ref_frame {
+            RefFrame::Last => references.last.clone(),
+            RefFrame::Golden => references.golden.clone(),
+            RefFrame::AltRef => references.altref.clone(),
+        };
+        if let Some(ref_frame) = update.copy_to_altref {
+            self.altref = copy(self, ref_frame);
+        }
+        if let Some(ref_frame) = update.copy_to_golden {
+            self.golden = copy(self, ref_frame);
+        }
+        if update.golden {
+            self.golden = Some(buffer.clone());
+        }
+        if update.altref {
+            self.altref = Some(buffer.clone());
+        }
+        if update.last {
+            self.last = Some(buffer.clone());
+        }
+    }
+}
+
+#[test]
+fn decode_skipped_key_frame() {
+    use crate::vp8::bitcode::BoolEncoder;
+    use crate::vp8::{tables, FrameTagType};
+
+    let mut encoder = BoolEncoder::new();
+    encoder.write_bit(false); // color_space
+    encoder.write_bit(false); // clamping_type
+    encoder.write_bit(false); // segmentation_enabled
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 0); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    super::encode_quant_indices(&mut encoder, &Default::default());
+    encoder.write_bit(true); // refresh_entropy_probs
+    super::encode_no_token_prob_updates(&mut encoder);
+    encoder.write_bit(true); // mb_no_coeff_skip
+    encoder.write_literal(8, 1); // prob_skip_false
+
+    //  DC_PRED without edges is 128, then H_PRED continues it while V_PRED
+    //  copies the 127 row above the frame
+    for (luma, chroma) in [
+        (tables::DC_PRED, tables::DC_PRED),
+        (tables::H_PRED, tables::V_PRED),
+    ] {
+        encoder.write_bool(1, 1);
+        encoder.write_tree(&tables::KF_YMODE_TREE, &tables::KF_YMODE_PROBS, luma as u8);
+        encoder.write_tree(
+            &tables::UV_MODE_TREE,
+            &tables::KF_UV_MODE_PROBS,
+            chroma as u8,
+        );
+    }
+
+    let frame_type = FrameTagType::KeyFrame {
+        width: 20,
+        width_scale: 0,
+        height: 16,
+        height_scale: 0,
+    };
+    let frame = super::frame_with_tag(&frame_type, encoder.flush());
+
+    let mut decoder = Decoder::new();
+    let decoded = decoder.decode(&frame).unwrap().unwrap();
+    assert_eq!((decoded.width, decoded.height), (20, 16));
+    assert_eq!(decoded.y, vec![128; 20 * 16]);
+    let chroma_row = [[128; 8].as_slice(), &[127; 2]].concat();
+    assert_eq!(decoded.u, chroma_row.repeat(8));
+    assert_eq!(decoded.v, decoded.u);
+}
+
+#[test]
+fn decode_test_frames() {
+    let ivf = std::fs::read("test_frames.ivf").unwrap();
+    let reader = crate::IvfReader::new(&ivf).unwrap();
+    let (width, height) = (reader.header().width, reader.header().height);
+    let mut decoder = Decoder::new();
+    let mut decoded = vec![];
+    for frame in reader {
+        let frame = decoder.decode(frame.unwrap().data).unwrap().unwrap();
+        assert_eq!(
+            (frame.width, frame.height),
+            (width as usize, height as usize)
+        );
+        decoded.push(frame.to_bytes());
+    }
+    assert_eq!(decoded.len(), 31);
+    //  the inter frames show motion rather than repeating the key frame
+    assert!(decoded.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+/// Compares every frame of the vp8-test-vectors
+/// (https://github.com/webmproject/vp8-test-vectors) in the directory named
+/// by `VP8_TEST_VECTORS` with the md5s next to them. Run with `--ignored`.
+#[test]
+#[ignore = "needs VP8_TEST_VECTORS"]
+fn test_vector_md5s() {
+    use md5::{Digest, Md5};
+
+    let dir = std::env::var_os("VP8_TEST_VECTORS")
+        .expect("VP8_TEST_VECTORS should name a vp8-test-vectors checkout");
+    let mut vectors: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ivf"))
+        .collect();
+    vectors.sort();
+    assert!(!vectors.is_empty(), "no .ivf files in VP8_TEST_VECTORS");
+
+    for path in vectors {
+        let ivf = std::fs::read(&path).unwrap();
+        let md5s = std::fs::read_to_string(path.with_extension("ivf.md5")).unwrap();
+        let mut md5s = md5s
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap());
+
+        let mut decoder = Decoder::new();
+        for (frame_idx, frame) in crate::IvfReader::new(&ivf).unwrap().enumerate() {
+            let frame = frame.unwrap().data;
+            if let Some(decoded) = decoder.decode(frame).unwrap() {
+                let md5 = format!("{:x}", Md5::digest(decoded.to_bytes()));
+                assert_eq!(
+                    Some(md5.as_str()),
+                    md5s.next(),
+                    "{} frame {}",
+                    path.display(),
+                    frame_idx
+                );
+            }
+        }
+        assert_eq!(
+            md5s.next(),
+            None,
+            "{} has fewer frames than md5s",
+            path.display()
+        );
+    }
+}
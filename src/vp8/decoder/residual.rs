@@ -0,0 +1,269 @@
+//! DCT token decoding and dequantization,
+//! https://datatracker.ietf.org/doc/html/rfc6386#section-13
+
+use super::tables::{AC_QUANT, COEFF_BANDS, DCT_CAT_BASE, DCT_CAT_PROBS, DC_QUANT, ZIGZAG};
+use super::transform;
+use crate::vp8::bitcode::BoolDecoder;
+use crate::vp8::{QuantIndices, TokenProbs};
+use crate::Error;
+
+//  block types indexing TokenProbs
+const Y_AFTER_Y2: usize = 0;
+const Y2: usize = 1;
+const CHROMA: usize = 2;
+const Y_WITH_DC: usize = 3;
+
+/// Index of the y2 block in `Residual::blocks` and of its token context
+const Y2_BLOCK: usize = 24;
+
+/// Dequantization factors of one segment, each as `[dc, ac]`
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-14.1
+#[derive(Debug, Clone, Copy)]
+pub struct DequantFactors {
+    y: [i32; 2],
+    y2: [i32; 2],
+    uv: [i32; 2],
+}
+
+impl DequantFactors {
+    /// `q` is the segment's y_ac_qi, the other indices are deltas from it
+    pub fn new(q: u8, indices: &QuantIndices) -> Self {
+        let index = |delta: i8| (q as i32 + delta as i32).clamp(0, 127) as usize;
+        let dc = |delta| DC_QUANT[index(delta)] as i32;
+        let ac = |delta| AC_QUANT[index(delta)] as i32;
+
+        Self {
+            y: [dc(indices.y_dc_delta), ac(0)],
+            y2: [
+                dc(indices.y2_dc_delta) * 2,
+                (ac(indices.y2_ac_delta) * 155 / 100).max(8),
+            ],
+            uv: [dc(indices.uv_dc_delta).min(132), ac(indices.uv_ac_delta)],
+        }
+    }
+}
+
+/// Whether the neighbouring blocks above or to the left of a macroblock
+/// decoded any tokens, which selects the probabilities of their first token.
+/// Holds 4 luma, 2 u and 2 v entries followed by y2, like libvpx's
+/// ENTROPY_CONTEXT_PLANES.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenContext([bool; 9]);
+
+impl TokenContext {
+    /// Clears the contexts of a macroblock without coefficients. Macroblocks
+    /// without a y2 block leave its context to the next one that has it.
+    pub fn reset(&mut self, has_y2: bool) {
+        self.0[..8].fill(false);
+        if has_y2 {
+            self.0[Y2_BLOCK - 16] = false;
+        }
+    }
+}
+
+/// Residual of a macroblock's 16 luma, 4 u and 4 v blocks followed by y2,
+/// each in raster order
+pub struct Residual {
+    pub blocks: [[i32; 16]; 25],
+    /// Set when any block decoded a token, which enables loop filtering of
+    /// the edges inside the macroblock
+    pub has_coeffs: bool,
+}
+
+impl Residual {
+    pub fn zero() -> Self {
+        Self {
+            blocks: [[0; 16]; 25],
+            has_coeffs: false,
+        }
+    }
+
+    /// Reads the dequantized coefficients of a macroblock
+    pub fn read(
+        decoder: &mut BoolDecoder,
+        probs: &TokenProbs,
+        factors: &DequantFactors,
+        has_y2: bool,
+        above: &mut TokenContext,
+        left: &mut TokenContext,
+    ) -> Result<Self, Error> {
+        let mut residual = Self::zero();
+        let mut read =
+            |block: usize, block_type: usize, first, factors, above: &mut bool, left: &mut bool| {
+                let ctx = *above as usize + *left as usize;
+                let probs = &probs[block_type];
+                let coeffs = &mut residual.blocks[block];
+                let nonzero = read_block(decoder, probs, ctx, first, factors, coeffs)?;
+                *above = nonzero;
+                *left = nonzero;
+                residual.has_coeffs |= nonzero;
+                Ok::<_, Error>(())
+            };
+
+        let (y_type, first) = if has_y2 {
+            let (above, left) = (&mut above.0[8], &mut left.0[8]);
+            read(Y2_BLOCK, Y2, 0, factors.y2, above, left)?;
+            (Y_AFTER_Y2, 1)
+        } else {
+            (Y_WITH_DC, 0)
+        };
+        for b in 0..16 {
+            let (above, left) = (&mut above.0[b % 4], &mut left.0[b / 4]);
+            read(b, y_type, first, factors.y, above, left)?;
+        }
+        //  u blocks use contexts 4 and 5, v blocks 6 and 7
+        for b in 16..24 {
+            let plane = 4 + (b - 16) / 4 * 2;
+            let (above, left) = (
+                &mut above.0[plane + b % 2],
+                &mut left.0[plane + (b / 2) % 2],
+            );
+            read(b, CHROMA, 0, factors.uv, above, left)?;
+        }
+
+        Ok(residual)
+    }
+
+    /// Replaces the coefficients of each block with its inverse transform
+    pub fn inverse_transform(&mut self, has_y2: bool) {
+        if has_y2 {
+            let dc = transform::iwht4x4(&self.blocks[Y2_BLOCK]);
+            for (block, dc) in self.blocks.iter_mut().zip(dc) {
+                block[0] = dc;
+            }
+        }
+        for block in &mut self.blocks[..24] {
+            if block.iter().any(|c| *c != 0) {
+                transform::idct4x4(block);
+            }
+        }
+    }
+}
+
+/// Reads the tokens of a block starting at position `first`, returning
+/// whether any were decoded before the end of block
+fn read_block(
+    decoder: &mut BoolDecoder,
+    probs: &[[[u8; 11]; 3]; 8],
+    mut ctx: usize,
+    first: usize,
+    factors: [i32; 2],
+    block: &mut [i32; 16],
+) -> Result<bool, Error> {
+    let mut i = first;
+    //  the tree skips the end of block branch after a zero token
+    let mut after_zero = false;
+    while i < 16 {
+        let p = &probs[COEFF_BANDS[i] as usize][ctx];
+        let mut read = |node: usize| decoder.read_bool(p[node] as u32);
+
+        if !after_zero && !read(0)? {
+            break;
+        }
+        if !read(1)? {
+            after_zero = true;
+            ctx = 0;
+            i += 1;
+            continue;
+        }
+
+        let value = if !read(2)? {
+            ctx = 1;
+            1
+        } else {
+            ctx = 2;
+            if !read(3)? {
+                if !read(4)? {
+                    2
+                } else {
+                    3 + read(5)? as i32
+                }
+            } else {
+                let cat = if !read(6)? {
+                    read(7)? as usize
+                } else {
+                    let high = read(8)? as usize;
+                    2 + high * 2 + read(9 + high)? as usize
+                };
+                let mut extra = 0;
+                for prob in DCT_CAT_PROBS[cat] {
+                    extra = (extra << 1) | decoder.read_bool(*prob as u32)? as i32;
+                }
+                DCT_CAT_BASE[cat] + extra
+            }
+        };
+        let value = if decoder.read_bit()? { -value } else { value };
+
+        block[ZIGZAG[i] as usize] = value * factors[(i > 0) as usize];
+        after_zero = false;
+        i += 1;
+    }
+
+    Ok(i > first)
+}
+
+#[test]
+fn read_y2_tokens() {
+    use crate::vp8::bitcode::BoolEncoder;
+    use crate::vp8::tables::DEFAULT_TOKEN_PROBS;
+
+    let probs = &DEFAULT_TOKEN_PROBS;
+    let mut encoder = BoolEncoder::new();
+    let write = |encoder: &mut BoolEncoder,
+                 block_type: usize,
+                 i: usize,
+                 ctx: usize,
+                 nodes: &[(usize, u8)]| {
+        let p = &probs[block_type][COEFF_BANDS[i] as usize][ctx];
+        for (node, bit) in nodes {
+            encoder.write_bool(p[*node] as u32, *bit);
+        }
+    };
+    //  y2 holds ONE, then a negative TWO, then the end of block
+    write(&mut encoder, Y2, 0, 0, &[(0, 1), (1, 1), (2, 0)]);
+    encoder.write_bool(128, 0);
+    write(
+        &mut encoder,
+        Y2,
+        1,
+        1,
+        &[(0, 1), (1, 1), (2, 1), (3, 0), (4, 0)],
+    );
+    encoder.write_bool(128, 1);
+    write(&mut encoder, Y2, 2, 2, &[(0, 0)]);
+    //  and every other block ends right away
+    for _ in 0..16 {
+        write(&mut encoder, Y_AFTER_Y2, 1, 0, &[(0, 0)]);
+    }
+    for _ in 0..8 {
+        write(&mut encoder, CHROMA, 0, 0, &[(0, 0)]);
+    }
+    let data = encoder.flush();
+
+    let factors = DequantFactors::new(10, &QuantIndices::default());
+    let (mut above, mut left) = (TokenContext::default(), TokenContext::default());
+    let mut decoder = BoolDecoder::new(&data).unwrap();
+    let mut residual =
+        Residual::read(&mut decoder, probs, &factors, true, &mut above, &mut left).unwrap();
+    assert!(residual.has_coeffs);
+    let mut y2 = [0; 16];
+    y2[ZIGZAG[0] as usize] = factors.y2[0];
+    y2[ZIGZAG[1] as usize] = -2 * factors.y2[1];
+    assert_eq!(residual.blocks[Y2_BLOCK], y2);
+    assert!(residual.blocks[..Y2_BLOCK].iter().all(|b| *b == [0; 16]));
+    //  only y2 decoded tokens
+    assert_eq!(
+        above.0,
+        [false, false, false, false, false, false, false, false, true]
+    );
+    assert_eq!(left.0, above.0);
+
+    //  the walsh-hadamard transform hands each luma block its dc, which the
+    //  dct spreads over the block
+    residual.inverse_transform(true);
+    for (block, dc) in residual.blocks[..16].iter().zip(transform::iwht4x4(&y2)) {
+        assert_eq!(*block, [(dc + 4) >> 3; 16]);
+    }
+    assert!(residual.blocks[16..Y2_BLOCK].iter().all(|b| *b == [0; 16]));
+}
@@ -0,0 +1,87 @@
+//! Constant tables for reconstruction from https://datatracker.ietf.org/doc/html/rfc6386
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-13.2, the band of
+///  each coefficient position selects its token probabilities
+pub const COEFF_BANDS: [u8; 16] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7];
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-13.3, coefficient
+///  position of each token in a 4x4 block
+pub const ZIGZAG: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+/// Smallest value of each DCT_CAT token, followed by extra bits read with
+/// `DCT_CAT_PROBS`, https://datatracker.ietf.org/doc/html/rfc6386#section-13.2
+pub const DCT_CAT_BASE: [i32; 6] = [5, 7, 11, 19, 35, 67];
+pub const DCT_CAT_PROBS: [&[u8]; 6] = [
+    &[159],
+    &[165, 145],
+    &[173, 148, 140],
+    &[176, 155, 140, 135],
+    &[180, 157, 141, 134, 130],
+    &[254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129],
+];
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-14.1
+#[rustfmt::skip]
+pub const DC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,   9,  10,  10,
+     11,  12,  13,  14,  15,  16,  17,  17,
+     18,  19,  20,  20,  21,  21,  22,  22,
+     23,  23,  24,  25,  25,  26,  27,  28,
+     29,  30,  31,  32,  33,  34,  35,  36,
+     37,  37,  38,  39,  40,  41,  42,  43,
+     44,  45,  46,  46,  47,  48,  49,  50,
+     51,  52,  53,  54,  55,  56,  57,  58,
+     59,  60,  61,  62,  63,  64,  65,  66,
+     67,  68,  69,  70,  71,  72,  73,  74,
+     75,  76,  76,  77,  78,  79,  80,  81,
+     82,  83,  84,  85,  86,  87,  88,  89,
+     91,  93,  95,  96,  98, 100, 101, 102,
+    104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136,
+    138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+#[rustfmt::skip]
+pub const AC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,   9,  10,  11,
+     12,  13,  14,  15,  16,  17,  18,  19,
+     20,  21,  22,  23,  24,  25,  26,  27,
+     28,  29,  30,  31,  32,  33,  34,  35,
+     36,  37,  38,  39,  40,  41,  42,  43,
+     44,  45,  46,  47,  48,  49,  50,  51,
+     52,  53,  54,  55,  56,  57,  58,  60,
+     62,  64,  66,  68,  70,  72,  74,  76,
+     78,  80,  82,  84,  86,  88,  90,  92,
+     94,  96,  98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128,
+    131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177,
+    181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245,
+    249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+/// Six-tap subpixel filters indexed by the 1/8th pixel fraction of a motion
+/// vector, https://datatracker.ietf.org/doc/html/rfc6386#section-18.3
+pub const SUBPEL_FILTERS: [[i32; 6]; 8] = [
+    [0, 0, 128, 0, 0, 0],
+    [0, -6, 123, 12, -1, 0],
+    [2, -11, 108, 36, -8, 1],
+    [0, -9, 93, 50, -6, 0],
+    [3, -16, 77, 77, -16, 3],
+    [0, -6, 50, 93, -9, 0],
+    [1, -8, 36, 108, -11, 2],
+    [0, -1, 12, 123, -6, 0],
+];
+
+/// Bilinear filters used by versions 1-3, laid out like `SUBPEL_FILTERS`
+pub const BILINEAR_FILTERS: [[i32; 6]; 8] = [
+    [0, 0, 128, 0, 0, 0],
+    [0, 0, 112, 16, 0, 0],
+    [0, 0, 96, 32, 0, 0],
+    [0, 0, 80, 48, 0, 0],
+    [0, 0, 64, 64, 0, 0],
+    [0, 0, 48, 80, 0, 0],
+    [0, 0, 32, 96, 0, 0],
+    [0, 0, 16, 112, 0, 0],
+];
@@ -0,0 +1,78 @@
+//! Inverse transforms, https://datatracker.ietf.org/doc/html/rfc6386#section-14.3
+
+const COS_PI8_SQRT2_MINUS1: i32 = 20091;
+const SIN_PI8_SQRT2: i32 = 35468;
+
+/// Inverse DCT of a dequantized block in place, columns first like libvpx's
+/// vp8_short_idct4x4llm_c
+pub fn idct4x4(block: &mut [i32; 16]) {
+    let mul_cos = |x: i32| x + ((x * COS_PI8_SQRT2_MINUS1) >> 16);
+    let mul_sin = |x: i32| (x * SIN_PI8_SQRT2) >> 16;
+
+    for i in 0..4 {
+        let (i0, i1, i2, i3) = (block[i], block[4 + i], block[8 + i], block[12 + i]);
+        let a = i0 + i2;
+        let b = i0 - i2;
+        let c = mul_sin(i1) - mul_cos(i3);
+        let d = mul_cos(i1) + mul_sin(i3);
+        block[i] = a + d;
+        block[4 + i] = b + c;
+        block[8 + i] = b - c;
+        block[12 + i] = a - d;
+    }
+
+    for row in block.chunks_exact_mut(4) {
+        let a = row[0] + row[2];
+        let b = row[0] - row[2];
+        let c = mul_sin(row[1]) - mul_cos(row[3]);
+        let d = mul_cos(row[1]) + mul_sin(row[3]);
+        row[0] = (a + d + 4) >> 3;
+        row[1] = (b + c + 4) >> 3;
+        row[2] = (b - c + 4) >> 3;
+        row[3] = (a - d + 4) >> 3;
+    }
+}
+
+/// Inverse Walsh-Hadamard transform of the y2 block, returning the DC
+/// coefficient of each luma block
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-14.3
+pub fn iwht4x4(block: &[i32; 16]) -> [i32; 16] {
+    let mut out = [0; 16];
+    for i in 0..4 {
+        let a = block[i] + block[12 + i];
+        let b = block[4 + i] + block[8 + i];
+        let c = block[4 + i] - block[8 + i];
+        let d = block[i] - block[12 + i];
+        out[i] = a + b;
+        out[4 + i] = c + d;
+        out[8 + i] = a - b;
+        out[12 + i] = d - c;
+    }
+
+    for row in out.chunks_exact_mut(4) {
+        let a = row[0] + row[3];
+        let b = row[1] + row[2];
+        let c = row[1] - row[2];
+        let d = row[0] - row[3];
+        row[0] = (a + b + 3) >> 3;
+        row[1] = (c + d + 3) >> 3;
+        row[2] = (a - b + 3) >> 3;
+        row[3] = (d - c + 3) >> 3;
+    }
+    out
+}
+
+#[test]
+fn dc_only_transforms() {
+    //  a lone DC coefficient spreads evenly, matching libvpx's dc only
+    //  shortcuts
+    let mut block = [0; 16];
+    block[0] = 100;
+    idct4x4(&mut block);
+    assert_eq!(block, [(100 + 4) >> 3; 16]);
+
+    let mut block = [0; 16];
+    block[0] = -37;
+    assert_eq!(iwht4x4(&block), [(-37 + 3) >> 3; 16]);
+}
@@ -1,15 +1,33 @@
+#[derive(Debug)]
 pub struct FrameInfo {
     pub tag: FrameTag,
     pub header: FrameHeader,
+    /// Fails if `data` doesn't hold the whole frame, while the header can
+    /// still be parsed from just the start of the first partition.
+    pub partitions: Result<Partitions, Error>,
+    /// Where the macroblock headers start in the first partition
+    macroblocks_start: BoolDecoderPosition,
 }
 
 impl FrameInfo {
-    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
-        let (data, tag) = FrameTag::parse(data)
-            .map_err(|e| anyhow::anyhow!("error parsing header {:?}", e.map(|e| e.code)))?;
-        let header = FrameHeader::parse(tag.frame_type.clone(), data)?;
+    pub fn parse(frame: &[u8]) -> Result<Self, Error> {
+        let (data, tag) = FrameTag::parse(frame)?;
+        let first_partition = data.get(..tag.first_part_size as usize).unwrap_or(data);
+        let mut decoder = BoolDecoder::new(first_partition)?;
+        let header = FrameHeader::read(tag.frame_type.clone(), &mut decoder)?;
+        let partitions = Partitions::parse(
+            frame,
+            tag.size(),
+            tag.first_part_size,
+            header.log2_nbr_of_dct_partitions,
+        );
 
-        Ok(Self { tag, header })
+        Ok(Self {
+            tag,
+            header,
+            partitions,
+            macroblocks_start: decoder.position(),
+        })
     }
 }
 
@@ -22,27 +40,65 @@ pub struct FrameTag {
 }
 
 use nom::number::complete::{le_u16, le_u24};
+use serde::Serialize;
 
-use self::bitcode::BoolDecoder;
+use self::bitcode::{BoolDecoder, BoolDecoderPosition};
+#[cfg(feature = "decoder")]
+pub use self::decoder::{Decoder, I420Frame};
+pub use self::modes::{
+    ChromaMode, InterMode, LumaMode, MacroblockHeader, ModeProbs, ModeStats, MotionVector,
+    Prediction, SubblockMode,
+};
+pub use self::partitions::{Partition, Partitions};
+pub use self::references::{FrameSource, ReferenceSources, ReferenceTracker};
+#[cfg(test)]
+pub(crate) use self::state::encode_frame;
+pub use self::state::{EffectiveLoopFilterDeltas, EffectiveParams, StreamFrame, Vp8StreamState};
+pub use self::tables::TokenProbs;
+use crate::Error;
+
+fn truncated_frame_tag(_: nom::Err<nom::error::Error<&[u8]>>) -> Error {
+    Error::TruncatedFrameTag
+}
 
 impl FrameTag {
-    pub fn parse(data: &[u8]) -> nom::IResult<&[u8], Self> {
+    /// Bytes preceding the first partition
+    pub fn size(&self) -> usize {
+        if self.frame_type.is_key_frame() {
+            10
+        } else {
+            3
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(&[u8], Self), Error> {
         //  https://datatracker.ietf.org/doc/html/rfc6386#section-19.1
-        let (data, tmp) = le_u24(data)?;
+        let (data, tmp) = le_u24(data).map_err(truncated_frame_tag)?;
         //  A 1-bit frame type (0 for key frames, 1 for interframes).
         let key_frame = (tmp & 0x1) == 0;
         let version = ((tmp >> 1) & 0x7) as u8;
+        if version > 3 {
+            return Err(Error::UnsupportedVersion(version));
+        }
         let show_frame = ((tmp >> 4) & 0x1) == 1;
         let first_part_size = (tmp >> 5) & 0x7FFFF;
 
         let (data, frame_type) = if key_frame {
-            let (data, _start_code) = nom::bytes::complete::tag(&[0x9du8, 0x01, 0x2a])(data)?;
+            let (data, start_code) =
+                nom::bytes::complete::take(3usize)(data).map_err(truncated_frame_tag)?;
+            if start_code != [0x9d, 0x01, 0x2a] {
+                return Err(Error::BadStartCode([
+                    start_code[0],
+                    start_code[1],
+                    start_code[2],
+                ]));
+            }
 
-            let (data, tmp) = le_u16(data)?;
+            let (data, tmp) = le_u16(data).map_err(truncated_frame_tag)?;
             let width = tmp & 0x3FFF;
             let width_scale = (tmp >> 14) as u8;
 
-            let (data, tmp) = le_u16(data)?;
+            let (data, tmp) = le_u16(data).map_err(truncated_frame_tag)?;
             let height = tmp & 0x3FFF;
             let height_scale = (tmp >> 14) as u8;
 
@@ -86,6 +142,13 @@ impl FrameTagType {
     pub fn is_key_frame(&self) -> bool {
         matches!(self, FrameTagType::KeyFrame { .. })
     }
+    /// Coded width and height, before scaling
+    pub fn dimensions(&self) -> Option<(u16, u16)> {
+        match self {
+            FrameTagType::KeyFrame { width, height, .. } => Some((*width, *height)),
+            FrameTagType::InterFrame => None,
+        }
+    }
     pub fn resolution(&self) -> Option<(u32, u32)> {
         match self {
             FrameTagType::KeyFrame {
@@ -114,102 +177,884 @@ fn scale_dimension(dimension: u32, scale_flag: u8) -> u32 {
 ///  https://datatracker.ietf.org/doc/html/rfc6386#section-19.2
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameHeader {
+    //  color_space and clamping_type are only present on key frames
+    pub color_space: Option<ColorSpace>,
+    pub clamping_type: Option<ClampingType>,
+    /// Set when segmentation_enabled
+    pub segmentation: Option<Segmentation>,
+    pub filter_type: FilterType,
+    pub loop_filter_level: u8, // 6 bits
+    pub sharpness_level: u8,   // 3 bits
+    pub loop_filter_adj_enable: bool,
+    /// Set when mode_ref_lf_delta_update
+    pub loop_filter_deltas: Option<LoopFilterDeltas>,
+    pub log2_nbr_of_dct_partitions: u8, // 2 bits
+    pub quant_indices: QuantIndices,
+    pub refresh_entropy_probs: bool,
     pub frame_buffer_update: FrameBufferUpdate,
+    pub token_prob_updates: TokenProbUpdates,
+    //  sign biases are always false on key frames
+    pub sign_bias_golden: bool,
+    pub sign_bias_altref: bool,
+    /// Set when mb_no_coeff_skip is enabled
+    pub prob_skip_false: Option<u8>,
+    /// Only present on inter frames
+    pub inter_probs: Option<InterProbs>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// YUV color space as specified in ITU-R BT.601, the only non-reserved value
+    Yuv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClampingType {
+    /// Decoder must clamp reconstructed pixel values
+    Required,
+    /// Encoder guarantees no clamping is necessary
+    NotRequired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    Normal,
+    Simple,
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.3
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Segmentation {
+    /// Set when update_segment_feature_data
+    pub feature_data: Option<SegmentFeatureData>,
+    /// Segment id tree probabilities, set when update_mb_segmentation_map
+    pub tree_probs: Option<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentFeatureData {
+    pub mode: SegmentFeatureMode,
+    //  values not present in the header are 0
+    pub quantizer: [i8; 4],         // 7 bits + sign
+    pub loop_filter_level: [i8; 4], // 6 bits + sign
+}
+
+/// Key frames reset segment feature data to zero deltas
+impl Default for SegmentFeatureData {
+    fn default() -> Self {
+        Self {
+            mode: SegmentFeatureMode::Delta,
+            quantizer: [0; 4],
+            loop_filter_level: [0; 4],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentFeatureMode {
+    /// Segment values are added to the frame's quantizer/loop filter level
+    Delta,
+    /// Segment values replace the frame's quantizer/loop filter level
+    Absolute,
+}
+
+impl Segmentation {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let update_mb_segmentation_map = decoder.read_bit()?;
+        let update_segment_feature_data = decoder.read_bit()?;
+
+        let feature_data = if update_segment_feature_data {
+            let mode = if decoder.read_bit()? {
+                SegmentFeatureMode::Absolute
+            } else {
+                SegmentFeatureMode::Delta
+            };
+            let mut quantizer = [0; 4];
+            for q in quantizer.iter_mut() {
+                *q = read_opt_signed_field(decoder, 7)?.unwrap_or(0) as i8;
+            }
+            let mut loop_filter_level = [0; 4];
+            for lf in loop_filter_level.iter_mut() {
+                *lf = read_opt_signed_field(decoder, 6)?.unwrap_or(0) as i8;
+            }
+            Some(SegmentFeatureData {
+                mode,
+                quantizer,
+                loop_filter_level,
+            })
+        } else {
+            None
+        };
+
+        let tree_probs = if update_mb_segmentation_map {
+            let mut probs = [255; 3];
+            for p in probs.iter_mut() {
+                if let Some(prob) = read_opt_literal(decoder, 8)? {
+                    *p = prob as u8;
+                }
+            }
+            Some(probs)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            feature_data,
+            tree_probs,
+        })
+    }
+
+    pub fn update_map(&self) -> bool {
+        self.tree_probs.is_some()
+    }
+
+    pub fn update_data(&self) -> bool {
+        self.feature_data.is_some()
+    }
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.6
+///
+/// Entries that are `None` were not updated and keep their previous value.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LoopFilterDeltas {
+    /// Indexed by reference frame: intra, last, golden, altref
+    pub ref_frame: [Option<i8>; 4], // 6 bits + sign
+    /// Indexed by mode: B_PRED, ZEROMV, NEARESTMV/NEARMV/NEWMV, SPLITMV
+    pub mode: [Option<i8>; 4], // 6 bits + sign
+}
+
+impl LoopFilterDeltas {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let mut deltas = Self::default();
+        for delta in deltas.ref_frame.iter_mut() {
+            *delta = read_opt_signed_field(decoder, 6)?.map(|d| d as i8);
+        }
+        for delta in deltas.mode.iter_mut() {
+            *delta = read_opt_signed_field(decoder, 6)?.map(|d| d as i8);
+        }
+        Ok(deltas)
+    }
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.6
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QuantIndices {
+    pub y_ac_qi: u8, // 7 bits
+    //  deltas are 4 bit magnitude + sign, 0 when not present
+    pub y_dc_delta: i8,
+    pub y2_dc_delta: i8,
+    pub y2_ac_delta: i8,
+    pub uv_dc_delta: i8,
+    pub uv_ac_delta: i8,
 }
 
+impl QuantIndices {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let y_ac_qi = decoder.read_literal(7)? as u8;
+        let mut read_delta =
+            || -> Result<i8, Error> { Ok(read_opt_signed_field(decoder, 4)?.unwrap_or(0) as i8) };
+
+        Ok(Self {
+            y_ac_qi,
+            y_dc_delta: read_delta()?,
+            y2_dc_delta: read_delta()?,
+            y2_ac_delta: read_delta()?,
+            uv_dc_delta: read_delta()?,
+            uv_ac_delta: read_delta()?,
+        })
+    }
+}
+
+/// Reference buffers written once this frame is decoded,
+/// https://datatracker.ietf.org/doc/html/rfc6386#section-9.7
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameBufferUpdate {
+    pub last: bool,
     pub golden: bool,
     pub altref: bool,
+    /// Buffer copied into golden, only possible when golden isn't refreshed
+    pub copy_to_golden: Option<RefFrame>,
+    /// Buffer copied into altref, only possible when altref isn't refreshed
+    pub copy_to_altref: Option<RefFrame>,
 }
 
-fn skip_opt_field(decoder: &mut BoolDecoder, field_size: usize) -> std::io::Result<()> {
-    if decoder.read_bit()? {
-        let _skip = decoder.read_literal(field_size as u32)?;
+impl FrameBufferUpdate {
+    fn key_frame() -> Self {
+        Self {
+            last: true,
+            golden: true,
+            altref: true,
+            copy_to_golden: None,
+            copy_to_altref: None,
+        }
+    }
+
+    pub fn modifies_golden(&self) -> bool {
+        self.golden || self.copy_to_golden.is_some()
+    }
+
+    pub fn modifies_altref(&self) -> bool {
+        self.altref || self.copy_to_altref.is_some()
     }
-    Ok(())
 }
 
-impl FrameHeader {
-    pub fn parse(frame_type: FrameTagType, data: &[u8]) -> std::io::Result<Self> {
-        let mut decoder = bitcode::BoolDecoder::new(data)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefFrame {
+    Last,
+    Golden,
+    AltRef,
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.10
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterProbs {
+    pub prob_intra: u8,
+    pub prob_last: u8,
+    pub prob_gf: u8,
+    /// Set when intra_16x16_prob_update_flag
+    pub intra_16x16_prob: Option<[u8; 4]>,
+    /// Set when intra_chroma_prob_update_flag
+    pub intra_chroma_prob: Option<[u8; 3]>,
+    pub mv_prob_updates: MvProbUpdates,
+}
 
-        if frame_type.is_key_frame() {
-            let _color_space = decoder.read_bit()?;
-            if _color_space {
-                panic!("unsupported color space");
+impl InterProbs {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let prob_intra = decoder.read_literal(8)? as u8;
+        let prob_last = decoder.read_literal(8)? as u8;
+        let prob_gf = decoder.read_literal(8)? as u8;
+
+        let intra_16x16_prob = if decoder.read_bit()? {
+            let mut probs = [0; 4];
+            for p in probs.iter_mut() {
+                *p = decoder.read_literal(8)? as u8;
+            }
+            Some(probs)
+        } else {
+            None
+        };
+        let intra_chroma_prob = if decoder.read_bit()? {
+            let mut probs = [0; 3];
+            for p in probs.iter_mut() {
+                *p = decoder.read_literal(8)? as u8;
             }
-            let _clamping_type = decoder.read_bit()?;
+            Some(probs)
+        } else {
+            None
         };
 
-        let segmentation_enabled = decoder.read_bit()?;
-        if segmentation_enabled {
-            let update_mb_segmentation_map = decoder.read_bit()?;
-            let update_segment_feature_data = decoder.read_bit()?;
-            if update_segment_feature_data {
-                let _segment_feature_mode = decoder.read_bit()?;
-                for _ in 0..4 {
-                    skip_opt_field(&mut decoder, 8)?;
-                }
+        Ok(Self {
+            prob_intra,
+            prob_last,
+            prob_gf,
+            intra_16x16_prob,
+            intra_chroma_prob,
+            mv_prob_updates: MvProbUpdates::parse(decoder)?,
+        })
+    }
+}
 
-                for _ in 0..4 {
-                    skip_opt_field(&mut decoder, 7)?;
-                }
-            }
+/// New coefficient probabilities indexed by
+/// `[block type][coefficient band][context][token tree node]`, `None` where
+/// the previous probability is kept.
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-13.4
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TokenProbUpdates(pub [[[[Option<u8>; 11]; 3]; 8]; 4]);
 
-            if update_mb_segmentation_map {
-                for _ in 0..3 {
-                    skip_opt_field(&mut decoder, 8)?;
-                }
+impl TokenProbUpdates {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let mut updates = [[[[None; 11]; 3]; 8]; 4];
+        for (update, update_prob) in updates.iter_mut().flatten().flatten().flatten().zip(
+            tables::COEFF_UPDATE_PROBS
+                .iter()
+                .flatten()
+                .flatten()
+                .flatten(),
+        ) {
+            if decoder.read_bool(*update_prob as u32)? {
+                *update = Some(decoder.read_literal(8)? as u8);
             }
         }
+        Ok(Self(updates))
+    }
 
-        let _filter_type = decoder.read_bit()?;
-        let _loop_filter_level = decoder.read_literal(6)?;
-        let _sharpness_level = decoder.read_literal(3)?;
+    /// Number of probabilities updated by this frame
+    pub fn count(&self) -> usize {
+        self.0
+            .iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .flatten()
+            .count()
+    }
+}
 
-        let loop_filter_adj_enable = decoder.read_bit()?;
-        if loop_filter_adj_enable {
-            let mode_ref_lf_delta_update = decoder.read_bit()?;
-            if mode_ref_lf_delta_update {
-                for _ in 0..4 {
-                    skip_opt_field(&mut decoder, 7)?;
-                }
-                for _ in 0..4 {
-                    skip_opt_field(&mut decoder, 7)?;
-                }
+/// New motion vector probabilities for the row and column components, `None`
+/// where the previous probability is kept.
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-17.2
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MvProbUpdates(pub [[Option<u8>; 19]; 2]);
+
+impl MvProbUpdates {
+    fn parse(decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let mut updates = [[None; 19]; 2];
+        for (update, update_prob) in updates
+            .iter_mut()
+            .flatten()
+            .zip(tables::MV_UPDATE_PROBS.iter().flatten())
+        {
+            if decoder.read_bool(*update_prob as u32)? {
+                //  7 bit probability, scaled to 8 bits with 0 mapped to 1
+                let prob = decoder.read_literal(7)? as u8;
+                *update = Some(if prob == 0 { 1 } else { prob << 1 });
             }
         }
+        Ok(Self(updates))
+    }
 
-        let _log2_nbr_of_dct_partitions = decoder.read_literal(2)?;
-        let _y_ac_qi = decoder.read_literal(7)?;
-        for _ in 0..5 {
-            skip_opt_field(&mut decoder, 5)?;
-        }
+    /// Number of probabilities updated by this frame
+    pub fn count(&self) -> usize {
+        self.0.iter().flatten().flatten().count()
+    }
+}
 
-        let frame_buffer_update = if frame_type.is_key_frame() {
-            let _refresh_entropy_probs = decoder.read_bit()?;
-            FrameBufferUpdate {
-                golden: true,
-                altref: true,
+/// Reads a flag-prefixed signed field, returning `None` if the flag is unset.
+fn read_opt_signed_field(
+    decoder: &mut BoolDecoder,
+    field_size: usize,
+) -> Result<Option<i32>, Error> {
+    if decoder.read_bit()? {
+        Ok(Some(decoder.read_signed_literal(field_size as u32)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_opt_literal(decoder: &mut BoolDecoder, field_size: usize) -> Result<Option<u32>, Error> {
+    if decoder.read_bit()? {
+        Ok(Some(decoder.read_literal(field_size as u32)?))
+    } else {
+        Ok(None)
+    }
+}
+
+impl FrameHeader {
+    /// Only key frames lack inter frame probabilities
+    pub fn is_key_frame(&self) -> bool {
+        self.inter_probs.is_none()
+    }
+
+    pub fn parse(frame_type: FrameTagType, data: &[u8]) -> Result<Self, Error> {
+        let mut decoder = bitcode::BoolDecoder::new(data)?;
+        Self::read(frame_type, &mut decoder)
+    }
+
+    /// Reads the header from the start of the first partition, leaving
+    /// `decoder` at the first macroblock header.
+    fn read(frame_type: FrameTagType, decoder: &mut BoolDecoder) -> Result<Self, Error> {
+        let (color_space, clamping_type) = if frame_type.is_key_frame() {
+            if decoder.read_bit()? {
+                return Err(Error::ReservedColorSpace);
             }
+            let clamping_type = if decoder.read_bit()? {
+                ClampingType::NotRequired
+            } else {
+                ClampingType::Required
+            };
+            (Some(ColorSpace::Yuv), Some(clamping_type))
         } else {
-            let refresh_golden = decoder.read_bit()?;
-            let refresh_altref = decoder.read_bit()?;
-            //  TODO: more fields here
+            (None, None)
+        };
 
-            FrameBufferUpdate {
-                golden: refresh_golden,
-                altref: refresh_altref,
-            }
+        let segmentation = if decoder.read_bit()? {
+            Some(Segmentation::parse(decoder)?)
+        } else {
+            None
+        };
+
+        let filter_type = if decoder.read_bit()? {
+            FilterType::Simple
+        } else {
+            FilterType::Normal
+        };
+        let loop_filter_level = decoder.read_literal(6)? as u8;
+        let sharpness_level = decoder.read_literal(3)? as u8;
+
+        let loop_filter_adj_enable = decoder.read_bit()?;
+        let loop_filter_deltas = if loop_filter_adj_enable && decoder.read_bit()? {
+            Some(LoopFilterDeltas::parse(decoder)?)
+        } else {
+            None
+        };
+
+        let log2_nbr_of_dct_partitions = decoder.read_literal(2)? as u8;
+        let quant_indices = QuantIndices::parse(decoder)?;
+
+        let (refresh_entropy_probs, frame_buffer_update, sign_bias_golden, sign_bias_altref) =
+            if frame_type.is_key_frame() {
+                let refresh_entropy_probs = decoder.read_bit()?;
+                (
+                    refresh_entropy_probs,
+                    FrameBufferUpdate::key_frame(),
+                    false,
+                    false,
+                )
+            } else {
+                let refresh_golden = decoder.read_bit()?;
+                let refresh_altref = decoder.read_bit()?;
+                let copy_to_golden = if !refresh_golden {
+                    match decoder.read_literal(2)? {
+                        1 => Some(RefFrame::Last),
+                        2 => Some(RefFrame::AltRef),
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                let copy_to_altref = if !refresh_altref {
+                    match decoder.read_literal(2)? {
+                        1 => Some(RefFrame::Last),
+                        2 => Some(RefFrame::Golden),
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                let sign_bias_golden = decoder.read_bit()?;
+                let sign_bias_altref = decoder.read_bit()?;
+                let refresh_entropy_probs = decoder.read_bit()?;
+                let refresh_last = decoder.read_bit()?;
+
+                (
+                    refresh_entropy_probs,
+                    FrameBufferUpdate {
+                        last: refresh_last,
+                        golden: refresh_golden,
+                        altref: refresh_altref,
+                        copy_to_golden,
+                        copy_to_altref,
+                    },
+                    sign_bias_golden,
+                    sign_bias_altref,
+                )
+            };
+
+        let token_prob_updates = TokenProbUpdates::parse(decoder)?;
+
+        let prob_skip_false = read_opt_literal(decoder, 8)?.map(|p| p as u8);
+
+        let inter_probs = if frame_type.is_key_frame() {
+            None
+        } else {
+            Some(InterProbs::parse(decoder)?)
         };
 
         Ok(Self {
+            color_space,
+            clamping_type,
+            segmentation,
+            filter_type,
+            loop_filter_level,
+            sharpness_level,
+            loop_filter_adj_enable,
+            loop_filter_deltas,
+            log2_nbr_of_dct_partitions,
+            quant_indices,
+            refresh_entropy_probs,
             frame_buffer_update,
+            token_prob_updates,
+            sign_bias_golden,
+            sign_bias_altref,
+            prob_skip_false,
+            inter_probs,
         })
     }
 }
 
 mod bitcode;
+#[cfg(feature = "decoder")]
+mod decoder;
+mod modes;
+mod partitions;
+mod references;
+mod state;
+mod tables;
 #[cfg(test)]
 mod testing;
+
+#[cfg(test)]
+fn encode_quant_indices(encoder: &mut bitcode::BoolEncoder, quant_indices: &QuantIndices) {
+    encoder.write_literal(7, quant_indices.y_ac_qi as u32);
+    for delta in [
+        quant_indices.y_dc_delta,
+        quant_indices.y2_dc_delta,
+        quant_indices.y2_ac_delta,
+        quant_indices.uv_dc_delta,
+        quant_indices.uv_ac_delta,
+    ] {
+        encoder.write_bit(delta != 0);
+        if delta != 0 {
+            encoder.write_signed_literal(4, delta as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+fn encode_no_token_prob_updates(encoder: &mut bitcode::BoolEncoder) {
+    for update_prob in tables::COEFF_UPDATE_PROBS
+        .iter()
+        .flatten()
+        .flatten()
+        .flatten()
+    {
+        encoder.write_bool(*update_prob as u32, 0);
+    }
+}
+
+#[cfg(test)]
+fn encode_no_mode_prob_updates(encoder: &mut bitcode::BoolEncoder) {
+    encoder.write_bit(false); // intra_16x16_prob_update_flag
+    encoder.write_bit(false); // intra_chroma_prob_update_flag
+    for update_prob in tables::MV_UPDATE_PROBS.iter().flatten() {
+        encoder.write_bool(*update_prob as u32, 0);
+    }
+}
+
+/// Prefixes `first_partition` with a frame tag for `frame_type`
+#[cfg(test)]
+fn frame_with_tag(frame_type: &FrameTagType, first_partition: Vec<u8>) -> Vec<u8> {
+    let inter_frame = !frame_type.is_key_frame() as u32;
+    let tag = inter_frame | 1 << 4 | (first_partition.len() as u32) << 5;
+    let mut frame = tag.to_le_bytes()[..3].to_vec();
+    if let Some((width, height)) = frame_type.dimensions() {
+        frame.extend([0x9d, 0x01, 0x2a]);
+        frame.extend(width.to_le_bytes());
+        frame.extend(height.to_le_bytes());
+    }
+    frame.extend(first_partition);
+    frame
+}
+
+#[test]
+fn parse_key_frame_header() {
+    let quant_indices = QuantIndices {
+        y_ac_qi: 60,
+        y_dc_delta: -3,
+        y2_dc_delta: 0,
+        y2_ac_delta: 7,
+        uv_dc_delta: -15,
+        uv_ac_delta: 0,
+    };
+
+    let mut encoder = bitcode::BoolEncoder::new();
+    encoder.write_bit(false); // color_space
+    encoder.write_bit(true); // clamping_type
+    encoder.write_bit(false); // segmentation_enabled
+    encoder.write_bit(true); // filter_type
+    encoder.write_literal(6, 34); // loop_filter_level
+    encoder.write_literal(3, 5); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 2); // log2_nbr_of_dct_partitions
+    encode_quant_indices(&mut encoder, &quant_indices);
+    encoder.write_bit(false); // refresh_entropy_probs
+    encode_no_token_prob_updates(&mut encoder);
+    encoder.write_bit(true); // mb_no_coeff_skip
+    encoder.write_literal(8, 200); // prob_skip_false
+    let data = encoder.flush();
+
+    let header = FrameHeader::parse(
+        FrameTagType::KeyFrame {
+            width: 640,
+            width_scale: 0,
+            height: 480,
+            height_scale: 0,
+        },
+        &data,
+    )
+    .unwrap();
+
+    assert_eq!(
+        header,
+        FrameHeader {
+            color_space: Some(ColorSpace::Yuv),
+            clamping_type: Some(ClampingType::NotRequired),
+            segmentation: None,
+            filter_type: FilterType::Simple,
+            loop_filter_level: 34,
+            sharpness_level: 5,
+            loop_filter_adj_enable: false,
+            loop_filter_deltas: None,
+            log2_nbr_of_dct_partitions: 2,
+            quant_indices,
+            refresh_entropy_probs: false,
+            frame_buffer_update: FrameBufferUpdate::key_frame(),
+            token_prob_updates: Default::default(),
+            sign_bias_golden: false,
+            sign_bias_altref: false,
+            prob_skip_false: Some(200),
+            inter_probs: None,
+        }
+    );
+}
+
+#[test]
+fn parse_inter_frame_header() {
+    let quant_indices = QuantIndices {
+        y_ac_qi: 127,
+        uv_ac_delta: 4,
+        ..Default::default()
+    };
+
+    let mut encoder = bitcode::BoolEncoder::new();
+    encoder.write_bit(false); // segmentation_enabled
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 63); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    encode_quant_indices(&mut encoder, &quant_indices);
+    encoder.write_bit(true); // refresh_golden_frame
+    encoder.write_bit(false); // refresh_alternate_frame
+    encoder.write_literal(2, 2); // copy_buffer_to_alternate
+    encoder.write_bit(false); // sign_bias_golden
+    encoder.write_bit(true); // sign_bias_alternate
+    encoder.write_bit(true); // refresh_entropy_probs
+    encoder.write_bit(false); // refresh_last
+    encode_no_token_prob_updates(&mut encoder);
+    encoder.write_bit(false); // mb_no_coeff_skip
+    encoder.write_literal(8, 30); // prob_intra
+    encoder.write_literal(8, 220); // prob_last
+    encoder.write_literal(8, 128); // prob_gf
+    encode_no_mode_prob_updates(&mut encoder);
+    let data = encoder.flush();
+
+    let header = FrameHeader::parse(FrameTagType::InterFrame, &data).unwrap();
+
+    assert_eq!(header.color_space, None);
+    assert_eq!(header.clamping_type, None);
+    assert_eq!(header.filter_type, FilterType::Normal);
+    assert_eq!(header.loop_filter_level, 63);
+    assert_eq!(header.sharpness_level, 0);
+    assert_eq!(header.log2_nbr_of_dct_partitions, 0);
+    assert_eq!(header.quant_indices, quant_indices);
+    assert!(header.refresh_entropy_probs);
+    assert_eq!(
+        header.frame_buffer_update,
+        FrameBufferUpdate {
+            last: false,
+            golden: true,
+            altref: false,
+            copy_to_golden: None,
+            copy_to_altref: Some(RefFrame::Golden),
+        }
+    );
+    assert!(!header.sign_bias_golden);
+    assert!(header.sign_bias_altref);
+    assert_eq!(header.prob_skip_false, None);
+    assert_eq!(
+        header.inter_probs,
+        Some(InterProbs {
+            prob_intra: 30,
+            prob_last: 220,
+            prob_gf: 128,
+            intra_16x16_prob: None,
+            intra_chroma_prob: None,
+            mv_prob_updates: Default::default(),
+        })
+    );
+}
+
+#[test]
+fn parse_segmentation_and_loop_filter_deltas() {
+    let mut encoder = bitcode::BoolEncoder::new();
+    encoder.write_bit(true); // segmentation_enabled
+    encoder.write_bit(true); // update_mb_segmentation_map
+    encoder.write_bit(true); // update_segment_feature_data
+    encoder.write_bit(false); // segment_feature_mode
+    for quantizer in [Some(-20), None, Some(12), None] {
+        encoder.write_bit(quantizer.is_some());
+        if let Some(quantizer) = quantizer {
+            encoder.write_signed_literal(7, quantizer);
+        }
+    }
+    for loop_filter_level in [None, Some(-63), None, Some(8)] {
+        encoder.write_bit(loop_filter_level.is_some());
+        if let Some(loop_filter_level) = loop_filter_level {
+            encoder.write_signed_literal(6, loop_filter_level);
+        }
+    }
+    for prob in [Some(128), None, Some(3)] {
+        encoder.write_bit(prob.is_some());
+        if let Some(prob) = prob {
+            encoder.write_literal(8, prob);
+        }
+    }
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 20); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(true); // loop_filter_adj_enable
+    encoder.write_bit(true); // mode_ref_lf_delta_update
+    for delta in [
+        Some(2),
+        Some(0),
+        Some(-2),
+        Some(-2),
+        Some(4),
+        Some(-2),
+        None,
+        Some(4),
+    ] {
+        encoder.write_bit(delta.is_some());
+        if let Some(delta) = delta {
+            encoder.write_signed_literal(6, delta);
+        }
+    }
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    encode_quant_indices(&mut encoder, &Default::default());
+    encoder.write_bit(false); // refresh_golden_frame
+    encoder.write_bit(false); // refresh_alternate_frame
+    encoder.write_literal(2, 0); // copy_buffer_to_golden
+    encoder.write_literal(2, 0); // copy_buffer_to_alternate
+    encoder.write_bit(false); // sign_bias_golden
+    encoder.write_bit(false); // sign_bias_alternate
+    encoder.write_bit(true); // refresh_entropy_probs
+    encoder.write_bit(true); // refresh_last
+    encode_no_token_prob_updates(&mut encoder);
+    encoder.write_bit(false); // mb_no_coeff_skip
+    encoder.write_literal(8, 1); // prob_intra
+    encoder.write_literal(8, 255); // prob_last
+    encoder.write_literal(8, 128); // prob_gf
+    encode_no_mode_prob_updates(&mut encoder);
+    let data = encoder.flush();
+
+    let header = FrameHeader::parse(FrameTagType::InterFrame, &data).unwrap();
+
+    let segmentation = header.segmentation.unwrap();
+    assert!(segmentation.update_map());
+    assert!(segmentation.update_data());
+    assert_eq!(
+        segmentation.feature_data,
+        Some(SegmentFeatureData {
+            mode: SegmentFeatureMode::Delta,
+            quantizer: [-20, 0, 12, 0],
+            loop_filter_level: [0, -63, 0, 8],
+        })
+    );
+    assert_eq!(segmentation.tree_probs, Some([128, 255, 3]));
+
+    assert!(header.loop_filter_adj_enable);
+    assert_eq!(
+        header.loop_filter_deltas,
+        Some(LoopFilterDeltas {
+            ref_frame: [Some(2), Some(0), Some(-2), Some(-2)],
+            mode: [Some(4), Some(-2), None, Some(4)],
+        })
+    );
+    assert_eq!(header.loop_filter_level, 20);
+    assert!(header.frame_buffer_update.last);
+}
+
+#[test]
+fn parse_errors() {
+    assert!(matches!(
+        FrameInfo::parse(&[0x10, 0x02]),
+        Err(Error::TruncatedFrameTag)
+    ));
+    assert!(matches!(
+        FrameInfo::parse(&[0x10, 0x02, 0x00, 0x9d, 0x01]),
+        Err(Error::TruncatedFrameTag)
+    ));
+    assert!(matches!(
+        FrameInfo::parse(&[0x10, 0x02, 0x00, 0x9d, 0x01, 0x2b, 0x10, 0x00, 0x10, 0x00]),
+        Err(Error::BadStartCode([0x9d, 0x01, 0x2b]))
+    ));
+    assert!(matches!(
+        FrameInfo::parse(&[0x1f, 0x02, 0x00, 0x00, 0x00]),
+        Err(Error::UnsupportedVersion(7))
+    ));
+    //  inter frame with only one byte of first partition
+    assert!(matches!(
+        FrameInfo::parse(&[0x11, 0x02, 0x00, 0x00]),
+        Err(Error::BoolDecoderUnderrun)
+    ));
+
+    let mut encoder = bitcode::BoolEncoder::new();
+    encoder.write_bit(true); // color_space
+    let mut frame = vec![0x10, 0x02, 0x00, 0x9d, 0x01, 0x2a, 0x10, 0x00, 0x10, 0x00];
+    frame.extend(encoder.flush());
+    assert!(matches!(
+        FrameInfo::parse(&frame),
+        Err(Error::ReservedColorSpace)
+    ));
+}
+
+#[test]
+fn parse_prob_updates() {
+    let mut encoder = bitcode::BoolEncoder::new();
+    encoder.write_bit(false); // segmentation_enabled
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 10); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    encode_quant_indices(&mut encoder, &Default::default());
+    encoder.write_bit(false); // refresh_golden_frame
+    encoder.write_bit(false); // refresh_alternate_frame
+    encoder.write_literal(2, 0); // copy_buffer_to_golden
+    encoder.write_literal(2, 0); // copy_buffer_to_alternate
+    encoder.write_bit(false); // sign_bias_golden
+    encoder.write_bit(false); // sign_bias_alternate
+    encoder.write_bit(false); // refresh_entropy_probs
+    encoder.write_bit(true); // refresh_last
+    for (i, update_prob) in tables::COEFF_UPDATE_PROBS
+        .iter()
+        .flatten()
+        .flatten()
+        .flatten()
+        .enumerate()
+    {
+        //  update the first and last probabilities
+        let update = i == 0 || i == 4 * 8 * 3 * 11 - 1;
+        encoder.write_bool(*update_prob as u32, update as u8);
+        if update {
+            encoder.write_literal(8, i as u32 % 256);
+        }
+    }
+    encoder.write_bit(true); // mb_no_coeff_skip
+    encoder.write_literal(8, 40); // prob_skip_false
+    encoder.write_literal(8, 30); // prob_intra
+    encoder.write_literal(8, 220); // prob_last
+    encoder.write_literal(8, 128); // prob_gf
+    encoder.write_bit(true); // intra_16x16_prob_update_flag
+    for prob in [1, 2, 3, 4] {
+        encoder.write_literal(8, prob);
+    }
+    encoder.write_bit(false); // intra_chroma_prob_update_flag
+    for (i, update_prob) in tables::MV_UPDATE_PROBS.iter().flatten().enumerate() {
+        let update = i == 1 || i == 19 + 5;
+        encoder.write_bool(*update_prob as u32, update as u8);
+        if update {
+            encoder.write_literal(7, if i == 1 { 0 } else { 100 });
+        }
+    }
+    let data = encoder.flush();
+
+    let header = FrameHeader::parse(FrameTagType::InterFrame, &data).unwrap();
+
+    assert_eq!(header.token_prob_updates.count(), 2);
+    assert_eq!(header.token_prob_updates.0[0][0][0][0], Some(0));
+    assert_eq!(header.token_prob_updates.0[3][7][2][10], Some(31));
+    assert_eq!(header.prob_skip_false, Some(40));
+
+    let inter_probs = header.inter_probs.unwrap();
+    assert_eq!(inter_probs.intra_16x16_prob, Some([1, 2, 3, 4]));
+    assert_eq!(inter_probs.intra_chroma_prob, None);
+    assert_eq!(inter_probs.mv_prob_updates.count(), 2);
+    assert_eq!(inter_probs.mv_prob_updates.0[0][1], Some(1));
+    assert_eq!(inter_probs.mv_prob_updates.0[1][5], Some(200));
+}
@@ -0,0 +1,1071 @@
+//! Per-macroblock headers from the first partition,
+//! https://datatracker.ietf.org/doc/html/rfc6386#section-19.3
+
+use serde::Serialize;
+
+use super::bitcode::BoolDecoder;
+use super::tables;
+#[cfg(test)]
+use super::FrameTagType;
+use super::{FrameHeader, FrameInfo, InterProbs, RefFrame};
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumaMode {
+    Dc,
+    V,
+    H,
+    Tm,
+    /// Each 4x4 subblock is predicted separately
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaMode {
+    Dc,
+    V,
+    H,
+    Tm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubblockMode {
+    Dc,
+    Tm,
+    Ve,
+    He,
+    Ld,
+    Rd,
+    Vr,
+    Vl,
+    Hd,
+    Hu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterMode {
+    Nearest,
+    Near,
+    Zero,
+    New,
+    Split,
+}
+
+/// Motion vector in quarter pixels, scaled by 2 as libvpx does so that
+/// averaged chroma vectors keep their 1/8th pixel precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MotionVector {
+    pub row: i16,
+    pub col: i16,
+}
+
+impl MotionVector {
+    fn is_zero(&self) -> bool {
+        self.row == 0 && self.col == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prediction {
+    Intra {
+        luma: LumaMode,
+        /// Modes implied by `luma` unless it is `LumaMode::B`
+        subblocks: [SubblockMode; 16],
+        chroma: ChromaMode,
+    },
+    Inter {
+        ref_frame: RefFrame,
+        mode: InterMode,
+        /// Equal to the last subblock's vector for split mode
+        mv: MotionVector,
+        /// Only decoded for split mode
+        subblock_mvs: Option<[MotionVector; 16]>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroblockHeader {
+    /// Only decoded when the frame updates the segment map, otherwise the
+    /// previous frame's segment id is kept (see `Vp8StreamState`).
+    pub segment_id: Option<u8>,
+    /// Set when mb_no_coeff_skip is enabled and the macroblock has no
+    /// non-zero coefficients
+    pub skip_coeff: bool,
+    pub prediction: Prediction,
+}
+
+impl MacroblockHeader {
+    fn ref_frame(&self) -> Option<RefFrame> {
+        match self.prediction {
+            Prediction::Intra { .. } => None,
+            Prediction::Inter { ref_frame, .. } => Some(ref_frame),
+        }
+    }
+
+    /// Intra macroblocks have a zero vector for neighboring mv prediction
+    fn mv(&self) -> MotionVector {
+        match self.prediction {
+            Prediction::Intra { .. } => MotionVector::default(),
+            Prediction::Inter { mv, .. } => mv,
+        }
+    }
+
+    fn subblock_mv(&self, b: usize) -> MotionVector {
+        match self.prediction {
+            Prediction::Inter {
+                subblock_mvs: Some(mvs),
+                ..
+            } => mvs[b],
+            _ => self.mv(),
+        }
+    }
+
+    fn is_split(&self) -> bool {
+        matches!(
+            self.prediction,
+            Prediction::Inter {
+                mode: InterMode::Split,
+                ..
+            }
+        )
+    }
+
+    fn subblock_mode(&self, b: usize) -> SubblockMode {
+        match self.prediction {
+            Prediction::Intra { subblocks, .. } => subblocks[b],
+            Prediction::Inter { .. } => SubblockMode::Dc,
+        }
+    }
+}
+
+/// Mode and motion vector probabilities that persist across inter frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeProbs {
+    pub ymode: [u8; 4],
+    pub uv_mode: [u8; 3],
+    pub mv: [[u8; 19]; 2],
+}
+
+impl Default for ModeProbs {
+    fn default() -> Self {
+        Self {
+            ymode: tables::YMODE_PROBS,
+            uv_mode: tables::UV_MODE_PROBS,
+            mv: tables::DEFAULT_MV_PROBS,
+        }
+    }
+}
+
+impl ModeProbs {
+    /// Applies an inter frame's probability updates
+    pub fn update(&mut self, inter_probs: &InterProbs) {
+        if let Some(ymode) = inter_probs.intra_16x16_prob {
+            self.ymode = ymode;
+        }
+        if let Some(uv_mode) = inter_probs.intra_chroma_prob {
+            self.uv_mode = uv_mode;
+        }
+        for (prob, update) in self
+            .mv
+            .iter_mut()
+            .flatten()
+            .zip(inter_probs.mv_prob_updates.0.iter().flatten())
+        {
+            if let Some(update) = update {
+                *prob = *update;
+            }
+        }
+    }
+}
+
+/// Macroblock counts for a frame, useful to spot scene cuts (mostly intra)
+/// or heavy motion (many new or split motion vectors).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ModeStats {
+    pub macroblocks: u32,
+    pub skipped: u32,
+    pub intra: u32,
+    /// Intra macroblocks predicted per 4x4 subblock
+    pub intra_b_pred: u32,
+    pub last: u32,
+    pub golden: u32,
+    pub altref: u32,
+    pub zero_mv: u32,
+    pub new_mv: u32,
+    pub split_mv: u32,
+}
+
+impl ModeStats {
+    pub fn from_macroblocks(macroblocks: &[MacroblockHeader]) -> Self {
+        let mut stats = Self {
+            macroblocks: macroblocks.len() as u32,
+            ..Default::default()
+        };
+        for mb in macroblocks {
+            stats.skipped += mb.skip_coeff as u32;
+            match mb.prediction {
+                Prediction::Intra { luma, .. } => {
+                    stats.intra += 1;
+                    stats.intra_b_pred += (luma == LumaMode::B) as u32;
+                }
+                Prediction::Inter {
+                    ref_frame, mode, ..
+                } => {
+                    match ref_frame {
+                        RefFrame::Last => stats.last += 1,
+                        RefFrame::Golden => stats.golden += 1,
+                        RefFrame::AltRef => stats.altref += 1,
+                    }
+                    match mode {
+                        InterMode::Zero => stats.zero_mv += 1,
+                        InterMode::New => stats.new_mv += 1,
+                        InterMode::Split => stats.split_mv += 1,
+                        InterMode::Nearest | InterMode::Near => {}
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// `count` as a percentage of all macroblocks in the frame
+    pub fn percent(&self, count: u32) -> f64 {
+        if self.macroblocks == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.macroblocks as f64
+        }
+    }
+}
+
+const LUMA_MODES: [LumaMode; 5] = [
+    LumaMode::Dc,
+    LumaMode::V,
+    LumaMode::H,
+    LumaMode::Tm,
+    LumaMode::B,
+];
+const CHROMA_MODES: [ChromaMode; 4] =
+    [ChromaMode::Dc, ChromaMode::V, ChromaMode::H, ChromaMode::Tm];
+const SUBBLOCK_MODES: [SubblockMode; 10] = [
+    SubblockMode::Dc,
+    SubblockMode::Tm,
+    SubblockMode::Ve,
+    SubblockMode::He,
+    SubblockMode::Ld,
+    SubblockMode::Rd,
+    SubblockMode::Vr,
+    SubblockMode::Vl,
+    SubblockMode::Hd,
+    SubblockMode::Hu,
+];
+
+impl LumaMode {
+    /// Subblock mode used as context by neighboring B_PRED macroblocks
+    fn implied_subblock_mode(&self) -> SubblockMode {
+        match self {
+            LumaMode::Dc | LumaMode::B => SubblockMode::Dc,
+            LumaMode::V => SubblockMode::Ve,
+            LumaMode::H => SubblockMode::He,
+            LumaMode::Tm => SubblockMode::Tm,
+        }
+    }
+}
+
+/// Range a predicted motion vector is clamped to so it points at most 16
+/// pixels outside of the frame.
+///
+/// In `i32` like libvpx, as the bounds of frames over 4080 pixels wide or
+/// tall don't fit in an `i16`.
+struct MvBounds {
+    to_left: i32,
+    to_right: i32,
+    to_top: i32,
+    to_bottom: i32,
+}
+
+impl MvBounds {
+    fn new(mb_row: usize, mb_col: usize, mb_rows: usize, mb_cols: usize) -> Self {
+        Self {
+            to_left: -(((mb_col + 1) << 7) as i32),
+            to_right: ((mb_cols - mb_col) << 7) as i32,
+            to_top: -(((mb_row + 1) << 7) as i32),
+            to_bottom: ((mb_rows - mb_row) << 7) as i32,
+        }
+    }
+
+    fn clamp(&self, mv: MotionVector) -> MotionVector {
+        //  the bounds straddle zero, so the clamped vector still fits
+        MotionVector {
+            row: (mv.row as i32).clamp(self.to_top, self.to_bottom) as i16,
+            col: (mv.col as i32).clamp(self.to_left, self.to_right) as i16,
+        }
+    }
+}
+
+impl FrameInfo {
+    /// Walks the per-macroblock headers in the first partition of `frame`.
+    ///
+    /// `dimensions` are the coded width and height from the most recent key
+    /// frame, and `probs` are the mode probabilities carried over from earlier
+    /// inter frames (`ModeProbs::default()` after a key frame).
+    pub fn parse_macroblock_headers(
+        &self,
+        frame: &[u8],
+        dimensions: (u16, u16),
+        probs: &ModeProbs,
+    ) -> Result<Vec<MacroblockHeader>, Error> {
+        let first_partition = frame
+            .get(self.tag.size()..self.tag.size() + self.tag.first_part_size as usize)
+            .ok_or(Error::TruncatedFirstPartition)?;
+        //  the encoder needn't flush the bits past the last macroblock header
+        let decoder = BoolDecoder::resume_zero_padded(first_partition, self.macroblocks_start);
+
+        let mut probs = probs.clone();
+        if let Some(inter_probs) = &self.header.inter_probs {
+            probs.update(inter_probs);
+        }
+
+        MacroblockParser {
+            decoder,
+            header: &self.header,
+            probs: &probs,
+            mb_cols: (dimensions.0 as usize).div_ceil(16),
+            mb_rows: (dimensions.1 as usize).div_ceil(16),
+            macroblocks: vec![],
+        }
+        .parse()
+    }
+}
+
+struct MacroblockParser<'a> {
+    decoder: BoolDecoder<'a>,
+    header: &'a FrameHeader,
+    probs: &'a ModeProbs,
+    mb_cols: usize,
+    mb_rows: usize,
+    macroblocks: Vec<MacroblockHeader>,
+}
+
+impl<'a> MacroblockParser<'a> {
+    fn parse(mut self) -> Result<Vec<MacroblockHeader>, Error> {
+        self.macroblocks.reserve(self.mb_rows * self.mb_cols);
+        for mb_row in 0..self.mb_rows {
+            for mb_col in 0..self.mb_cols {
+                let mb = self.read_macroblock(mb_row, mb_col)?;
+                self.macroblocks.push(mb);
+            }
+        }
+        Ok(self.macroblocks)
+    }
+
+    /// Neighboring macroblock, `None` outside of the frame
+    fn neighbor(
+        &self,
+        mb_row: usize,
+        mb_col: usize,
+        dy: usize,
+        dx: usize,
+    ) -> Option<&MacroblockHeader> {
+        if mb_row < dy || mb_col < dx {
+            None
+        } else {
+            self.macroblocks
+                .get((mb_row - dy) * self.mb_cols + mb_col - dx)
+        }
+    }
+
+    fn read_macroblock(&mut self, mb_row: usize, mb_col: usize) -> Result<MacroblockHeader, Error> {
+        let segment_id = match self.header.segmentation.as_ref().and_then(|s| s.tree_probs) {
+            Some(tree_probs) => Some(
+                self.decoder
+                    .read_tree(&tables::SEGMENT_ID_TREE, &tree_probs)?,
+            ),
+            None => None,
+        };
+
+        let skip_coeff = match self.header.prob_skip_false {
+            Some(prob) => self.decoder.read_bool(prob as u32)?,
+            None => false,
+        };
+
+        let prediction = match &self.header.inter_probs {
+            None => self.read_key_frame_modes(mb_row, mb_col)?,
+            Some(inter_probs) => {
+                if self.decoder.read_bool(inter_probs.prob_intra as u32)? {
+                    self.read_inter_modes(mb_row, mb_col, inter_probs)?
+                } else {
+                    self.read_intra_modes()?
+                }
+            }
+        };
+
+        Ok(MacroblockHeader {
+            segment_id,
+            skip_coeff,
+            prediction,
+        })
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-11.2
+    fn read_key_frame_modes(&mut self, mb_row: usize, mb_col: usize) -> Result<Prediction, Error> {
+        let luma = LUMA_MODES[self
+            .decoder
+            .read_tree(&tables::KF_YMODE_TREE, &tables::KF_YMODE_PROBS)?
+            as usize];
+
+        let subblocks = if luma == LumaMode::B {
+            let mut subblocks = [SubblockMode::Dc; 16];
+            for b in 0..16 {
+                let above = if b < 4 {
+                    self.neighbor(mb_row, mb_col, 1, 0)
+                        .map(|mb| mb.subblock_mode(b + 12))
+                        .unwrap_or(SubblockMode::Dc)
+                } else {
+                    subblocks[b - 4]
+                };
+                let left = if b & 3 == 0 {
+                    self.neighbor(mb_row, mb_col, 0, 1)
+                        .map(|mb| mb.subblock_mode(b + 3))
+                        .unwrap_or(SubblockMode::Dc)
+                } else {
+                    subblocks[b - 1]
+                };
+                subblocks[b] = SUBBLOCK_MODES[self.decoder.read_tree(
+                    &tables::BMODE_TREE,
+                    &tables::KF_BMODE_PROBS[above as usize][left as usize],
+                )? as usize];
+            }
+            subblocks
+        } else {
+            [luma.implied_subblock_mode(); 16]
+        };
+
+        let chroma = CHROMA_MODES[self
+            .decoder
+            .read_tree(&tables::UV_MODE_TREE, &tables::KF_UV_MODE_PROBS)?
+            as usize];
+
+        Ok(Prediction::Intra {
+            luma,
+            subblocks,
+            chroma,
+        })
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-16.1
+    fn read_intra_modes(&mut self) -> Result<Prediction, Error> {
+        let luma = LUMA_MODES[self
+            .decoder
+            .read_tree(&tables::YMODE_TREE, &self.probs.ymode)?
+            as usize];
+
+        let subblocks = if luma == LumaMode::B {
+            let mut subblocks = [SubblockMode::Dc; 16];
+            for subblock in subblocks.iter_mut() {
+                *subblock = SUBBLOCK_MODES[self
+                    .decoder
+                    .read_tree(&tables::BMODE_TREE, &tables::BMODE_PROBS)?
+                    as usize];
+            }
+            subblocks
+        } else {
+            [luma.implied_subblock_mode(); 16]
+        };
+
+        let chroma = CHROMA_MODES[self
+            .decoder
+            .read_tree(&tables::UV_MODE_TREE, &self.probs.uv_mode)?
+            as usize];
+
+        Ok(Prediction::Intra {
+            luma,
+            subblocks,
+            chroma,
+        })
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-16.3
+    fn read_inter_modes(
+        &mut self,
+        mb_row: usize,
+        mb_col: usize,
+        inter_probs: &InterProbs,
+    ) -> Result<Prediction, Error> {
+        let ref_frame = if !self.decoder.read_bool(inter_probs.prob_last as u32)? {
+            RefFrame::Last
+        } else if !self.decoder.read_bool(inter_probs.prob_gf as u32)? {
+            RefFrame::Golden
+        } else {
+            RefFrame::AltRef
+        };
+
+        let (near_mvs, counts) = self.find_near_mvs(mb_row, mb_col, ref_frame);
+        let bounds = MvBounds::new(mb_row, mb_col, self.mb_rows, self.mb_cols);
+        let best_mv = bounds.clamp(near_mvs[0]);
+
+        let probs = [
+            tables::MODE_CONTEXTS[counts[0] as usize][0],
+            tables::MODE_CONTEXTS[counts[1] as usize][1],
+            tables::MODE_CONTEXTS[counts[2] as usize][2],
+            tables::MODE_CONTEXTS[counts[3] as usize][3],
+        ];
+
+        let (mode, mv, subblock_mvs) = if !self.decoder.read_bool(probs[0] as u32)? {
+            (InterMode::Zero, MotionVector::default(), None)
+        } else if !self.decoder.read_bool(probs[1] as u32)? {
+            (InterMode::Nearest, bounds.clamp(near_mvs[1]), None)
+        } else if !self.decoder.read_bool(probs[2] as u32)? {
+            (InterMode::Near, bounds.clamp(near_mvs[2]), None)
+        } else if !self.decoder.read_bool(probs[3] as u32)? {
+            let mv = self.read_mv()?;
+            (
+                InterMode::New,
+                MotionVector {
+                    row: mv.row.wrapping_add(best_mv.row),
+                    col: mv.col.wrapping_add(best_mv.col),
+                },
+                None,
+            )
+        } else {
+            let mvs = self.read_split_mvs(mb_row, mb_col, best_mv)?;
+            (InterMode::Split, mvs[15], Some(mvs))
+        };
+
+        Ok(Prediction::Inter {
+            ref_frame,
+            mode,
+            mv,
+            subblock_mvs,
+        })
+    }
+
+    /// Returns the best, nearest and near motion vectors, plus the counts used
+    /// to look up mode probabilities.
+    ///
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-16.3
+    fn find_near_mvs(
+        &self,
+        mb_row: usize,
+        mb_col: usize,
+        ref_frame: RefFrame,
+    ) -> ([MotionVector; 3], [u8; 4]) {
+        const CNT_INTRA: usize = 0;
+        const CNT_NEAREST: usize = 1;
+        const CNT_NEAR: usize = 2;
+        const CNT_SPLITMV: usize = 3;
+
+        let above = self.neighbor(mb_row, mb_col, 1, 0);
+        let left = self.neighbor(mb_row, mb_col, 0, 1);
+        let above_left = self.neighbor(mb_row, mb_col, 1, 1);
+
+        let mut near_mvs = [MotionVector::default(); 4];
+        let mut counts = [0u8; 4];
+        let mut idx = 0;
+
+        for (neighbor, weight) in [(above, 2), (left, 2), (above_left, 1)] {
+            let neighbor = match neighbor {
+                Some(neighbor) => neighbor,
+                None => continue,
+            };
+            let neighbor_ref = match neighbor.ref_frame() {
+                Some(neighbor_ref) => neighbor_ref,
+                None => continue,
+            };
+            let mut mv = neighbor.mv();
+            if mv.is_zero() {
+                counts[CNT_INTRA] += weight;
+                continue;
+            }
+            if self.sign_bias(neighbor_ref) != self.sign_bias(ref_frame) {
+                mv.row = mv.row.wrapping_neg();
+                mv.col = mv.col.wrapping_neg();
+            }
+            //  the first neighbor with a vector always starts a new entry
+            if idx == 0 || mv != near_mvs[idx] {
+                idx += 1;
+                near_mvs[idx] = mv;
+            }
+            counts[idx] += weight;
+        }
+
+        //  if we have three distinct vectors, merge the above left with nearest
+        if counts[CNT_SPLITMV] > 0 && near_mvs[idx] == near_mvs[CNT_NEAREST] {
+            counts[CNT_NEAREST] += 1;
+        }
+
+        counts[CNT_SPLITMV] = (above.map(|mb| mb.is_split()).unwrap_or(false) as u8
+            + left.map(|mb| mb.is_split()).unwrap_or(false) as u8)
+            * 2
+            + above_left.map(|mb| mb.is_split()).unwrap_or(false) as u8;
+
+        if counts[CNT_NEAR] > counts[CNT_NEAREST] {
+            counts.swap(CNT_NEAREST, CNT_NEAR);
+            near_mvs.swap(CNT_NEAREST, CNT_NEAR);
+        }
+
+        if counts[CNT_NEAREST] >= counts[CNT_INTRA] {
+            near_mvs[CNT_INTRA] = near_mvs[CNT_NEAREST];
+        }
+
+        (
+            [
+                near_mvs[CNT_INTRA],
+                near_mvs[CNT_NEAREST],
+                near_mvs[CNT_NEAR],
+            ],
+            counts,
+        )
+    }
+
+    fn sign_bias(&self, ref_frame: RefFrame) -> bool {
+        match ref_frame {
+            RefFrame::Last => false,
+            RefFrame::Golden => self.header.sign_bias_golden,
+            RefFrame::AltRef => self.header.sign_bias_altref,
+        }
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-16.4
+    fn read_split_mvs(
+        &mut self,
+        mb_row: usize,
+        mb_col: usize,
+        best_mv: MotionVector,
+    ) -> Result<[MotionVector; 16], Error> {
+        let partitioning =
+            self.decoder
+                .read_tree(&tables::MBSPLIT_TREE, &tables::MBSPLIT_PROBS)? as usize;
+        let partitions = &tables::MBSPLITS[partitioning];
+
+        //  bottom row of the macroblock above and right column of the one to
+        //  the left
+        let above = self.neighbor(mb_row, mb_col, 1, 0);
+        let above_mvs =
+            [0, 1, 2, 3].map(|b| above.map(|mb| mb.subblock_mv(b + 12)).unwrap_or_default());
+        let left = self.neighbor(mb_row, mb_col, 0, 1);
+        let left_mvs =
+            [0, 1, 2, 3].map(|b| left.map(|mb| mb.subblock_mv(b * 4 + 3)).unwrap_or_default());
+
+        let mut mvs = [MotionVector::default(); 16];
+        for partition in 0..tables::MBSPLIT_COUNT[partitioning] {
+            //  context comes from the partition's first subblock
+            let first = partitions
+                .iter()
+                .position(|p| *p as usize == partition)
+                .unwrap_or_default();
+            let left_mv = if first & 3 != 0 {
+                mvs[first - 1]
+            } else {
+                left_mvs[first / 4]
+            };
+            let above_mv = if first > 3 {
+                mvs[first - 4]
+            } else {
+                above_mvs[first]
+            };
+
+            let context = if left_mv == above_mv {
+                if left_mv.is_zero() {
+                    4
+                } else {
+                    3
+                }
+            } else if above_mv.is_zero() {
+                2
+            } else if left_mv.is_zero() {
+                1
+            } else {
+                0
+            };
+
+            let mv = match self
+                .decoder
+                .read_tree(&tables::SUBMV_REF_TREE, &tables::SUBMV_REF_PROBS[context])?
+                as i8
+            {
+                tables::LEFT_4X4 => left_mv,
+                tables::ABOVE_4X4 => above_mv,
+                tables::ZERO_4X4 => MotionVector::default(),
+                _ => {
+                    let mv = self.read_mv()?;
+                    MotionVector {
+                        row: mv.row.wrapping_add(best_mv.row),
+                        col: mv.col.wrapping_add(best_mv.col),
+                    }
+                }
+            };
+
+            for (b, p) in partitions.iter().enumerate() {
+                if *p as usize == partition {
+                    mvs[b] = mv;
+                }
+            }
+        }
+
+        Ok(mvs)
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-17.1
+    fn read_mv(&mut self) -> Result<MotionVector, Error> {
+        Ok(MotionVector {
+            row: self.read_mv_component(0)?,
+            col: self.read_mv_component(1)?,
+        })
+    }
+
+    fn read_mv_component(&mut self, component: usize) -> Result<i16, Error> {
+        const IS_SHORT: usize = 0;
+        const SIGN: usize = 1;
+        const SHORT: usize = 2;
+        const LONG_BITS: usize = 9;
+        const LONG_WIDTH: usize = 10;
+
+        let probs = &self.probs.mv[component];
+        let mut x = if self.decoder.read_bool(probs[IS_SHORT] as u32)? {
+            let mut x = 0i16;
+            for i in 0..3 {
+                x += (self.decoder.read_bool(probs[LONG_BITS + i] as u32)? as i16) << i;
+            }
+            for i in (4..LONG_WIDTH).rev() {
+                x += (self.decoder.read_bool(probs[LONG_BITS + i] as u32)? as i16) << i;
+            }
+            //  bit 3 is implicit when no higher bits are set, since short
+            //  vectors already cover 0..=7
+            if x < 16 || self.decoder.read_bool(probs[LONG_BITS + 3] as u32)? {
+                x += 8;
+            }
+            x
+        } else {
+            self.decoder
+                .read_tree(&tables::SMALL_MV_TREE, &probs[SHORT..LONG_BITS])? as i16
+        };
+
+        if x != 0 && self.decoder.read_bool(probs[SIGN] as u32)? {
+            x = -x;
+        }
+        Ok(x * 2)
+    }
+}
+
+#[cfg(test)]
+fn encode_short_mv_component(encoder: &mut super::bitcode::BoolEncoder, probs: &[u8; 19], x: i8) {
+    encoder.write_bool(probs[0] as u32, 0); // is_short
+    encoder.write_tree(&tables::SMALL_MV_TREE, &probs[2..9], x.unsigned_abs());
+    if x != 0 {
+        encoder.write_bool(probs[1] as u32, (x < 0) as u8);
+    }
+}
+
+#[test]
+fn parse_key_frame_modes() {
+    use super::bitcode::BoolEncoder;
+
+    let segment_probs = [100, 50, 200];
+    let mut encoder = BoolEncoder::new();
+    encoder.write_bit(false); // color_space
+    encoder.write_bit(false); // clamping_type
+    encoder.write_bit(true); // segmentation_enabled
+    encoder.write_bit(true); // update_mb_segmentation_map
+    encoder.write_bit(false); // update_segment_feature_data
+    for prob in segment_probs {
+        encoder.write_bit(true);
+        encoder.write_literal(8, prob);
+    }
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 0); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    super::encode_quant_indices(&mut encoder, &Default::default());
+    encoder.write_bit(true); // refresh_entropy_probs
+    super::encode_no_token_prob_updates(&mut encoder);
+    encoder.write_bit(true); // mb_no_coeff_skip
+    encoder.write_literal(8, 40); // prob_skip_false
+
+    let segment_probs = segment_probs.map(|p| p as u8);
+
+    //  macroblock 0: B_PRED with every subblock context outside the frame
+    //  or within the macroblock
+    encoder.write_tree(&tables::SEGMENT_ID_TREE, &segment_probs, 2);
+    encoder.write_bool(40, 1);
+    encoder.write_tree(
+        &tables::KF_YMODE_TREE,
+        &tables::KF_YMODE_PROBS,
+        tables::B_PRED as u8,
+    );
+    let subblocks: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5];
+    for (b, mode) in subblocks.iter().enumerate() {
+        let above = if b < 4 { 0 } else { subblocks[b - 4] };
+        let left = if b & 3 == 0 { 0 } else { subblocks[b - 1] };
+        encoder.write_tree(
+            &tables::BMODE_TREE,
+            &tables::KF_BMODE_PROBS[above as usize][left as usize],
+            *mode,
+        );
+    }
+    encoder.write_tree(
+        &tables::UV_MODE_TREE,
+        &tables::KF_UV_MODE_PROBS,
+        tables::H_PRED as u8,
+    );
+
+    //  macroblock 1: TM_PRED
+    encoder.write_tree(&tables::SEGMENT_ID_TREE, &segment_probs, 1);
+    encoder.write_bool(40, 0);
+    encoder.write_tree(
+        &tables::KF_YMODE_TREE,
+        &tables::KF_YMODE_PROBS,
+        tables::TM_PRED as u8,
+    );
+    encoder.write_tree(
+        &tables::UV_MODE_TREE,
+        &tables::KF_UV_MODE_PROBS,
+        tables::V_PRED as u8,
+    );
+
+    let frame_type = FrameTagType::KeyFrame {
+        width: 20,
+        width_scale: 0,
+        height: 16,
+        height_scale: 0,
+    };
+    let frame = super::frame_with_tag(&frame_type, encoder.flush());
+    let info = FrameInfo::parse(&frame).unwrap();
+    let macroblocks = info
+        .parse_macroblock_headers(&frame, (20, 16), &ModeProbs::default())
+        .unwrap();
+
+    assert_eq!(
+        macroblocks,
+        vec![
+            MacroblockHeader {
+                segment_id: Some(2),
+                skip_coeff: true,
+                prediction: Prediction::Intra {
+                    luma: LumaMode::B,
+                    subblocks: subblocks.map(|mode| SUBBLOCK_MODES[mode as usize]),
+                    chroma: ChromaMode::H,
+                },
+            },
+            MacroblockHeader {
+                segment_id: Some(1),
+                skip_coeff: false,
+                prediction: Prediction::Intra {
+                    luma: LumaMode::Tm,
+                    subblocks: [SubblockMode::Tm; 16],
+                    chroma: ChromaMode::V,
+                },
+            },
+        ]
+    );
+
+    let stats = ModeStats::from_macroblocks(&macroblocks);
+    assert_eq!(stats.intra, 2);
+    assert_eq!(stats.intra_b_pred, 1);
+    assert_eq!(stats.percent(stats.skipped), 50.0);
+
+    assert!(matches!(
+        info.parse_macroblock_headers(&frame[..frame.len() - 1], (20, 16), &ModeProbs::default()),
+        Err(Error::TruncatedFirstPartition)
+    ));
+}
+
+/// Writes an inter frame header without segmentation, loop filtering or
+/// probability updates, up to the intra mode probabilities
+#[cfg(test)]
+fn encode_inter_frame_header(encoder: &mut super::bitcode::BoolEncoder) {
+    encoder.write_bit(false); // segmentation_enabled
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 0); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(false); // loop_filter_adj_enable
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    super::encode_quant_indices(encoder, &Default::default());
+    encoder.write_bit(false); // refresh_golden_frame
+    encoder.write_bit(false); // refresh_alternate_frame
+    encoder.write_literal(2, 0); // copy_buffer_to_golden
+    encoder.write_literal(2, 0); // copy_buffer_to_alternate
+    encoder.write_bit(false); // sign_bias_golden
+    encoder.write_bit(false); // sign_bias_alternate
+    encoder.write_bit(true); // refresh_entropy_probs
+    encoder.write_bit(true); // refresh_last
+    super::encode_no_token_prob_updates(encoder);
+    encoder.write_bit(false); // mb_no_coeff_skip
+    encoder.write_literal(8, 60); // prob_intra
+    encoder.write_literal(8, 150); // prob_last
+    encoder.write_literal(8, 80); // prob_gf
+}
+
+#[test]
+fn parse_inter_frame_modes() {
+    use super::bitcode::BoolEncoder;
+
+    let mut encoder = BoolEncoder::new();
+    encode_inter_frame_header(&mut encoder);
+    encoder.write_bit(true); // intra_16x16_prob_update_flag
+    for prob in [10, 20, 30, 40] {
+        encoder.write_literal(8, prob);
+    }
+    encoder.write_bit(false); // intra_chroma_prob_update_flag
+    for update_prob in tables::MV_UPDATE_PROBS.iter().flatten() {
+        encoder.write_bool(*update_prob as u32, 0);
+    }
+
+    let mv_probs = tables::DEFAULT_MV_PROBS;
+    let contexts = |counts: [usize; 4]| -> [u32; 4] {
+        [0, 1, 2, 3].map(|i| tables::MODE_CONTEXTS[counts[i]][i] as u32)
+    };
+
+    //  macroblock 0: intra V_PRED using the updated ymode probabilities
+    encoder.write_bool(60, 0);
+    encoder.write_tree(&tables::YMODE_TREE, &[10, 20, 30, 40], tables::V_PRED as u8);
+    encoder.write_tree(
+        &tables::UV_MODE_TREE,
+        &tables::UV_MODE_PROBS,
+        tables::DC_PRED as u8,
+    );
+
+    //  macroblock 1: last frame, new mv with only an intra neighbor
+    encoder.write_bool(60, 1);
+    encoder.write_bool(150, 0);
+    let probs = contexts([0, 0, 0, 0]);
+    for (prob, bit) in probs.iter().zip([1, 1, 1, 0]) {
+        encoder.write_bool(*prob, bit);
+    }
+    encode_short_mv_component(&mut encoder, &mv_probs[0], 4);
+    encode_short_mv_component(&mut encoder, &mv_probs[1], -6);
+
+    //  macroblock 2: golden frame, zero mv
+    encoder.write_bool(60, 1);
+    encoder.write_bool(150, 1);
+    encoder.write_bool(80, 0);
+    encoder.write_bool(contexts([0, 0, 0, 0])[0], 0);
+
+    //  macroblock 3: last frame, nearest mv taken from above while the left
+    //  zero mv counts towards the intra context
+    encoder.write_bool(60, 1);
+    encoder.write_bool(150, 0);
+    let probs = contexts([2, 2, 0, 0]);
+    encoder.write_bool(probs[0], 1);
+    encoder.write_bool(probs[1], 0);
+
+    let frame = super::frame_with_tag(&FrameTagType::InterFrame, encoder.flush());
+    let info = FrameInfo::parse(&frame).unwrap();
+    let macroblocks = info
+        .parse_macroblock_headers(&frame, (32, 32), &ModeProbs::default())
+        .unwrap();
+
+    let new_mv = MotionVector { row: 8, col: -12 };
+    assert_eq!(
+        macroblocks
+            .iter()
+            .map(|mb| mb.prediction.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            Prediction::Intra {
+                luma: LumaMode::V,
+                subblocks: [SubblockMode::Ve; 16],
+                chroma: ChromaMode::Dc,
+            },
+            Prediction::Inter {
+                ref_frame: RefFrame::Last,
+                mode: InterMode::New,
+                mv: new_mv,
+                subblock_mvs: None,
+            },
+            Prediction::Inter {
+                ref_frame: RefFrame::Golden,
+                mode: InterMode::Zero,
+                mv: MotionVector::default(),
+                subblock_mvs: None,
+            },
+            Prediction::Inter {
+                ref_frame: RefFrame::Last,
+                mode: InterMode::Nearest,
+                mv: new_mv,
+                subblock_mvs: None,
+            },
+        ]
+    );
+    assert!(macroblocks
+        .iter()
+        .all(|mb| mb.segment_id.is_none() && !mb.skip_coeff));
+
+    assert_eq!(
+        ModeStats::from_macroblocks(&macroblocks),
+        ModeStats {
+            macroblocks: 4,
+            skipped: 0,
+            intra: 1,
+            intra_b_pred: 0,
+            last: 2,
+            golden: 1,
+            altref: 0,
+            zero_mv: 1,
+            new_mv: 1,
+            split_mv: 0,
+        }
+    );
+}
+
+#[test]
+fn parse_wide_inter_frame_modes() {
+    use super::bitcode::BoolEncoder;
+
+    //  256 macroblock columns, whose motion vector bounds overflow an i16
+    let (width, mb_cols) = (4096, 256);
+    let mut encoder = BoolEncoder::new();
+    encode_inter_frame_header(&mut encoder);
+    encoder.write_bit(false); // intra_16x16_prob_update_flag
+    encoder.write_bit(false); // intra_chroma_prob_update_flag
+    for update_prob in tables::MV_UPDATE_PROBS.iter().flatten() {
+        encoder.write_bool(*update_prob as u32, 0);
+    }
+
+    let mv_probs = tables::DEFAULT_MV_PROBS;
+    let contexts = |counts: [usize; 4]| -> [u32; 4] {
+        [0, 1, 2, 3].map(|i| tables::MODE_CONTEXTS[counts[i]][i] as u32)
+    };
+
+    //  macroblock 0: last frame, new mv
+    encoder.write_bool(60, 1);
+    encoder.write_bool(150, 0);
+    for (prob, bit) in contexts([0, 0, 0, 0]).iter().zip([1, 1, 1, 0]) {
+        encoder.write_bool(*prob, bit);
+    }
+    encode_short_mv_component(&mut encoder, &mv_probs[0], 4);
+    encode_short_mv_component(&mut encoder, &mv_probs[1], -6);
+
+    //  the rest up to the last column: nearest mv taken from the left
+    let probs = contexts([0, 2, 0, 0]);
+    for _ in 1..mb_cols {
+        encoder.write_bool(60, 1);
+        encoder.write_bool(150, 0);
+        encoder.write_bool(probs[0], 1);
+        encoder.write_bool(probs[1], 0);
+    }
+
+    let frame = super::frame_with_tag(&FrameTagType::InterFrame, encoder.flush());
+    let info = FrameInfo::parse(&frame).unwrap();
+    let macroblocks = info
+        .parse_macroblock_headers(&frame, (width, 16), &ModeProbs::default())
+        .unwrap();
+
+    assert_eq!(macroblocks.len(), mb_cols);
+    for (mb_col, mb) in macroblocks.iter().enumerate() {
+        assert_eq!(
+            mb.prediction,
+            Prediction::Inter {
+                ref_frame: RefFrame::Last,
+                mode: if mb_col == 0 {
+                    InterMode::New
+                } else {
+                    InterMode::Nearest
+                },
+                mv: MotionVector { row: 8, col: -12 },
+                subblock_mvs: None,
+            }
+        );
+    }
+}
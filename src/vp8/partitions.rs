@@ -0,0 +1,135 @@
+use crate::Error;
+
+/// Byte range of a partition, relative to the start of the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Partition {
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+}
+
+/// Layout of a frame's first (mode) partition and DCT token partitions,
+/// https://datatracker.ietf.org/doc/html/rfc6386#section-9.5
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partitions {
+    pub first: Partition,
+    pub tokens: Vec<Partition>,
+}
+
+impl Partitions {
+    /// `tag_size` is the size of the frame tag (3 bytes, 10 for key frames)
+    /// that precedes the first partition in `frame`.
+    pub fn parse(
+        frame: &[u8],
+        tag_size: usize,
+        first_part_size: u32,
+        log2_nbr_of_dct_partitions: u8,
+    ) -> Result<Self, Error> {
+        let first = Partition {
+            offset: tag_size,
+            len: first_part_size as usize,
+        };
+        if first.end() > frame.len() {
+            return Err(Error::TruncatedFirstPartition);
+        }
+
+        //  every partition but the last is prefixed by a 3 byte little endian size
+        let num_partitions = 1usize << log2_nbr_of_dct_partitions;
+        let table_size = 3 * (num_partitions - 1);
+        let table = frame
+            .get(first.end()..first.end() + table_size)
+            .ok_or(Error::TruncatedPartitionTable)?;
+
+        let mut offset = first.end() + table_size;
+        let mut tokens = Vec::with_capacity(num_partitions);
+        for (idx, size) in table.chunks_exact(3).enumerate() {
+            let len = size[0] as usize | (size[1] as usize) << 8 | (size[2] as usize) << 16;
+            let partition = Partition { offset, len };
+            if partition.end() > frame.len() {
+                return Err(Error::TruncatedPartition(idx));
+            }
+            offset = partition.end();
+            tokens.push(partition);
+        }
+        tokens.push(Partition {
+            offset,
+            len: frame.len() - offset,
+        });
+
+        Ok(Self { first, tokens })
+    }
+
+    /// Bytes spent on residual data, as opposed to the frame header and modes
+    pub fn token_bytes(&self) -> usize {
+        self.tokens.iter().map(|p| p.len).sum()
+    }
+}
+
+#[test]
+fn parse_partitions() {
+    let mut frame = vec![0u8; 3];
+    frame.extend([1u8; 20]); // first partition
+    frame.extend([4, 0, 0, 0, 1, 0, 2, 0, 0]); // partition sizes
+    frame.extend([2u8; 4]);
+    frame.extend([3u8; 256]);
+    frame.extend([4u8; 2]);
+    frame.extend([5u8; 7]);
+
+    let partitions = Partitions::parse(&frame, 3, 20, 2).unwrap();
+    assert_eq!(partitions.first, Partition { offset: 3, len: 20 });
+    assert_eq!(
+        partitions.tokens,
+        vec![
+            Partition { offset: 32, len: 4 },
+            Partition {
+                offset: 36,
+                len: 256
+            },
+            Partition {
+                offset: 292,
+                len: 2
+            },
+            Partition {
+                offset: 294,
+                len: 7
+            },
+        ]
+    );
+    assert_eq!(partitions.token_bytes(), 269);
+}
+
+#[test]
+fn parse_truncated_partitions() {
+    let mut frame = vec![0u8; 3];
+    frame.extend([1u8; 20]);
+    assert!(matches!(
+        Partitions::parse(&frame, 3, 21, 0),
+        Err(Error::TruncatedFirstPartition)
+    ));
+    assert_eq!(
+        Partitions::parse(&frame, 3, 20, 0).unwrap().tokens,
+        vec![Partition { offset: 23, len: 0 }]
+    );
+
+    frame.extend([4, 0, 0, 0, 1]);
+    assert!(matches!(
+        Partitions::parse(&frame, 3, 20, 2),
+        Err(Error::TruncatedPartitionTable)
+    ));
+
+    frame.push(0);
+    frame.extend([2u8; 10]);
+    assert!(matches!(
+        Partitions::parse(&frame, 3, 20, 1),
+        Ok(Partitions { tokens, .. }) if tokens.len() == 2
+    ));
+    assert!(matches!(
+        Partitions::parse(&frame, 3, 20, 2),
+        Err(Error::TruncatedPartition(1))
+    ));
+}
@@ -0,0 +1,242 @@
+use serde::Serialize;
+
+use super::{FrameBufferUpdate, FrameHeader, ModeStats, RefFrame};
+
+/// Identifies the frame that wrote a reference buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FrameSource {
+    /// RTP timestamp
+    pub pts: u32,
+    pub picture_id: Option<u16>,
+}
+
+/// Which frames currently occupy the last, golden and altref buffers.
+/// Frames must be passed to `update` in decode order.
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceTracker {
+    last: Option<Buffered>,
+    golden: Option<Buffered>,
+    altref: Option<Buffered>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Buffered {
+    source: FrameSource,
+    /// Cleared when the frame, or one it was predicted from, was lost
+    decodable: bool,
+}
+
+/// References available to a frame, `None` when no frame has written the
+/// buffer yet or the frame is a key frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ReferenceSources {
+    pub last: Option<FrameSource>,
+    pub golden: Option<FrameSource>,
+    pub altref: Option<FrameSource>,
+    /// Motion vectors from golden/altref macroblocks are inverted when used
+    /// to predict last frame macroblocks, and vice versa
+    pub sign_bias_golden: bool,
+    pub sign_bias_altref: bool,
+}
+
+impl ReferenceTracker {
+    pub fn get(&self, ref_frame: RefFrame) -> Option<FrameSource> {
+        self.buffer(ref_frame).map(|buffered| buffered.source)
+    }
+
+    fn buffer(&self, ref_frame: RefFrame) -> Option<Buffered> {
+        match ref_frame {
+            RefFrame::Last => self.last,
+            RefFrame::Golden => self.golden,
+            RefFrame::AltRef => self.altref,
+        }
+    }
+
+    /// Whether a frame with `header` can be decoded from the current buffers.
+    /// Each buffer an inter frame predicts from must hold a frame whose whole
+    /// reference chain back to a key frame was received. Without the frame's
+    /// macroblock `modes` it may predict from any buffer.
+    pub fn decodable(&self, header: &FrameHeader, modes: Option<&ModeStats>) -> bool {
+        header.is_key_frame() || self.buffers_decodable(modes)
+    }
+
+    fn buffers_decodable(&self, modes: Option<&ModeStats>) -> bool {
+        let used = |count: fn(&ModeStats) -> u32| modes.is_none_or(|modes| count(modes) > 0);
+        [
+            (self.last, used(|modes| modes.last)),
+            (self.golden, used(|modes| modes.golden)),
+            (self.altref, used(|modes| modes.altref)),
+        ]
+        .iter()
+        .filter(|(_, used)| *used)
+        .all(|(buffered, _)| buffered.is_some_and(|b| b.decodable))
+    }
+
+    /// Returns the references `source` is predicted from, then applies its
+    /// buffer updates. Buffers written by an incomplete frame become
+    /// undecodable.
+    pub fn update(
+        &mut self,
+        source: FrameSource,
+        header: &FrameHeader,
+        modes: Option<&ModeStats>,
+        complete: bool,
+    ) -> ReferenceSources {
+        let sources = if header.is_key_frame() {
+            ReferenceSources::default()
+        } else {
+            ReferenceSources {
+                last: self.get(RefFrame::Last),
+                golden: self.get(RefFrame::Golden),
+                altref: self.get(RefFrame::AltRef),
+                sign_bias_golden: header.sign_bias_golden,
+                sign_bias_altref: header.sign_bias_altref,
+            }
+        };
+        let decodable = complete && self.decodable(header, modes);
+        self.apply(source, decodable, &header.frame_buffer_update);
+        sources
+    }
+
+    /// Records a frame lost before its header could be parsed, which may
+    /// have written any buffer
+    pub fn lose_frame(&mut self) {
+        for buffered in [&mut self.last, &mut self.golden, &mut self.altref]
+            .into_iter()
+            .flatten()
+        {
+            buffered.decodable = false;
+        }
+    }
+
+    ///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.7
+    fn apply(&mut self, source: FrameSource, decodable: bool, update: &FrameBufferUpdate) {
+        let buffered = Some(Buffered { source, decodable });
+        //  libvpx copies to altref first, so copying altref to golden in the
+        //  same frame sees the new altref
+        if let Some(ref_frame) = update.copy_to_altref {
+            self.altref = self.buffer(ref_frame);
+        }
+        if let Some(ref_frame) = update.copy_to_golden {
+            self.golden = self.buffer(ref_frame);
+        }
+        if update.golden {
+            self.golden = buffered;
+        }
+        if update.altref {
+            self.altref = buffered;
+        }
+        if update.last {
+            self.last = buffered;
+        }
+    }
+}
+
+#[test]
+fn apply_buffer_updates() {
+    let frame = |pts| FrameSource {
+        pts,
+        picture_id: Some(pts as u16),
+    };
+    let mut tracker = ReferenceTracker::default();
+
+    tracker.apply(frame(0), true, &FrameBufferUpdate::key_frame());
+    tracker.apply(
+        frame(1),
+        true,
+        &FrameBufferUpdate {
+            last: true,
+            golden: false,
+            altref: false,
+            copy_to_golden: None,
+            copy_to_altref: None,
+        },
+    );
+    assert_eq!(tracker.get(RefFrame::Last), Some(frame(1)));
+    assert_eq!(tracker.get(RefFrame::Golden), Some(frame(0)));
+    assert_eq!(tracker.get(RefFrame::AltRef), Some(frame(0)));
+
+    //  golden takes the altref that was just copied from last
+    tracker.apply(
+        frame(2),
+        true,
+        &FrameBufferUpdate {
+            last: false,
+            golden: false,
+            altref: false,
+            copy_to_golden: Some(RefFrame::AltRef),
+            copy_to_altref: Some(RefFrame::Last),
+        },
+    );
+    assert_eq!(tracker.get(RefFrame::Last), Some(frame(1)));
+    assert_eq!(tracker.get(RefFrame::Golden), Some(frame(1)));
+    assert_eq!(tracker.get(RefFrame::AltRef), Some(frame(1)));
+
+    //  copies see the buffers from before this frame
+    tracker.apply(
+        frame(3),
+        true,
+        &FrameBufferUpdate {
+            last: true,
+            golden: true,
+            altref: false,
+            copy_to_golden: None,
+            copy_to_altref: Some(RefFrame::Last),
+        },
+    );
+    assert_eq!(tracker.get(RefFrame::Last), Some(frame(3)));
+    assert_eq!(tracker.get(RefFrame::Golden), Some(frame(3)));
+    assert_eq!(tracker.get(RefFrame::AltRef), Some(frame(1)));
+}
+
+#[test]
+fn track_decodable_buffers() {
+    let frame = |pts| FrameSource {
+        pts,
+        picture_id: None,
+    };
+    let last_only = FrameBufferUpdate {
+        last: true,
+        golden: false,
+        altref: false,
+        copy_to_golden: None,
+        copy_to_altref: None,
+    };
+    let mut tracker = ReferenceTracker::default();
+    assert!(!tracker.buffers_decodable(None));
+
+    tracker.apply(frame(0), true, &FrameBufferUpdate::key_frame());
+    assert!(tracker.buffers_decodable(None));
+
+    tracker.apply(frame(1), false, &last_only);
+    assert!(!tracker.buffers_decodable(None));
+    tracker.apply(frame(2), true, &last_only);
+    assert!(tracker.buffers_decodable(None));
+
+    tracker.lose_frame();
+    assert!(!tracker.buffers_decodable(None));
+    assert_eq!(tracker.get(RefFrame::Last), Some(frame(2)));
+    tracker.apply(frame(3), true, &FrameBufferUpdate::key_frame());
+    assert!(tracker.buffers_decodable(None));
+
+    //  frames predicting only from last don't need the lost golden frame
+    tracker.apply(
+        frame(4),
+        false,
+        &FrameBufferUpdate {
+            last: false,
+            golden: true,
+            ..last_only
+        },
+    );
+    assert!(!tracker.buffers_decodable(None));
+    let modes = |last, golden| ModeStats {
+        last,
+        golden,
+        ..Default::default()
+    };
+    assert!(tracker.buffers_decodable(Some(&modes(10, 0))));
+    assert!(!tracker.buffers_decodable(Some(&modes(10, 1))));
+    //  nor do frames made up of intra macroblocks
+    assert!(tracker.buffers_decodable(Some(&modes(0, 0))));
+}
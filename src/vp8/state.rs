@@ -0,0 +1,478 @@
+use super::tables::{self, TokenProbs};
+use super::{
+    FrameInfo, MacroblockHeader, ModeProbs, SegmentFeatureData, SegmentFeatureMode,
+    TokenProbUpdates,
+};
+use crate::Error;
+
+/// Header state that persists from one frame to the next: segmentation,
+/// loop filter deltas and entropy probabilities. Frames must be pushed in
+/// decode order, and everything is reset by key frames.
+///
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.11
+#[derive(Debug, Clone)]
+pub struct Vp8StreamState {
+    dimensions: Option<(u16, u16)>,
+    segment_feature_data: SegmentFeatureData,
+    /// Only tracked by `push_with_macroblocks`
+    segment_map: Vec<u8>,
+    loop_filter_deltas: EffectiveLoopFilterDeltas,
+    token_probs: TokenProbs,
+    mode_probs: ModeProbs,
+}
+
+/// Parameters a frame is decoded with once earlier frames' state is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveParams {
+    /// Coded dimensions from the most recent key frame
+    pub dimensions: Option<(u16, u16)>,
+    /// Set when segmentation is enabled for this frame
+    pub segment_feature_data: Option<SegmentFeatureData>,
+    /// Quantizer index (y_ac_qi) used by each segment
+    pub quantizer: [u8; 4],
+    /// Loop filter level used by each segment, before loop filter deltas
+    pub loop_filter_level: [u8; 4],
+    /// Set when loop_filter_adj_enable
+    pub loop_filter_deltas: Option<EffectiveLoopFilterDeltas>,
+    pub token_probs: TokenProbs,
+    pub mode_probs: ModeProbs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EffectiveLoopFilterDeltas {
+    /// Indexed by reference frame: intra, last, golden, altref
+    pub ref_frame: [i8; 4],
+    /// Indexed by mode: B_PRED, ZEROMV, NEARESTMV/NEARMV/NEWMV, SPLITMV
+    pub mode: [i8; 4],
+}
+
+#[derive(Debug)]
+pub struct StreamFrame {
+    pub info: FrameInfo,
+    pub params: EffectiveParams,
+    /// Only set by `push_with_macroblocks`, with segment ids filled in from
+    /// the persistent segment map when segmentation is enabled.
+    pub macroblocks: Option<Vec<MacroblockHeader>>,
+}
+
+impl Default for Vp8StreamState {
+    fn default() -> Self {
+        Self {
+            dimensions: None,
+            segment_feature_data: Default::default(),
+            segment_map: vec![],
+            loop_filter_deltas: Default::default(),
+            token_probs: tables::DEFAULT_TOKEN_PROBS,
+            mode_probs: Default::default(),
+        }
+    }
+}
+
+impl Vp8StreamState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Coded dimensions from the most recent key frame
+    pub fn dimensions(&self) -> Option<(u16, u16)> {
+        self.dimensions
+    }
+
+    /// Parses the next frame's header and applies it to the stream state.
+    /// Only the start of the first partition is needed.
+    pub fn push(&mut self, frame: &[u8]) -> Result<StreamFrame, Error> {
+        self.push_frame(frame, false)
+    }
+
+    /// Like `push`, also decoding macroblock headers and tracking the segment
+    /// map. Needs the whole first partition and a preceding key frame.
+    pub fn push_with_macroblocks(&mut self, frame: &[u8]) -> Result<StreamFrame, Error> {
+        self.push_frame(frame, true)
+    }
+
+    fn push_frame(&mut self, frame: &[u8], parse_macroblocks: bool) -> Result<StreamFrame, Error> {
+        let info = FrameInfo::parse(frame)?;
+        //  key frames reset everything
+        let reset = info.tag.frame_type.dimensions().map(|dimensions| Self {
+            dimensions: Some(dimensions),
+            ..Default::default()
+        });
+
+        //  decode macroblocks before touching any state so a truncated frame
+        //  doesn't leave it half updated
+        let mut macroblocks = if parse_macroblocks {
+            let state = reset.as_ref().unwrap_or(self);
+            let dimensions = state.dimensions.ok_or(Error::MissingKeyFrame)?;
+            Some(info.parse_macroblock_headers(frame, dimensions, &state.mode_probs)?)
+        } else {
+            None
+        };
+        if let Some(reset) = reset {
+            *self = reset;
+        }
+
+        let header = &info.header;
+        if let Some(feature_data) = header
+            .segmentation
+            .as_ref()
+            .and_then(|s| s.feature_data.as_ref())
+        {
+            self.segment_feature_data = feature_data.clone();
+        }
+        if let Some(deltas) = &header.loop_filter_deltas {
+            let persistent = &mut self.loop_filter_deltas;
+            for (delta, update) in persistent
+                .ref_frame
+                .iter_mut()
+                .chain(persistent.mode.iter_mut())
+                .zip(deltas.ref_frame.iter().chain(deltas.mode.iter()))
+            {
+                if let Some(update) = update {
+                    *delta = *update;
+                }
+            }
+        }
+
+        //  probabilities are saved before this frame's updates and restored
+        //  once it is decoded
+        let saved_probs = if header.refresh_entropy_probs {
+            None
+        } else {
+            Some((self.token_probs, self.mode_probs.clone()))
+        };
+        apply_token_prob_updates(&mut self.token_probs, &header.token_prob_updates);
+        if let Some(inter_probs) = &header.inter_probs {
+            self.mode_probs.update(inter_probs);
+        }
+
+        let segment_feature_data = header
+            .segmentation
+            .as_ref()
+            .map(|_| self.segment_feature_data.clone());
+        let (quantizer, loop_filter_level) = match &segment_feature_data {
+            Some(data) => (
+                resolve_segment_values(
+                    data.mode,
+                    header.quant_indices.y_ac_qi,
+                    data.quantizer,
+                    127,
+                ),
+                resolve_segment_values(
+                    data.mode,
+                    header.loop_filter_level,
+                    data.loop_filter_level,
+                    63,
+                ),
+            ),
+            None => (
+                [header.quant_indices.y_ac_qi; 4],
+                [header.loop_filter_level; 4],
+            ),
+        };
+
+        let params = EffectiveParams {
+            dimensions: self.dimensions,
+            segment_feature_data,
+            quantizer,
+            loop_filter_level,
+            loop_filter_deltas: header
+                .loop_filter_adj_enable
+                .then_some(self.loop_filter_deltas),
+            token_probs: self.token_probs,
+            mode_probs: self.mode_probs.clone(),
+        };
+
+        if let Some((token_probs, mode_probs)) = saved_probs {
+            self.token_probs = token_probs;
+            self.mode_probs = mode_probs;
+        }
+
+        if let Some(macroblocks) = &mut macroblocks {
+            //  key frames reset every macroblock to segment 0 unless the map
+            //  is updated
+            if info.tag.frame_type.is_key_frame() || self.segment_map.len() != macroblocks.len() {
+                self.segment_map = vec![0; macroblocks.len()];
+            }
+            if header.segmentation.is_some() {
+                for (segment_id, mb) in self.segment_map.iter_mut().zip(macroblocks.iter_mut()) {
+                    match mb.segment_id {
+                        Some(id) => *segment_id = id,
+                        None => mb.segment_id = Some(*segment_id),
+                    }
+                }
+            }
+        }
+
+        Ok(StreamFrame {
+            info,
+            params,
+            macroblocks,
+        })
+    }
+}
+
+fn apply_token_prob_updates(token_probs: &mut TokenProbs, updates: &TokenProbUpdates) {
+    for (prob, update) in token_probs
+        .iter_mut()
+        .flatten()
+        .flatten()
+        .flatten()
+        .zip(updates.0.iter().flatten().flatten().flatten())
+    {
+        if let Some(update) = update {
+            *prob = *update;
+        }
+    }
+}
+
+///  https://datatracker.ietf.org/doc/html/rfc6386#section-9.3
+fn resolve_segment_values(mode: SegmentFeatureMode, base: u8, values: [i8; 4], max: u8) -> [u8; 4] {
+    values.map(|value| {
+        let value = match mode {
+            SegmentFeatureMode::Absolute => value as i32,
+            SegmentFeatureMode::Delta => base as i32 + value as i32,
+        };
+        value.clamp(0, max as i32) as u8
+    })
+}
+
+#[cfg(test)]
+use super::{bitcode::BoolEncoder, FrameTagType, LoopFilterDeltas};
+
+/// Frame with segmentation and loop filter adjustments enabled, whose
+/// header optionally updates the persistent state
+#[cfg(test)]
+pub(crate) fn encode_frame(
+    key_frame: bool,
+    feature_data: Option<&SegmentFeatureData>,
+    loop_filter_deltas: Option<&LoopFilterDeltas>,
+    refresh_entropy_probs: bool,
+    token_prob_update: Option<u8>,
+) -> Vec<u8> {
+    let mut encoder = BoolEncoder::new();
+    if key_frame {
+        encoder.write_bit(false); // color_space
+        encoder.write_bit(false); // clamping_type
+    }
+    encoder.write_bit(true); // segmentation_enabled
+    encoder.write_bit(false); // update_mb_segmentation_map
+    encoder.write_bit(feature_data.is_some()); // update_segment_feature_data
+    if let Some(data) = feature_data {
+        encoder.write_bit(data.mode == SegmentFeatureMode::Absolute);
+        for (value, bits) in data
+            .quantizer
+            .iter()
+            .map(|q| (q, 7))
+            .chain(data.loop_filter_level.iter().map(|lf| (lf, 6)))
+        {
+            encoder.write_bit(*value != 0);
+            if *value != 0 {
+                encoder.write_signed_literal(bits, *value as i32);
+            }
+        }
+    }
+    encoder.write_bit(false); // filter_type
+    encoder.write_literal(6, 40); // loop_filter_level
+    encoder.write_literal(3, 0); // sharpness_level
+    encoder.write_bit(true); // loop_filter_adj_enable
+    encoder.write_bit(loop_filter_deltas.is_some()); // mode_ref_lf_delta_update
+    if let Some(deltas) = loop_filter_deltas {
+        for delta in deltas.ref_frame.iter().chain(deltas.mode.iter()) {
+            encoder.write_bit(delta.is_some());
+            if let Some(delta) = delta {
+                encoder.write_signed_literal(6, *delta as i32);
+            }
+        }
+    }
+    encoder.write_literal(2, 0); // log2_nbr_of_dct_partitions
+    super::encode_quant_indices(
+        &mut encoder,
+        &super::QuantIndices {
+            y_ac_qi: 100,
+            ..Default::default()
+        },
+    );
+    if !key_frame {
+        encoder.write_bit(false); // refresh_golden_frame
+        encoder.write_bit(false); // refresh_alternate_frame
+        encoder.write_literal(2, 0); // copy_buffer_to_golden
+        encoder.write_literal(2, 0); // copy_buffer_to_alternate
+        encoder.write_bit(false); // sign_bias_golden
+        encoder.write_bit(false); // sign_bias_alternate
+    }
+    encoder.write_bit(refresh_entropy_probs);
+    if !key_frame {
+        encoder.write_bit(true); // refresh_last
+    }
+    for (idx, update_prob) in tables::COEFF_UPDATE_PROBS
+        .iter()
+        .flatten()
+        .flatten()
+        .flatten()
+        .enumerate()
+    {
+        match token_prob_update {
+            Some(prob) if idx == 0 => {
+                encoder.write_bool(*update_prob as u32, 1);
+                encoder.write_literal(8, prob as u32);
+            }
+            _ => encoder.write_bool(*update_prob as u32, 0),
+        }
+    }
+    encoder.write_bit(false); // mb_no_coeff_skip
+    if !key_frame {
+        encoder.write_literal(8, 128); // prob_intra
+        encoder.write_literal(8, 128); // prob_last
+        encoder.write_literal(8, 128); // prob_gf
+        super::encode_no_mode_prob_updates(&mut encoder);
+    }
+
+    let frame_type = if key_frame {
+        FrameTagType::KeyFrame {
+            width: 16,
+            width_scale: 0,
+            height: 16,
+            height_scale: 0,
+        }
+    } else {
+        FrameTagType::InterFrame
+    };
+    super::frame_with_tag(&frame_type, encoder.flush())
+}
+
+#[test]
+fn persistent_segmentation_and_loop_filter_deltas() {
+    let mut state = Vp8StreamState::new();
+
+    let feature_data = SegmentFeatureData {
+        mode: SegmentFeatureMode::Delta,
+        quantizer: [0, -20, 40, -120],
+        loop_filter_level: [-3, 0, 30, 0],
+    };
+    let deltas = LoopFilterDeltas {
+        ref_frame: [Some(2), None, Some(-2), Some(-2)],
+        mode: [Some(4), Some(-12), Some(2), Some(4)],
+    };
+    let key_frame = encode_frame(true, Some(&feature_data), Some(&deltas), true, None);
+    let params = state.push(&key_frame).unwrap().params;
+    assert_eq!(params.dimensions, Some((16, 16)));
+    assert_eq!(params.segment_feature_data, Some(feature_data.clone()));
+    assert_eq!(params.quantizer, [100, 80, 127, 0]);
+    assert_eq!(params.loop_filter_level, [37, 40, 63, 40]);
+    assert_eq!(
+        params.loop_filter_deltas,
+        Some(EffectiveLoopFilterDeltas {
+            ref_frame: [2, 0, -2, -2],
+            mode: [4, -12, 2, 4],
+        })
+    );
+
+    //  inter frames keep values that aren't updated
+    let deltas = LoopFilterDeltas {
+        ref_frame: [None, Some(1), None, None],
+        mode: [None; 4],
+    };
+    let inter_frame = encode_frame(false, None, Some(&deltas), true, None);
+    let params = state.push(&inter_frame).unwrap().params;
+    assert_eq!(params.dimensions, Some((16, 16)));
+    assert_eq!(params.segment_feature_data, Some(feature_data));
+    assert_eq!(params.quantizer, [100, 80, 127, 0]);
+    assert_eq!(
+        params.loop_filter_deltas,
+        Some(EffectiveLoopFilterDeltas {
+            ref_frame: [2, 1, -2, -2],
+            mode: [4, -12, 2, 4],
+        })
+    );
+
+    let feature_data = SegmentFeatureData {
+        mode: SegmentFeatureMode::Absolute,
+        quantizer: [10, 20, 30, 40],
+        loop_filter_level: [0, 0, 0, 0],
+    };
+    let inter_frame = encode_frame(false, Some(&feature_data), None, true, None);
+    let params = state.push(&inter_frame).unwrap().params;
+    assert_eq!(params.quantizer, [10, 20, 30, 40]);
+    assert_eq!(params.loop_filter_level, [0; 4]);
+
+    //  key frames reset everything
+    let key_frame = encode_frame(true, None, None, true, None);
+    let params = state.push(&key_frame).unwrap().params;
+    assert_eq!(params.segment_feature_data, Some(Default::default()));
+    assert_eq!(params.quantizer, [100; 4]);
+    assert_eq!(params.loop_filter_deltas, Some(Default::default()));
+}
+
+#[test]
+fn persistent_entropy_probs() {
+    let mut state = Vp8StreamState::new();
+    let default_prob = tables::DEFAULT_TOKEN_PROBS[0][0][0][0];
+
+    let params = state
+        .push(&encode_frame(true, None, None, true, Some(7)))
+        .unwrap()
+        .params;
+    assert_eq!(params.token_probs[0][0][0][0], 7);
+
+    //  updates with refresh_entropy_probs=0 only last for one frame
+    let params = state
+        .push(&encode_frame(false, None, None, false, Some(9)))
+        .unwrap()
+        .params;
+    assert_eq!(params.token_probs[0][0][0][0], 9);
+    let params = state
+        .push(&encode_frame(false, None, None, true, None))
+        .unwrap()
+        .params;
+    assert_eq!(params.token_probs[0][0][0][0], 7);
+
+    let params = state
+        .push(&encode_frame(true, None, None, true, None))
+        .unwrap()
+        .params;
+    assert_eq!(params.token_probs[0][0][0][0], default_prob);
+}
+
+#[test]
+fn missing_key_frame() {
+    let mut state = Vp8StreamState::new();
+    let inter_frame = encode_frame(false, None, None, true, None);
+    assert!(state
+        .push(&inter_frame)
+        .unwrap()
+        .params
+        .dimensions
+        .is_none());
+    assert!(matches!(
+        state.push_with_macroblocks(&inter_frame),
+        Err(Error::MissingKeyFrame)
+    ));
+}
+
+#[test]
+fn truncated_key_frame() {
+    let mut state = Vp8StreamState::new();
+    let feature_data = SegmentFeatureData {
+        mode: SegmentFeatureMode::Absolute,
+        quantizer: [10, 20, 30, 40],
+        loop_filter_level: [0; 4],
+    };
+    let key_frame = encode_frame(true, Some(&feature_data), None, true, Some(7));
+    state.push_with_macroblocks(&key_frame).unwrap();
+
+    //  a key frame whose macroblocks can't be read leaves the state as it was
+    let mut key_frame = encode_frame(true, None, None, true, None);
+    //  claiming one more first partition byte than the frame holds
+    let tag = u32::from_le_bytes([key_frame[0], key_frame[1], key_frame[2], 0]) + (1 << 5);
+    key_frame[..3].copy_from_slice(&tag.to_le_bytes()[..3]);
+    assert!(matches!(
+        state.push_with_macroblocks(&key_frame),
+        Err(Error::TruncatedFirstPartition)
+    ));
+    let params = state
+        .push(&encode_frame(false, None, None, true, None))
+        .unwrap()
+        .params;
+    assert_eq!(params.segment_feature_data, Some(feature_data));
+    assert_eq!(params.token_probs[0][0][0][0], 7);
+}
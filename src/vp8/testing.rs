@@ -4,9 +4,23 @@ use vpx_sys::VPX_DECODER_ABI_VERSION;
 
 use super::FrameBufferUpdate;
 
-#[derive(bincode::Encode, bincode::Decode)]
-struct TestFrames {
-    frames: Vec<Vec<u8>>,
+/// Reference refreshes as reported by `VP8D_GET_LAST_REF_UPDATES`, which
+/// doesn't include buffer copies.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RefUpdates {
+    last: bool,
+    golden: bool,
+    altref: bool,
+}
+
+impl From<FrameBufferUpdate> for RefUpdates {
+    fn from(update: FrameBufferUpdate) -> Self {
+        Self {
+            last: update.last,
+            golden: update.golden,
+            altref: update.altref,
+        }
+    }
 }
 
 struct Vp8TestDecoder {
@@ -31,9 +45,7 @@ impl Vp8TestDecoder {
         Self { ctx: codec }
     }
 
-    pub fn analyze_frame(&mut self, frame: &[u8]) -> FrameBufferUpdate {
-        let mut ref_update_flag = 0i32;
-
+    fn decode(&mut self, frame: &[u8]) {
         unsafe {
             assert_eq!(
                 vpx_sys::vpx_codec_decode(
@@ -45,7 +57,40 @@ impl Vp8TestDecoder {
                 ),
                 vpx_sys::vpx_codec_err_t::VPX_CODEC_OK
             );
+        }
+    }
+
+    /// Decodes `frame`, returning the image it shows as I420 planes without
+    /// their stride padding, like `I420Frame::to_bytes`
+    #[cfg(feature = "decoder")]
+    pub fn decode_frame(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        self.decode(frame);
+        unsafe {
+            let mut iter: vpx_sys::vpx_codec_iter_t = std::ptr::null();
+            let image = vpx_sys::vpx_codec_get_frame(&mut self.ctx, &mut iter).as_ref()?;
+            let (width, height) = (image.d_w as usize, image.d_h as usize);
+            let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+            let mut bytes = vec![];
+            for (plane, width, height) in [
+                (0, width, height),
+                (1, chroma_width, chroma_height),
+                (2, chroma_width, chroma_height),
+            ] {
+                for row in 0..height {
+                    let start =
+                        image.planes[plane].offset(row as isize * image.stride[plane] as isize);
+                    bytes.extend_from_slice(std::slice::from_raw_parts(start, width));
+                }
+            }
+            Some(bytes)
+        }
+    }
+
+    pub fn analyze_frame(&mut self, frame: &[u8]) -> RefUpdates {
+        let mut ref_update_flag = 0i32;
+        self.decode(frame);
 
+        unsafe {
             assert_eq!(
                 vpx_sys::vpx_codec_control_(
                     &mut self.ctx,
@@ -56,16 +101,15 @@ impl Vp8TestDecoder {
             );
         }
 
-        let _last_frame_updated =
+        let last_frame_updated =
             (ref_update_flag & vpx_sys::vpx_ref_frame_type::VP8_LAST_FRAME as i32) > 0;
         let alt_frame_updated =
             (ref_update_flag & vpx_sys::vpx_ref_frame_type::VP8_ALTR_FRAME as i32) > 0;
         let gold_frame_updated =
             (ref_update_flag & vpx_sys::vpx_ref_frame_type::VP8_GOLD_FRAME as i32) > 0;
 
-        dbg!(ref_update_flag);
-
-        FrameBufferUpdate {
+        RefUpdates {
+            last: last_frame_updated,
             golden: gold_frame_updated,
             altref: alt_frame_updated,
         }
@@ -82,39 +126,46 @@ impl Drop for Vp8TestDecoder {
 
 #[test]
 fn vpx() {
-    let frames: TestFrames = bincode::decode_from_slice(
-        &std::fs::read("test_frames.vp8").unwrap(),
-        bincode::config::Configuration::standard(),
-    )
-    .unwrap();
+    let ivf = std::fs::read("test_frames.ivf").unwrap();
+    let frames = crate::IvfReader::new(&ivf)
+        .unwrap()
+        .map(|frame| frame.unwrap().data)
+        .collect::<Vec<_>>();
 
     let mut decoder = Vp8TestDecoder::new();
 
     let expected = frames
-        .frames
         .iter()
-        .map(|f| decoder.analyze_frame(&f))
+        .map(|f| decoder.analyze_frame(f))
         .collect::<Vec<_>>();
 
     let parsed = frames
-        .frames
         .iter()
         .map(|f| {
-            crate::vp8::FrameInfo::parse(&f)
+            crate::vp8::FrameInfo::parse(f)
                 .unwrap()
                 .header
                 .frame_buffer_update
+                .into()
         })
         .collect::<Vec<_>>();
 
     assert_eq!(expected, parsed);
+}
 
-    // for (idx, frame) in frames.frames.into_iter().enumerate() {
-    //     eprintln!("testing frame {}", idx);
-    //     let expected = decoder.analyze_frame(&frame);
-
-    //     let parsed = crate::vp8::FrameInfo::parse(&frame).unwrap();
-
-    //     assert_eq!(parsed.header.frame_buffer_update, expected);
-    // }
+/// Checks the decoder's pixels against libvpx, covering the prediction, loop
+/// filtering and dequantization of an ordinary encode
+#[cfg(feature = "decoder")]
+#[test]
+fn decoder_matches_vpx() {
+    let ivf = std::fs::read("test_frames.ivf").unwrap();
+    let mut vpx = Vp8TestDecoder::new();
+    let mut decoder = crate::vp8::Decoder::new();
+    for (idx, frame) in crate::IvfReader::new(&ivf).unwrap().enumerate() {
+        let frame = frame.unwrap().data;
+        let expected = vpx.decode_frame(frame);
+        let decoded = decoder.decode(frame).unwrap().map(|frame| frame.to_bytes());
+        //  not assert_eq, which would print every pixel
+        assert!(decoded == expected, "frame {} differs from libvpx", idx);
+    }
 }
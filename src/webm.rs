@@ -0,0 +1,330 @@
+//! Minimal WebM (Matroska) demuxing, https://www.matroska.org/technical/elements.html
+//!
+//! Only the elements leading to tracks and blocks are read. Segments,
+//! clusters and the like are walked into rather than skipped, so live
+//! recordings leaving their sizes unknown can be read too.
+
+use crate::Error;
+
+//  element ids, with their length markers
+const SEGMENT: u64 = 0x18538067;
+const INFO: u64 = 0x1549a966;
+const TIMESTAMP_SCALE: u64 = 0x2ad7b1;
+const TRACKS: u64 = 0x1654ae6b;
+const TRACK_ENTRY: u64 = 0xae;
+const TRACK_NUMBER: u64 = 0xd7;
+const CODEC_ID: u64 = 0x86;
+const CLUSTER: u64 = 0x1f43b675;
+const TIMESTAMP: u64 = 0xe7;
+const SIMPLE_BLOCK: u64 = 0xa3;
+const BLOCK_GROUP: u64 = 0xa0;
+const BLOCK: u64 = 0xa1;
+const REFERENCE_BLOCK: u64 = 0xfb;
+
+/// Master elements whose children are read as if they followed them
+const WALKED_INTO: [u64; 4] = [SEGMENT, INFO, TRACKS, CLUSTER];
+
+/// Reads a variable length integer and its length. Element ids keep their
+/// length marker, sizes and track numbers have it cleared.
+fn vint(data: &[u8], keep_marker: bool) -> Result<(u64, usize), Error> {
+    let first = *data.first().ok_or(Error::TruncatedWebmFile)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(Error::InvalidWebmElement);
+    }
+    let bytes = data.get(..len).ok_or(Error::TruncatedWebmFile)?;
+    let first = if keep_marker {
+        first
+    } else {
+        first & (0xff >> len) as u8
+    };
+    let value = bytes[1..]
+        .iter()
+        .fold(first as u64, |value, byte| value << 8 | *byte as u64);
+    Ok((value, len))
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+struct Element<'a> {
+    id: u64,
+    /// `None` when the element's size is unknown
+    body: Option<&'a [u8]>,
+    /// Data following the element's header when its size is unknown, or its
+    /// body otherwise
+    rest: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let (id, id_len) = vint(data, true)?;
+        let (size, size_len) = vint(&data[id_len..], false)?;
+        let data = &data[id_len + size_len..];
+        //  all value bits set
+        if size == (1 << (7 * size_len)) - 1 {
+            return Ok(Self {
+                id,
+                body: None,
+                rest: data,
+            });
+        }
+        let size = usize::try_from(size).map_err(|_| Error::TruncatedWebmFile)?;
+        if size > data.len() {
+            return Err(Error::TruncatedWebmFile);
+        }
+        let (body, rest) = data.split_at(size);
+        Ok(Self {
+            id,
+            body: Some(body),
+            rest,
+        })
+    }
+
+    fn body(&self) -> Result<&'a [u8], Error> {
+        self.body.ok_or(Error::InvalidWebmElement)
+    }
+}
+
+/// Children of an element of known size
+fn children(mut data: &[u8]) -> Result<Vec<(u64, &[u8])>, Error> {
+    let mut children = vec![];
+    while !data.is_empty() {
+        let element = Element::parse(data)?;
+        children.push((element.id, element.body()?));
+        data = element.rest;
+    }
+    Ok(children)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebmTrack {
+    pub number: u64,
+    /// `V_VP8` for VP8
+    pub codec_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebmBlock<'a> {
+    pub track: u64,
+    /// In units of the file's timestamp scale
+    pub timestamp: i64,
+    /// Set on SimpleBlocks by their keyframe flag, and on blocks of a
+    /// BlockGroup without a ReferenceBlock
+    pub keyframe: bool,
+    pub data: &'a [u8],
+}
+
+/// Tracks and blocks of a WebM file, blocks in the order they're stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebmFile<'a> {
+    /// Nanoseconds per timestamp tick
+    pub timestamp_scale: u64,
+    pub tracks: Vec<WebmTrack>,
+    pub blocks: Vec<WebmBlock<'a>>,
+}
+
+impl<'a> WebmFile<'a> {
+    pub fn parse(mut data: &'a [u8]) -> Result<Self, Error> {
+        let mut file = Self {
+            timestamp_scale: 1_000_000,
+            tracks: vec![],
+            blocks: vec![],
+        };
+        let mut cluster_timestamp = 0;
+        while !data.is_empty() {
+            let element = Element::parse(data)?;
+            if WALKED_INTO.contains(&element.id) {
+                //  its children, then whatever follows it
+                data = match element.body {
+                    Some(body) => &data[data.len() - element.rest.len() - body.len()..],
+                    None => element.rest,
+                };
+                continue;
+            }
+            data = element.rest;
+            let body = element.body()?;
+            match element.id {
+                TIMESTAMP_SCALE => file.timestamp_scale = uint(body),
+                TRACK_ENTRY => file.tracks.push(Self::track(body)?),
+                TIMESTAMP => cluster_timestamp = uint(body),
+                SIMPLE_BLOCK => {
+                    let keyframe = Self::flags(body)? & 0x80 != 0;
+                    file.blocks
+                        .push(Self::block(body, cluster_timestamp, keyframe)?);
+                }
+                BLOCK_GROUP => {
+                    let children = children(body)?;
+                    let keyframe = !children.iter().any(|(id, _)| *id == REFERENCE_BLOCK);
+                    if let Some((_, block)) = children.iter().find(|(id, _)| *id == BLOCK) {
+                        file.blocks
+                            .push(Self::block(block, cluster_timestamp, keyframe)?);
+                    }
+                }
+                //  the EBML header, cues, tags and everything else
+                _ => {}
+            }
+        }
+        Ok(file)
+    }
+
+    /// Number of the first VP8 track
+    pub fn vp8_track(&self) -> Option<u64> {
+        self.tracks
+            .iter()
+            .find(|track| track.codec_id == "V_VP8")
+            .map(|track| track.number)
+    }
+
+    fn track(body: &[u8]) -> Result<WebmTrack, Error> {
+        let mut track = WebmTrack {
+            number: 0,
+            codec_id: String::new(),
+        };
+        for (id, body) in children(body)? {
+            match id {
+                TRACK_NUMBER => track.number = uint(body),
+                CODEC_ID => {
+                    //  strings may be padded with zeros
+                    let codec_id = body.split(|b| *b == 0).next().unwrap_or_default();
+                    track.codec_id = String::from_utf8_lossy(codec_id).into_owned();
+                }
+                _ => {}
+            }
+        }
+        Ok(track)
+    }
+
+    /// Flags byte following a block's track number and relative timestamp
+    fn flags(body: &[u8]) -> Result<u8, Error> {
+        let (_, len) = vint(body, false)?;
+        body.get(len + 2).copied().ok_or(Error::TruncatedWebmFile)
+    }
+
+    fn block(
+        body: &'a [u8],
+        cluster_timestamp: u64,
+        keyframe: bool,
+    ) -> Result<WebmBlock<'a>, Error> {
+        let (track, len) = vint(body, false)?;
+        let header = body.get(len..len + 3).ok_or(Error::TruncatedWebmFile)?;
+        let relative = i16::from_be_bytes([header[0], header[1]]);
+        //  video is stored a frame per block, lacing is for audio
+        if header[2] & 0x06 != 0 {
+            return Err(Error::UnsupportedWebmLacing);
+        }
+        Ok(WebmBlock {
+            track,
+            timestamp: cluster_timestamp as i64 + relative as i64,
+            keyframe,
+            data: &body[len + 3..],
+        })
+    }
+}
+
+/// Builds a WebM file with a VP8 track 1 and an Opus track 2, holding
+/// `blocks` of track, relative timestamp, keyframe flag and data in a single
+/// cluster at timestamp 1000. Segment and cluster sizes are left unknown,
+/// keyframes are stored as SimpleBlocks and other frames in BlockGroups.
+#[cfg(test)]
+pub(crate) fn test_webm(blocks: &[(u8, i16, bool, &[u8])]) -> Vec<u8> {
+    fn element(id: u64, body: &[u8]) -> Vec<u8> {
+        let id_len = (64 - id.leading_zeros() as usize).div_ceil(8);
+        let mut element = id.to_be_bytes()[8 - id_len..].to_vec();
+        //  8 byte sizes
+        element.push(0x01);
+        element.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(body);
+        element
+    }
+    let unknown_size = |id: u64| {
+        [
+            &id.to_be_bytes()[4..],
+            &[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff][..],
+        ]
+        .concat()
+    };
+    let track = |number: u8, codec_id: &str| {
+        element(
+            TRACK_ENTRY,
+            &[
+                element(TRACK_NUMBER, &[number]),
+                element(CODEC_ID, codec_id.as_bytes()),
+            ]
+            .concat(),
+        )
+    };
+
+    //  EBML header with just its DocType
+    let mut webm = element(0x1a45dfa3, &element(0x4282, b"webm"));
+    webm.extend(unknown_size(SEGMENT));
+    webm.extend(element(
+        INFO,
+        &element(TIMESTAMP_SCALE, &[0x0f, 0x42, 0x40]),
+    ));
+    webm.extend(element(
+        TRACKS,
+        &[track(1, "V_VP8"), track(2, "A_OPUS")].concat(),
+    ));
+    webm.extend(unknown_size(CLUSTER));
+    webm.extend(element(TIMESTAMP, &[0x03, 0xe8]));
+    for (track, relative, keyframe, data) in blocks {
+        let block =
+            |flags: u8| [&[0x80 | track][..], &relative.to_be_bytes(), &[flags], data].concat();
+        webm.extend(if *keyframe {
+            element(SIMPLE_BLOCK, &block(0x80))
+        } else {
+            element(
+                BLOCK_GROUP,
+                &[element(BLOCK, &block(0)), element(REFERENCE_BLOCK, &[0xdf])].concat(),
+            )
+        });
+    }
+    webm
+}
+
+#[test]
+fn parse_webm_blocks() {
+    let webm = test_webm(&[
+        (1, 0, true, &[1, 2]),
+        (2, 10, true, &[3]),
+        (1, 33, false, &[4]),
+        (1, -5, false, &[]),
+    ]);
+    let file = WebmFile::parse(&webm).unwrap();
+    assert_eq!(file.timestamp_scale, 1_000_000);
+    assert_eq!(file.vp8_track(), Some(1));
+    assert_eq!(
+        file.tracks,
+        [
+            WebmTrack {
+                number: 1,
+                codec_id: "V_VP8".to_string()
+            },
+            WebmTrack {
+                number: 2,
+                codec_id: "A_OPUS".to_string()
+            },
+        ]
+    );
+    let blocks: Vec<_> = file
+        .blocks
+        .iter()
+        .map(|block| (block.track, block.timestamp, block.keyframe, block.data))
+        .collect();
+    assert_eq!(
+        blocks,
+        [
+            (1, 1000, true, &[1, 2][..]),
+            (2, 1010, true, &[3][..]),
+            (1, 1033, false, &[4][..]),
+            (1, 995, false, &[][..]),
+        ]
+    );
+
+    assert!(matches!(
+        WebmFile::parse(&webm[..webm.len() - 1]),
+        Err(Error::TruncatedWebmFile)
+    ));
+}